@@ -1,14 +1,48 @@
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=data.txt");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // A new commit changes HEAD or the branch it points to, only existing files can be watched
+    for git_file in [".git/HEAD", ".git/refs/heads"] {
+        if std::path::Path::new(git_file).exists() {
+            println!("cargo:rerun-if-changed={}", git_file);
+        }
+    }
+
+    // Builds outside of a git checkout, e.g. from a source tarball, have no commit hash
+    let git_hash = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    // Reproducible builds set SOURCE_DATE_EPOCH instead of using the current time
+    let build_timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
 
     let out_dir = std::env::var_os("OUT_DIR").unwrap();
     let path = std::path::Path::new(&out_dir).join("constants.rs");
     std::fs::write(
         &path,
         format!(
-            "pub const DEFAULT_CONF_FILE: &str = {:?};",
-            std::env::var_os("DEFAULT_CONF_FILE").unwrap_or_else(|| "cf-dynamic.conf".into())
+            "pub const DEFAULT_CONF_FILE: &str = {:?};\n\
+             pub const VERSION: &str = {:?};\n\
+             pub const GIT_HASH: &str = {:?};\n\
+             pub const BUILD_TIMESTAMP: u64 = {};\n",
+            std::env::var_os("DEFAULT_CONF_FILE").unwrap_or_else(|| "cf-dynamic.conf".into()),
+            std::env::var("CARGO_PKG_VERSION").unwrap(),
+            git_hash,
+            build_timestamp
         ),
     )
     .expect("TODO: panic message");
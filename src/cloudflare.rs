@@ -1,49 +1,233 @@
-use crate::cloudflare::cache::{Cache, DnsRecord};
-use reqwest::blocking::Client;
+use crate::cloudflare::cache::{Cache, DnsRecord, DEFAULT_CACHE_TTL_SECS};
+use crate::cloudflare::retry::{with_retry, RequestError, RetryConfig};
+use crate::error::{display_chain, CloudflareError};
+use crate::ip_detection::IpVersion;
+use crate::proxy::ProxyConfig;
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::{HeaderMap, AUTHORIZATION, RETRY_AFTER};
+use reqwest::{Method, StatusCode, Url};
+use serde::de::IgnoredAny;
 use serde::{Deserialize, Serialize};
-use serde_json::from_str;
+use serde_json::{from_str, json};
+use std::env;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{debug, debug_span, warn, Level};
 
+#[cfg(feature = "tokio")]
+mod async_api;
 pub mod cache;
+pub mod retry;
 
-static API_URL: &str = "https://api.cloudflare.com/client/v4";
+#[cfg(feature = "tokio")]
+pub(crate) use async_api::AsyncCloudflareApi;
 
-#[derive(Serialize, Deserialize)]
+pub(crate) static API_URL: &str = "https://api.cloudflare.com/client/v4";
+/// Record types updates can manage, CNAME records point to the cname_target of a domain
+pub const SUPPORTED_RECORD_TYPES: [&str; 3] = ["A", "AAAA", "CNAME"];
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct DomainRegistration {
     pub domain: String,
+    #[serde(default, alias = "v4Disabled")]
     pub v4_disabled: bool,
+    #[serde(default, alias = "v4Suffix", skip_serializing_if = "Option::is_none")]
     pub v4_suffix: Option<String>,
+    #[serde(default, alias = "v4Source")]
+    pub v4_source: Option<String>,
+    #[serde(default, alias = "v6Disabled")]
     pub v6_disabled: bool,
+    #[serde(default, alias = "v6Suffix", skip_serializing_if = "Option::is_none")]
     pub v6_suffix: Option<String>,
+    #[serde(default, alias = "v6Source")]
+    pub v6_source: Option<String>,
+    /// Interface whose delegated prefix the v6_suffix is appended to (`pd:<name>`), replaces v6_source
+    #[serde(default, alias = "v6PrefixSource")]
+    pub v6_prefix_source: Option<String>,
+    #[serde(default)]
+    pub ttl: Option<u32>,
+    #[serde(default)]
+    pub proxied: bool,
+    /// IPv4 address the A record had after the last update
+    #[serde(default, alias = "lastV4")]
+    pub last_v4: Option<String>,
+    /// IPv6 address the AAAA record had after the last update
+    #[serde(default, alias = "lastV6")]
+    pub last_v6: Option<String>,
+    /// Unix timestamp of the last update
+    #[serde(default, alias = "lastUpdate")]
+    pub last_update: Option<u64>,
+    /// URL notified about changes of this domain instead of the webhook_url of the config file
+    #[serde(default, alias = "webhookUrl")]
+    pub webhook_url: Option<String>,
+    /// Record types managed for this domain, A and AAAA if empty. The disable flags still apply
+    #[serde(default, alias = "recordTypes")]
+    pub record_types: Vec<String>,
+    /// Name the CNAME record points to, only used if CNAME is one of the record types
+    #[serde(
+        default,
+        alias = "cnameTarget",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cname_target: Option<String>,
+    /// Unicode form of an internationalized domain, `domain` holds its ASCII (punycode) form
+    #[serde(default, alias = "displayName")]
+    pub display_name: Option<String>,
+    /// Comment of the DNS records, the records' comments are left alone if none is set
+    #[serde(default)]
+    pub comment: Option<String>,
+    /// Labels to select the domain with `--tags`, e.g. home or work
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Only update the record of the domain's own zone, not the ones of parent or child zones
+    #[serde(default, alias = "singleZone")]
+    pub single_zone: bool,
 }
 
 impl DomainRegistration {
-    pub fn new(
-        domain: &String,
-        disable_v4: &bool,
-        v4_suffix: &Option<String>,
-        disable_v6: &bool,
-        v6_suffix: &Option<String>,
-    ) -> DomainRegistration {
+    pub fn new(domain: &str) -> DomainRegistration {
         DomainRegistration {
-            domain: domain.clone(),
-            v4_disabled: *disable_v4,
-            v4_suffix: v4_suffix.clone(),
-            v6_disabled: *disable_v6,
-            v6_suffix: v6_suffix.clone(),
+            domain: domain.to_string(),
+            v4_disabled: false,
+            v4_suffix: None,
+            v4_source: None,
+            v6_disabled: false,
+            v6_suffix: None,
+            v6_source: None,
+            v6_prefix_source: None,
+            ttl: None,
+            proxied: false,
+            last_v4: None,
+            last_v6: None,
+            last_update: None,
+            webhook_url: None,
+            record_types: Vec::new(),
+            cname_target: None,
+            display_name: None,
+            comment: None,
+            tags: Vec::new(),
+            single_zone: false,
+        }
+    }
+
+    pub fn ip_versions(&self) -> Vec<IpVersion> {
+        // The ip versions of the managed record types, e.g. V4 for an A record
+        let ip_versions: Vec<IpVersion> = match self.record_types.is_empty() {
+            true => vec![IpVersion::V4, IpVersion::V6],
+            false => self
+                .record_types
+                .iter()
+                .filter_map(|record_type| IpVersion::from_record_type(record_type))
+                .collect(),
+        };
+
+        ip_versions
+            .into_iter()
+            .filter(|ip_version| match ip_version {
+                IpVersion::V4 => !self.v4_disabled,
+                IpVersion::V6 => !self.v6_disabled,
+            })
+            .collect()
+    }
+
+    pub fn cname_target(&self) -> Option<&String> {
+        // The target only counts while CNAME is a managed record type
+        self.cname_target
+            .as_ref()
+            .filter(|_| self.record_types.iter().any(|t| t == "CNAME"))
+    }
+
+    pub fn managed_record_types(&self) -> Vec<&'static str> {
+        // The record types an update writes, in the order they are updated
+        let mut record_types: Vec<&'static str> = self
+            .ip_versions()
+            .iter()
+            .map(|ip_version| ip_version.record_type())
+            .collect();
+        if self.cname_target().is_some() {
+            record_types.push("CNAME");
+        }
+        record_types
+    }
+
+    pub fn default_record_types(&self) -> Vec<String> {
+        // The address record types the disable flags leave enabled
+        self.ip_versions()
+            .iter()
+            .map(|ip_version| ip_version.record_type().to_string())
+            .collect()
+    }
+
+    pub fn enable_record_type(&mut self, record_type: &str) -> bool {
+        // Listed record types are the only managed ones, so an enabled type has to be listed too.
+        // True if the type was added
+        let missing =
+            !self.record_types.is_empty() && !self.record_types.iter().any(|t| t == record_type);
+        if missing {
+            self.record_types.push(record_type.to_string());
         }
+        missing
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        // Registrations without any managed record type are skipped by updates
+        self.managed_record_types().is_empty()
+    }
+}
+
+pub fn parse_record_type(record_type: &str) -> Result<String, CloudflareError> {
+    // Only record types whose content updates know where to take from
+    let record_type = record_type.to_uppercase();
+    match SUPPORTED_RECORD_TYPES.contains(&record_type.as_str()) {
+        true => Ok(record_type),
+        false => Err(CloudflareError::UnsupportedRecordType { record_type }),
     }
 }
 
+/// ASCII (punycode) form of a domain as used by the Cloudflare API, e.g. xn--mnchen-3ya.de
+pub fn to_ascii_domain(domain: &str) -> String {
+    idna::domain_to_ascii(domain).unwrap_or_else(|_| domain.to_string())
+}
+
+/// Number of results requested per page from list endpoints
+const PER_PAGE: u32 = 100;
+
 #[derive(Deserialize)]
 struct CloudflareApiResponse<V> {
     success: bool,
-    errors: Vec<String>,
+    #[serde(default)]
+    errors: Vec<CloudflareApiError>,
     result: Option<V>,
+    result_info: Option<ResultInfo>,
+}
+
+/// Error object of a failed Cloudflare API response
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CloudflareApiError {
+    pub code: u32,
+    pub message: String,
+}
+
+impl fmt::Display for CloudflareApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.code, self.message)
+    }
+}
+
+#[derive(Deserialize)]
+struct ResultInfo {
+    page: u32,
+    per_page: u32,
+    total_count: u32,
+    total_pages: u32,
 }
 
 #[derive(Deserialize)]
 struct CloudflareZone {
     id: String,
+    name: String,
 }
 
 #[derive(Deserialize)]
@@ -53,174 +237,872 @@ struct CloudflareDnsRecord {
     content: String,
     #[serde(rename = "type")]
     record_type: String,
+    ttl: u32,
+    proxied: bool,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+impl CloudflareDnsRecord {
+    fn to_dns_record(&self, zone_id: &str) -> DnsRecord {
+        DnsRecord {
+            id: self.id.clone(),
+            zone_id: zone_id.to_string(),
+            name: self.name.clone(),
+            record_type: self.record_type.clone(),
+            content: self.content.clone(),
+            ttl: self.ttl,
+            proxied: self.proxied,
+            comment: self.comment.clone(),
+        }
+    }
+}
+
+/// DNS record together with the name and type it is registered under
+pub struct ZoneDnsRecord {
+    /// Name of the zone the record belongs to
+    pub zone: String,
+    pub name: String,
+    pub record_type: String,
+    pub record: DnsRecord,
+}
+
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+pub const DEFAULT_READ_TIMEOUT_SECS: u64 = 30;
+/// Longest DNS record comment accepted for free zones
+pub const MAX_COMMENT_LENGTH: usize = 50;
+
+/// Settings used to build the HTTP client talking to the Cloudflare API
+#[derive(Clone)]
+pub struct ClientConfig {
+    pub token: String,
+    pub retry_config: RetryConfig,
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    /// File the zones and DNS records are cached in between invocations
+    pub cache_file: Option<PathBuf>,
+    pub cache_ttl: u64,
+    /// Look up DNS records in the cache, otherwise they are always fetched from Cloudflare
+    pub cache: bool,
+    /// Base URL of the Cloudflare API, e.g. of a mock server in tests
+    pub api_url: Option<String>,
+    /// Accept invalid TLS certificates, e.g. of a local proxy
+    pub insecure: bool,
+    pub proxy: ProxyConfig,
+    /// Address of the network interface all requests are sent from, e.g. on hosts with two uplinks
+    pub local_address: Option<IpAddr>,
+}
+
+/// Applies the timeouts, TLS, interface and proxy settings of a `ClientConfig` to a builder of
+/// the blocking or the async reqwest client, which have the same methods but share no trait
+macro_rules! build_http_client {
+    ($builder:expr, $client_config:expr) => {{
+        let builder = $builder
+            .connect_timeout($client_config.connect_timeout)
+            .timeout($client_config.read_timeout)
+            .danger_accept_invalid_certs($client_config.insecure)
+            .local_address($client_config.local_address);
+        match $client_config.proxy.proxy() {
+            Some(proxy) => builder.proxy(proxy),
+            None => builder.no_proxy(),
+        }
+        .build()
+        .map_err(CloudflareError::Client)
+    }};
+}
+
+impl ClientConfig {
+    pub fn new(token: String) -> ClientConfig {
+        ClientConfig {
+            token,
+            retry_config: RetryConfig::default(),
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            read_timeout: Duration::from_secs(DEFAULT_READ_TIMEOUT_SECS),
+            cache_file: None,
+            cache_ttl: DEFAULT_CACHE_TTL_SECS,
+            cache: true,
+            api_url: None,
+            insecure: false,
+            proxy: ProxyConfig::default(),
+            local_address: None,
+        }
+    }
+
+    fn api_url(&self) -> String {
+        // The config wins over the environment, both over the real Cloudflare API
+        self.api_url
+            .clone()
+            .or_else(|| env::var("CLOUDFLARE_API_URL").ok())
+            .unwrap_or_else(|| API_URL.to_string())
+            .trim_end_matches('/')
+            .to_string()
+    }
+
+    pub(crate) fn http_client(&self) -> Result<Client, CloudflareError> {
+        build_http_client!(Client::builder(), self)
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn async_http_client(&self) -> Result<reqwest::Client, CloudflareError> {
+        build_http_client!(reqwest::Client::builder(), self)
+    }
+
+    fn load_cache(&self) -> Cache {
+        // Start with an empty cache if there is no usable cache file or the cache is disabled
+        let mut cache = match self.cache_file {
+            Some(ref path) if self.cache && path.exists() => {
+                Cache::load(path).unwrap_or_else(|e| {
+                    warn!("{}, ignoring it", display_chain(&e));
+                    Cache::new()
+                })
+            }
+            _ => Cache::new(),
+        };
+        cache.set_ttl(self.cache_ttl);
+        if !self.cache {
+            cache.disable();
+        }
+        cache
+    }
 }
 
 pub(crate) struct CloudflareApi {
+    api_url: String,
     token: String,
     client: Client,
     cache: Cache,
+    cache_file: Option<PathBuf>,
+    retry_config: RetryConfig,
+    insecure: bool,
 }
 
 impl CloudflareApi {
-    pub fn new(token: String) -> CloudflareApi {
-        CloudflareApi {
-            token,
-            client: Client::new(),
-            cache: Cache::new(),
-        }
+    pub fn new(client_config: ClientConfig) -> Result<CloudflareApi, CloudflareError> {
+        Ok(CloudflareApi {
+            api_url: client_config.api_url(),
+            cache: client_config.load_cache(),
+            client: client_config.http_client()?,
+            token: client_config.token,
+            cache_file: client_config.cache_file,
+            retry_config: client_config.retry_config,
+            insecure: client_config.insecure,
+        })
     }
 
-    pub fn fetch_cloudflare_zones(self: &mut Self) -> Result<Vec<String>, String> {
+    pub fn fetch_cloudflare_zones(&mut self) -> Result<Vec<String>, CloudflareError> {
         // Fetch all zones from Cloudflare API or return cached response
         if !self.cache.zones_cached() {
-            let api_response: Result<Vec<CloudflareZone>, String> =
-                self.fetch_cloudflare_api("zones".to_string());
-            let zones = match api_response {
-                Ok(zones) => zones,
-                Err(e) => return Err(e),
-            };
+            let zones: Vec<CloudflareZone> = self.fetch_all_cloudflare_pages("zones")?;
 
             zones
                 .iter()
-                .for_each(|zone| self.cache.add_zone(zone.id.clone()));
+                .for_each(|zone| self.cache.add_zone(zone.name.clone(), zone.id.clone()));
+            self.save_cache();
         }
         Ok(self.cache.get_zones())
     }
 
+    pub fn fetch_cloudflare_zone_names(
+        &mut self,
+    ) -> Result<Vec<(String, String)>, CloudflareError> {
+        // Names and ids of all zones accessible with the token
+        self.fetch_cloudflare_zones()?;
+        Ok(self.cache.get_zone_names())
+    }
+
     pub fn fetch_cloudflare_dns_record<'c>(
-        self: &'c mut Self,
+        &'c mut self,
         domain: &str,
         record_type: &str,
-    ) -> Result<&'c DnsRecord, String> {
-        // Fetch all dns records for a given zone from Cloudflare API or return cached response
-        if self.cache.get_dns_record(domain, record_type).is_none() {
-            let zones = match self.fetch_cloudflare_zones() {
-                Ok(zones) => zones,
-                Err(e) => return Err(e),
+    ) -> Result<&'c DnsRecord, CloudflareError> {
+        // Fetch the dns records of the domain's zone from Cloudflare API or return cached response
+        let domain = to_ascii_domain(domain);
+        let domain = domain.as_str();
+        if self.cache.is_expired(domain, record_type) {
+            let zone_id = self.zone_id_for_domain(domain)?;
+            match SUPPORTED_RECORD_TYPES.contains(&record_type) {
+                true => self.list_dns_records(&zone_id)?,
+                false => self.list_dns_records_of_types(&zone_id, &[record_type])?,
             };
+        }
+        self.cache
+            .get_dns_record(domain, record_type)
+            .ok_or_else(|| record_not_found(domain, record_type))
+    }
 
-            for zone in zones.iter() {
-                let dns_records: Vec<CloudflareDnsRecord> = match self
-                    .fetch_cloudflare_api(format!("zones/{}/dns_records?type=A&type=AAAA", zone))
-                {
-                    Ok(dns_records) => dns_records,
-                    Err(e) => return Err(e),
-                };
-
-                for record in dns_records.iter() {
-                    self.cache.set_dns_record(
-                        record.name.as_str(),
-                        record.record_type.as_str(),
-                        record.id.as_str(),
-                        zone,
-                        record.content.as_str(),
-                    );
-                }
+    pub fn fetch_all_dns_records_for_domain(
+        &mut self,
+        domain: &str,
+        record_type: &str,
+    ) -> Result<Vec<DnsRecord>, CloudflareError> {
+        // Records of the domain in every zone containing it, e.g. in example.com and foo.example.com
+        let domain = to_ascii_domain(domain);
+        let domain = domain.as_str();
+        self.fetch_cloudflare_zones()?;
+        let zone_ids = self.cache.find_zone_ids(domain);
+        if zone_ids.len() <= 1 {
+            return Ok(self
+                .fetch_cloudflare_dns_record(domain, record_type)
+                .ok()
+                .into_iter()
+                .cloned()
+                .collect());
+        }
+
+        // The most specific zone is loaded last, so its record stays in the cache
+        let mut records = Vec::new();
+        for zone_id in zone_ids.iter().rev() {
+            records.extend(
+                self.list_dns_records(zone_id)?
+                    .into_iter()
+                    .filter(|record| record.name == domain && record.record_type == record_type),
+            );
+        }
+        records.reverse();
+        Ok(records)
+    }
+
+    pub fn fetch_cloudflare_zone_dns_records(
+        &mut self,
+        zone: &Option<String>,
+        record_types: &[&str],
+    ) -> Result<Vec<ZoneDnsRecord>, CloudflareError> {
+        // Records of the given types, or of every type if none are given, of one zone or of all zones
+        let mut zones = self.fetch_cloudflare_zone_names()?;
+        if let Some(zone) = zone {
+            zones.retain(|(name, _)| name == zone);
+            if zones.is_empty() {
+                return Err(CloudflareError::ZoneNotFound {
+                    domain: zone.clone(),
+                });
             }
         }
+
+        let mut zone_dns_records = Vec::new();
+        for (zone_name, zone_id) in zones.iter() {
+            for record in self
+                .list_dns_records_of_types(zone_id, record_types)?
+                .into_iter()
+            {
+                zone_dns_records.push(ZoneDnsRecord {
+                    zone: zone_name.clone(),
+                    name: record.name.clone(),
+                    record_type: record.record_type.clone(),
+                    record,
+                });
+            }
+        }
+        Ok(zone_dns_records)
+    }
+
+    pub fn list_dns_records(&mut self, zone_id: &str) -> Result<Vec<DnsRecord>, CloudflareError> {
+        self.list_dns_records_of_types(zone_id, &SUPPORTED_RECORD_TYPES)
+    }
+
+    pub fn list_dns_records_of_types(
+        &mut self,
+        zone_id: &str,
+        record_types: &[&str],
+    ) -> Result<Vec<DnsRecord>, CloudflareError> {
+        // Fetch the records of the given types, always from Cloudflare, and refresh the cache with them
+        let dns_records: Vec<CloudflareDnsRecord> =
+            self.fetch_all_cloudflare_pages(dns_records_path(zone_id, record_types).as_str())?;
+        let dns_records: Vec<DnsRecord> = dns_records
+            .iter()
+            .map(|record| record.to_dns_record(zone_id))
+            .collect();
+
+        self.cache.set_dns_records(&dns_records);
+        self.save_cache();
+        Ok(dns_records)
+    }
+
+    pub fn zone_name_for_domain(&mut self, domain: &str) -> Result<String, CloudflareError> {
+        // Name of the most specific zone containing the domain
+        let zone_id = self.zone_id_for_domain(&to_ascii_domain(domain))?;
+        self.cache
+            .get_zone_name(&zone_id)
+            .cloned()
+            .ok_or_else(|| zone_not_found(domain))
+    }
+
+    pub fn zone_id_for_domain(&mut self, domain: &str) -> Result<String, CloudflareError> {
+        self.fetch_cloudflare_zones()?;
+        self.cache
+            .find_zone_id(domain)
+            .cloned()
+            .ok_or_else(|| zone_not_found(domain))
+    }
+
+    pub fn create_cloudflare_dns_record<'c>(
+        &'c mut self,
+        domain: &str,
+        record_type: &str,
+        content: &str,
+        ttl: &Option<u32>,
+        proxied: &bool,
+        comment: &Option<String>,
+    ) -> Result<&'c DnsRecord, CloudflareError> {
+        // Create a new dns record in the zone of the domain, using automatic TTL if none is given
+        let zone_id = self.zone_id_for_domain(domain)?;
+
+        let body = json!({
+            "type": record_type,
+            "name": domain,
+            "content": content,
+            "ttl": ttl.unwrap_or(1),
+            "proxied": proxied,
+            "comment": comment,
+        })
+        .to_string();
+
+        let record: CloudflareDnsRecord = self.cloudflare_api_request(
+            Method::POST,
+            format!("zones/{}/dns_records", zone_id),
+            Some(body),
+        )?;
+
+        self.cache
+            .set_dns_record(domain, record_type, record.to_dns_record(&zone_id));
+        self.save_cache();
         self.cache
             .get_dns_record(domain, record_type)
-            .ok_or("Unable to find record".to_string())
+            .ok_or_else(|| record_not_found(domain, record_type))
     }
 
     pub fn update_cloudflare_dns_record<'c>(
-        self: &'c mut Self,
+        &'c mut self,
         domain: &str,
         record_type: &str,
         content: &str,
-    ) -> Result<&'c DnsRecord, String> {
-        // Update a dns record for a given zone from Cloudflare API
-        let body = format!("{{\"content\": \"{}\"}}", content);
-
-        let record = match self.fetch_cloudflare_dns_record(domain, record_type) {
-            Ok(record) => record.clone(),
-            Err(cause) => {
-                return Err(format!(
-                    "Unable to find record for {} {} (Cause: {})",
-                    domain, record_type, cause
-                ));
-            }
-        };
+        ttl: &Option<u32>,
+        proxied: &bool,
+        comment: &Option<String>,
+    ) -> Result<&'c DnsRecord, CloudflareError> {
+        // Update the cached dns record of the domain's zone from Cloudflare API
+        let record = self
+            .fetch_cloudflare_dns_record(domain, record_type)?
+            .clone();
+        let updated_record = self.update_dns_record(&record, content, ttl, proxied, comment)?;
 
-        let api_response: Result<CloudflareDnsRecord, String> = self.put_cloudflare_api(
-            format!("zones/{}/dns_records/{}", record.zone_id, record.id),
-            body,
-        );
+        self.cache
+            .set_dns_record(domain, record_type, updated_record);
+        self.save_cache();
+        self.cache
+            .get_dns_record(domain, record_type)
+            .ok_or_else(|| record_not_found(domain, record_type))
+    }
 
-        let new_ip = match api_response {
-            Ok(api_response) => api_response.content,
-            Err(e) => return Err(e),
-        };
+    pub fn update_dns_record(
+        &mut self,
+        record: &DnsRecord,
+        content: &str,
+        ttl: &Option<u32>,
+        proxied: &bool,
+        comment: &Option<String>,
+    ) -> Result<DnsRecord, CloudflareError> {
+        // Update the given dns record, without touching the cache as it might be of another zone
+        let updated_record: CloudflareDnsRecord = self.cloudflare_api_request(
+            Method::PUT,
+            format!("zones/{}/dns_records/{}", record.zone_id, record.id),
+            Some(update_body(content, ttl, proxied, comment)),
+        )?;
+        check_updated_record(&updated_record, content)?;
+        Ok(updated_record.to_dns_record(&record.zone_id))
+    }
 
-        if new_ip != content {
-            return Err(format!(
-                "Unable to update dns record in Cloudflare API: Record not updated"
-            ));
+    fn save_cache(&self) {
+        if let Some(ref path) = self.cache_file {
+            if let Err(e) = self.cache.save(path) {
+                warn!("{}", display_chain(&e));
+            }
         }
+    }
+
+    pub fn delete_cloudflare_dns_record(
+        &mut self,
+        domain: &str,
+        record_type: &str,
+    ) -> Result<(), CloudflareError> {
+        // Delete a dns record from Cloudflare API and the cache
+        let record = self
+            .fetch_cloudflare_dns_record(domain, record_type)?
+            .clone();
+        self.delete_dns_record(&record)
+    }
+
+    pub fn delete_dns_record(&mut self, record: &DnsRecord) -> Result<(), CloudflareError> {
+        // The cached record of the name and type might be the deleted one, so it is dropped
+        let _: IgnoredAny = self.cloudflare_api_request(
+            Method::DELETE,
+            format!("zones/{}/dns_records/{}", record.zone_id, record.id),
+            None,
+        )?;
 
-        self.cache.set_dns_record(
-            domain,
-            record_type,
-            record.id.as_str(),
-            record.zone_id.as_str(),
-            new_ip.as_str(),
-        );
         self.cache
-            .get_dns_record(domain, record_type)
-            .ok_or("Unable to fetch updated IP from Cloudflare API".to_string())
+            .remove_dns_record(&record.name, &record.record_type);
+        self.save_cache();
+        Ok(())
+    }
+
+    pub fn cache_dns_record(&mut self, record: DnsRecord) {
+        // Remember a record changed with `update_dns_record` as the one of its name and type
+        let (name, record_type) = (record.name.clone(), record.record_type.clone());
+        self.cache.set_dns_record(&name, &record_type, record);
+        self.save_cache();
     }
 
-    fn fetch_cloudflare_api<V: for<'a> Deserialize<'a>>(
-        self: &Self,
+    fn fetch_all_cloudflare_pages<V: for<'a> Deserialize<'a>>(
+        &self,
+        path: &str,
+    ) -> Result<Vec<V>, CloudflareError> {
+        // Request the pages of a list endpoint one after another and collect all results
+        collect_pages(|page| {
+            self.cloudflare_api_page_request(Method::GET, paginated_path(path, page), None)
+        })
+    }
+
+    fn cloudflare_api_request<V: for<'a> Deserialize<'a>>(
+        &self,
+        method: Method,
         path: String,
-    ) -> Result<V, String> {
-        // Make Request to Cloudflare API with the given path and return the result as json
-        let url = format!("{}/{}", API_URL, path);
-        let authorization_header = format!("Bearer {}", self.token);
+        body: Option<String>,
+    ) -> Result<V, CloudflareError> {
+        self.cloudflare_api_page_request(method, path, body)
+            .map(|(result, _)| result)
+    }
 
-        self.client
-            .get(url)
-            .header("Authorization", authorization_header)
-            .send()
-            .and_then(|res| res.text())
-            .map_err(|e| e.to_string())
-            .and_then(|body| from_str(&body).map_err(|e| e.to_string()))
-            .and_then(
-                |api_response: CloudflareApiResponse<V>| match api_response.success {
-                    true => Ok(api_response.result.unwrap()),
-                    false => Err(format!(
-                        "Error in get request to Cloudflare API: {:?}",
-                        api_response.errors
-                    )),
-                },
-            )
-    }
-
-    fn put_cloudflare_api<V: for<'a> Deserialize<'a>>(
-        self: &Self,
+    fn cloudflare_api_page_request<V: for<'a> Deserialize<'a>>(
+        &self,
+        method: Method,
         path: String,
-        body: String,
-    ) -> Result<V, String> {
-        // Make Request to Cloudflare API with the given path and return the result as json
-        let url = format!("{}/{}", API_URL, path);
+        body: Option<String>,
+    ) -> Result<(V, Option<ResultInfo>), CloudflareError> {
+        // Make Request to Cloudflare API with the given method and path and return the result as json
+        let url = format!("{}/{}", self.api_url, path);
         let authorization_header = format!("Bearer {}", self.token);
+        let method_name = method.as_str().to_lowercase();
+        if self.insecure {
+            warn!("Not verifying the TLS certificate of {}", url);
+        }
+
+        with_retry(&self.retry_config, || {
+            let mut request = self
+                .client
+                .request(method.clone(), url.as_str())
+                .header("Authorization", authorization_header.as_str());
+            if let Some(ref body) = body {
+                request = request.body(body.clone());
+            }
+            send_cloudflare_request(request, &method_name)
+        })
+    }
+}
+
+fn send_cloudflare_request<V: for<'a> Deserialize<'a>>(
+    request: RequestBuilder,
+    method: &str,
+) -> Result<(V, Option<ResultInfo>), RequestError> {
+    let _span = debug_span!("cloudflare_request", method = method.to_uppercase()).entered();
+    if tracing::enabled!(Level::DEBUG) {
+        if let Some(request) = request.try_clone().and_then(|r| r.build().ok()) {
+            log_request(
+                request.method(),
+                request.url(),
+                request.headers(),
+                request.body().and_then(|b| b.as_bytes()),
+            );
+        }
+    }
+
+    let response = request.send().map_err(request_error)?;
+    let status = response.status();
+    debug!("{} {} -> {}", method.to_uppercase(), response.url(), status);
+    let retry_after = retry_after(response.headers());
+    let body = response.text().map_err(request_error)?;
+    debug!("Response body: {}", body);
+    parse_cloudflare_page(status, retry_after, body.as_str(), method)
+}
+
+pub(crate) fn log_request(method: &Method, url: &Url, headers: &HeaderMap, body: Option<&[u8]>) {
+    // Log everything that is sent to the API, except the token
+    let headers: Vec<String> = headers
+        .iter()
+        .map(|(name, value)| {
+            let value = value.to_str().unwrap_or_default();
+            match (name == AUTHORIZATION, value.strip_prefix("Bearer ")) {
+                (true, Some(token)) => format!("{}: Bearer {}", name, redact_token(token)),
+                (true, None) => format!("{}: {}", name, redact_token(value)),
+                (false, _) => format!("{}: {}", name, value),
+            }
+        })
+        .collect();
+    debug!("{} {} [{}]", method, url, headers.join(", "));
+    if let Some(body) = body {
+        debug!("Request body: {}", String::from_utf8_lossy(body));
+    }
+}
+
+pub(crate) fn redact_token(token: &str) -> String {
+    // Only the last 4 characters are shown, enough to tell tokens apart
+    let chars: Vec<char> = token.chars().collect();
+    match chars.len() > 4 {
+        true => format!(
+            "****{}",
+            chars[chars.len() - 4..].iter().collect::<String>()
+        ),
+        false => "****".to_string(),
+    }
+}
+
+fn dns_records_path(zone_id: &str, record_types: &[&str]) -> String {
+    // List the records of all given types at once, or of every type without any
+    let types: Vec<String> = record_types
+        .iter()
+        .map(|record_type| format!("type={}", record_type))
+        .collect();
+    match types.is_empty() {
+        true => format!("zones/{}/dns_records", zone_id),
+        false => format!("zones/{}/dns_records?{}", zone_id, types.join("&")),
+    }
+}
+
+fn paginated_path(path: &str, page: u32) -> String {
+    let separator = match path.contains('?') {
+        true => '&',
+        false => '?',
+    };
+    format!("{}{}page={}&per_page={}", path, separator, page, PER_PAGE)
+}
+
+fn next_page(result_info: &Option<ResultInfo>) -> Option<u32> {
+    // Responses without result_info are not paginated
+    result_info
+        .as_ref()
+        .filter(|info| info.page < info.total_pages)
+        .map(|info| info.page + 1)
+}
+
+fn collect_pages<V, F>(mut fetch_page: F) -> Result<Vec<V>, CloudflareError>
+where
+    F: FnMut(u32) -> Result<(Vec<V>, Option<ResultInfo>), CloudflareError>,
+{
+    let mut results = Vec::new();
+    let mut page = 1;
+    loop {
+        let (mut page_results, result_info) = fetch_page(page)?;
+        if let Some(ref info) = result_info {
+            debug!(
+                "Fetched page {} of {} ({} per page, {} results in total)",
+                info.page, info.total_pages, info.per_page, info.total_count
+            );
+        }
+        results.append(&mut page_results);
+        match next_page(&result_info) {
+            Some(next) => page = next,
+            None => return Ok(results),
+        }
+    }
+}
+
+fn update_body(
+    content: &str,
+    ttl: &Option<u32>,
+    proxied: &bool,
+    comment: &Option<String>,
+) -> String {
+    // The TTL and comment of the record are kept unless new ones are given
+    let mut body = json!({ "content": content, "proxied": proxied });
+    if let Some(ttl) = ttl {
+        body["ttl"] = json!(ttl);
+    }
+    if let Some(comment) = comment {
+        body["comment"] = json!(comment);
+    }
+    body.to_string()
+}
+
+fn check_updated_record(
+    updated_record: &CloudflareDnsRecord,
+    content: &str,
+) -> Result<(), CloudflareError> {
+    match updated_record.content == content {
+        true => Ok(()),
+        false => Err(CloudflareError::RecordNotUpdated {
+            domain: updated_record.name.clone(),
+            record_type: updated_record.record_type.clone(),
+        }),
+    }
+}
+
+fn request_error(e: reqwest::Error) -> RequestError {
+    // Errors while building the request will not go away by sending it again
+    match e.is_builder() {
+        true => RequestError::Permanent(CloudflareError::HttpError(e)),
+        false => RequestError::Transient {
+            error: CloudflareError::HttpError(e),
+            retry_after: None,
+        },
+    }
+}
+
+fn zone_not_found(domain: &str) -> CloudflareError {
+    CloudflareError::ZoneNotFound {
+        domain: domain.to_string(),
+    }
+}
+
+fn record_not_found(domain: &str, record_type: &str) -> CloudflareError {
+    CloudflareError::RecordNotFound {
+        domain: domain.to_string(),
+        record_type: record_type.to_string(),
+    }
+}
+
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+fn parse_cloudflare_page<V: for<'a> Deserialize<'a>>(
+    status: StatusCode,
+    retry_after: Option<Duration>,
+    body: &str,
+    method: &str,
+) -> Result<(V, Option<ResultInfo>), RequestError> {
+    // Rate limits and server errors are worth retrying, everything else is reported right away
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(RequestError::Transient {
+            error: CloudflareError::RateLimited {
+                retry_after: retry_after.map(|d| d.as_secs()).unwrap_or(0),
+            },
+            retry_after,
+        });
+    }
+    if status.is_server_error() {
+        return Err(RequestError::Transient {
+            error: CloudflareError::StatusError {
+                method: method.to_string(),
+                status,
+            },
+            retry_after,
+        });
+    }
+
+    let api_response: CloudflareApiResponse<V> =
+        from_str(body).map_err(|e| RequestError::Permanent(CloudflareError::ParseError(e)))?;
+    match (api_response.success, api_response.result) {
+        (true, Some(result)) => Ok((result, api_response.result_info)),
+        (true, None) => Err(RequestError::Permanent(CloudflareError::MissingResult {
+            method: method.to_string(),
+        })),
+        (false, _) => Err(RequestError::Permanent(CloudflareError::ApiError {
+            errors: api_response.errors,
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_unicode_domain_to_ascii() {
+        assert_eq!(to_ascii_domain("münchen.de"), "xn--mnchen-3ya.de");
+        assert_eq!(to_ascii_domain("Example.COM"), "example.com");
+    }
+
+    #[test]
+    fn reads_domains_of_old_versions() {
+        let domain: DomainRegistration = serde_json::from_str(
+            r#"{"domain": "example.com", "v4_disabled": false, "v6_disabled": false}"#,
+        )
+        .unwrap();
+        let expected = serde_json::to_value(DomainRegistration::new("example.com")).unwrap();
+        assert_eq!(serde_json::to_value(&domain).unwrap(), expected);
+        assert!(domain.ip_versions() == vec![IpVersion::V4, IpVersion::V6]);
+
+        let domain: DomainRegistration =
+            serde_json::from_str(r#"{"domain": "example.com"}"#).unwrap();
+        assert!(!domain.v4_disabled && !domain.v6_disabled && !domain.proxied);
+        assert!(domain.ttl.is_none() && domain.tags.is_empty() && domain.last_v4.is_none());
+    }
+
+    #[test]
+    fn reads_camel_case_and_writes_snake_case() {
+        let domain: DomainRegistration = serde_json::from_str(
+            r#"{"domain": "example.com", "v4Disabled": true, "v6Suffix": "::1", "lastUpdate": 5}"#,
+        )
+        .unwrap();
+        assert!(domain.v4_disabled);
+        assert_eq!(domain.v6_suffix.as_deref(), Some("::1"));
+        assert_eq!(domain.last_update, Some(5));
+
+        let json = serde_json::to_value(&domain).unwrap();
+        assert_eq!(json["v4_disabled"], true);
+        assert_eq!(json["v6_suffix"], "::1");
+        assert!(json.get("v4_suffix").is_none());
+        assert!(json.get("v4Disabled").is_none());
+    }
+
+    #[test]
+    fn detects_registrations_without_record_types() {
+        let domain = DomainRegistration::new("example.com");
+        assert!(!domain.is_disabled());
+        assert!(DomainRegistration {
+            v4_disabled: true,
+            v6_disabled: true,
+            ..domain.clone()
+        }
+        .is_disabled());
+        assert!(DomainRegistration {
+            v6_disabled: true,
+            record_types: vec!["AAAA".to_string()],
+            ..domain
+        }
+        .is_disabled());
+    }
+
+    #[test]
+    fn manages_cname_record_only_with_target() {
+        let domain = DomainRegistration {
+            record_types: vec!["CNAME".to_string()],
+            ..DomainRegistration::new("cdn.example.com")
+        };
+        assert!(domain.is_disabled());
+
+        let domain = DomainRegistration {
+            cname_target: Some("origin.example.net".to_string()),
+            ..domain
+        };
+        assert_eq!(domain.managed_record_types(), vec!["CNAME"]);
+        assert_eq!(parse_record_type("cname").unwrap(), "CNAME");
+        assert!(parse_record_type("MX").is_err());
+    }
+
+    #[test]
+    fn derives_default_record_types_from_disable_flags() {
+        let domain = DomainRegistration {
+            v4_disabled: true,
+            ..DomainRegistration::new("example.com")
+        };
+        assert_eq!(domain.default_record_types(), vec!["AAAA"]);
+
+        let mut domain = DomainRegistration {
+            record_types: domain.default_record_types(),
+            ..domain
+        };
+        domain.v4_disabled = false;
+        assert!(domain.enable_record_type("A"));
+        assert!(!domain.enable_record_type("A"));
+        assert_eq!(domain.managed_record_types(), vec!["AAAA", "A"]);
+    }
+
+    #[test]
+    fn redacts_all_but_last_four_characters() {
+        assert_eq!(redact_token("abcdefgh1234"), "****1234");
+    }
 
-        self.client
-            .put(url)
-            .header("Authorization", authorization_header)
-            .body(body)
-            .send()
-            .and_then(|res| res.text())
-            .map_err(|e| e.to_string())
-            .and_then(|body| from_str(body.as_str()).map_err(|e| e.to_string()))
-            .and_then(
-                |api_response: CloudflareApiResponse<V>| match api_response.success {
-                    true => Ok(api_response.result.unwrap()),
-                    false => Err(format!(
-                        "Error in put request to Cloudflare API: {:?}",
-                        api_response.errors
-                    )),
-                },
-            )
+    #[test]
+    fn redacts_short_tokens_completely() {
+        assert_eq!(redact_token("1234"), "****");
+        assert_eq!(redact_token(""), "****");
+    }
+
+    #[test]
+    fn redacts_multibyte_tokens_by_character() {
+        assert_eq!(redact_token("tökenäöü"), "****näöü");
+    }
+
+    static ZONES_PAGE_1: &str = include_str!("../tests/fixtures/zones_page_1.json");
+    static ZONES_PAGE_2: &str = include_str!("../tests/fixtures/zones_page_2.json");
+
+    fn parse_zones_page(body: &str) -> (Vec<CloudflareZone>, Option<ResultInfo>) {
+        match parse_cloudflare_page(StatusCode::OK, None, body, "get") {
+            Ok(page) => page,
+            Err(RequestError::Transient { error, .. }) | Err(RequestError::Permanent(error)) => {
+                panic!("{}", error)
+            }
+        }
+    }
+
+    #[test]
+    fn collects_zones_from_all_pages() {
+        let mut requested_pages = Vec::new();
+        let zones = collect_pages(|page| {
+            requested_pages.push(page);
+            match page {
+                1 => Ok(parse_zones_page(ZONES_PAGE_1)),
+                2 => Ok(parse_zones_page(ZONES_PAGE_2)),
+                _ => panic!("Unexpected page {}", page),
+            }
+        })
+        .unwrap();
+
+        assert_eq!(requested_pages, vec![1, 2]);
+        let names: Vec<&str> = zones.iter().map(|zone| zone.name.as_str()).collect();
+        assert_eq!(names, vec!["example.com", "example.org", "example.net"]);
+    }
+
+    #[test]
+    fn stops_without_result_info() {
+        let (_, result_info) = parse_zones_page(r#"{"success": true, "errors": [], "result": []}"#);
+        assert_eq!(next_page(&result_info), None);
+    }
+
+    #[test]
+    fn reports_successful_response_without_result() {
+        let body = r#"{"success": true, "errors": [], "result": null}"#;
+        match parse_cloudflare_page::<IgnoredAny>(StatusCode::OK, None, body, "delete") {
+            Err(RequestError::Permanent(CloudflareError::MissingResult { method })) => {
+                assert_eq!(method, "delete")
+            }
+            _ => panic!("Expected a missing result error"),
+        }
+    }
+
+    #[test]
+    fn parses_api_errors() {
+        let body = include_str!("../tests/fixtures/api_error.json");
+        let error =
+            match parse_cloudflare_page::<IgnoredAny>(StatusCode::BAD_REQUEST, None, body, "get") {
+                Err(RequestError::Permanent(error)) => error,
+                _ => panic!("Expected a permanent error"),
+            };
+        match error {
+            CloudflareError::ApiError { ref errors } => assert_eq!(
+                errors[0],
+                CloudflareApiError {
+                    code: 1003,
+                    message: "Invalid or missing zone id.".to_string()
+                }
+            ),
+            ref e => panic!("Unexpected error {}", e),
+        }
+        assert_eq!(
+            error.to_string(),
+            "Cloudflare API returned errors: 1003:Invalid or missing zone id., 9109:Invalid access token"
+        );
+    }
+
+    #[test]
+    fn appends_page_to_path() {
+        assert_eq!(paginated_path("zones", 2), "zones?page=2&per_page=100");
+        assert_eq!(
+            paginated_path("zones/1/dns_records?type=A", 1),
+            "zones/1/dns_records?type=A&page=1&per_page=100"
+        );
+    }
+
+    #[test]
+    fn filters_dns_records_by_type() {
+        assert_eq!(
+            dns_records_path("1", &SUPPORTED_RECORD_TYPES),
+            "zones/1/dns_records?type=A&type=AAAA&type=CNAME"
+        );
+        assert_eq!(dns_records_path("1", &[]), "zones/1/dns_records");
     }
 }
@@ -0,0 +1,346 @@
+use crate::cloudflare::cache::{Cache, DnsRecord};
+use crate::cloudflare::retry::{with_retry_async, RetryConfig};
+use crate::cloudflare::{
+    check_updated_record, dns_records_path, log_request, next_page, paginated_path,
+    parse_cloudflare_page, record_not_found, request_error, retry_after, to_ascii_domain,
+    update_body, zone_not_found, ClientConfig, CloudflareDnsRecord, CloudflareZone, ResultInfo,
+    SUPPORTED_RECORD_TYPES,
+};
+use crate::error::{display_chain, CloudflareError};
+use reqwest::{Client, Method};
+use serde::Deserialize;
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
+use tracing::{debug, debug_span, warn, Instrument, Level};
+
+/// Asynchronous `CloudflareApi` whose clones share one cache, so it can be used from many tasks
+#[derive(Clone)]
+pub(crate) struct AsyncCloudflareApi {
+    api_url: String,
+    token: String,
+    client: Client,
+    cache: Arc<RwLock<Cache>>,
+    fetch_lock: Arc<Mutex<()>>,
+    cache_file: Option<PathBuf>,
+    retry_config: RetryConfig,
+    insecure: bool,
+}
+
+impl AsyncCloudflareApi {
+    pub fn new(client_config: ClientConfig) -> Result<AsyncCloudflareApi, CloudflareError> {
+        Ok(AsyncCloudflareApi {
+            api_url: client_config.api_url(),
+            cache: Arc::new(RwLock::new(client_config.load_cache())),
+            client: client_config.async_http_client()?,
+            token: client_config.token,
+            fetch_lock: Arc::new(Mutex::new(())),
+            cache_file: client_config.cache_file,
+            retry_config: client_config.retry_config,
+            insecure: client_config.insecure,
+        })
+    }
+
+    pub async fn fetch_cloudflare_zones(&self) -> Result<Vec<String>, CloudflareError> {
+        let _guard = self.fetch_lock.lock().await;
+        self.load_cloudflare_zones().await
+    }
+
+    pub async fn fetch_cloudflare_dns_record(
+        &self,
+        domain: &str,
+        record_type: &str,
+    ) -> Result<DnsRecord, CloudflareError> {
+        let domain = to_ascii_domain(domain);
+        let domain = domain.as_str();
+        if let Some(record) = self.unexpired_dns_record(domain, record_type) {
+            return Ok(record);
+        }
+
+        let _guard = self.fetch_lock.lock().await;
+        // Another task might have filled the cache while we were waiting for the lock
+        if let Some(record) = self.unexpired_dns_record(domain, record_type) {
+            return Ok(record);
+        }
+
+        // Only the dns records of the domain's zone are needed
+        self.load_cloudflare_zones().await?;
+        let zone_id = self.cached_zone_id(domain)?;
+        self.load_zone_dns_records(&zone_id).await?;
+        self.save_cache();
+
+        self.cached_dns_record(domain, record_type)
+            .ok_or_else(|| record_not_found(domain, record_type))
+    }
+
+    pub async fn fetch_all_dns_records_for_domain(
+        &self,
+        domain: &str,
+        record_type: &str,
+    ) -> Result<Vec<DnsRecord>, CloudflareError> {
+        // Records of the domain in every zone containing it, e.g. in example.com and foo.example.com
+        let domain = to_ascii_domain(domain);
+        let domain = domain.as_str();
+        self.fetch_cloudflare_zones().await?;
+        let zone_ids = self.cache.read().unwrap().find_zone_ids(domain);
+        if zone_ids.len() <= 1 {
+            return Ok(self
+                .fetch_cloudflare_dns_record(domain, record_type)
+                .await
+                .ok()
+                .into_iter()
+                .collect());
+        }
+
+        // The most specific zone is loaded last, so its record stays in the cache
+        let _guard = self.fetch_lock.lock().await;
+        let mut records = Vec::new();
+        for zone_id in zone_ids.iter().rev() {
+            records.extend(
+                self.load_zone_dns_records(zone_id)
+                    .await?
+                    .into_iter()
+                    .filter(|record| record.name == domain && record.record_type == record_type),
+            );
+        }
+        self.save_cache();
+        records.reverse();
+        Ok(records)
+    }
+
+    pub async fn zone_id_for_domain(&self, domain: &str) -> Result<String, CloudflareError> {
+        self.fetch_cloudflare_zones().await?;
+        self.cached_zone_id(domain)
+    }
+
+    pub async fn create_cloudflare_dns_record(
+        &self,
+        domain: &str,
+        record_type: &str,
+        content: &str,
+        ttl: &Option<u32>,
+        proxied: &bool,
+        comment: &Option<String>,
+    ) -> Result<DnsRecord, CloudflareError> {
+        // Create a new dns record in the zone of the domain, using automatic TTL if none is given
+        let zone_id = self.zone_id_for_domain(domain).await?;
+
+        let body = json!({
+            "type": record_type,
+            "name": domain,
+            "content": content,
+            "ttl": ttl.unwrap_or(1),
+            "proxied": proxied,
+            "comment": comment,
+        })
+        .to_string();
+
+        let record: CloudflareDnsRecord = self
+            .cloudflare_api_request(
+                Method::POST,
+                format!("zones/{}/dns_records", zone_id),
+                Some(body),
+            )
+            .await?;
+
+        let record = record.to_dns_record(&zone_id);
+        self.cache
+            .write()
+            .unwrap()
+            .set_dns_record(domain, record_type, record.clone());
+        self.save_cache();
+        Ok(record)
+    }
+
+    pub async fn update_cloudflare_dns_record(
+        &self,
+        domain: &str,
+        record_type: &str,
+        content: &str,
+        ttl: &Option<u32>,
+        proxied: &bool,
+        comment: &Option<String>,
+    ) -> Result<DnsRecord, CloudflareError> {
+        // Update the cached dns record of the domain's zone from Cloudflare API
+        let record = self
+            .fetch_cloudflare_dns_record(domain, record_type)
+            .await?;
+        let updated_record = self
+            .update_dns_record(&record, content, ttl, proxied, comment)
+            .await?;
+
+        self.cache
+            .write()
+            .unwrap()
+            .set_dns_record(domain, record_type, updated_record.clone());
+        self.save_cache();
+        Ok(updated_record)
+    }
+
+    pub async fn update_dns_record(
+        &self,
+        record: &DnsRecord,
+        content: &str,
+        ttl: &Option<u32>,
+        proxied: &bool,
+        comment: &Option<String>,
+    ) -> Result<DnsRecord, CloudflareError> {
+        // Update the given dns record, without touching the cache as it might be of another zone
+        let updated_record: CloudflareDnsRecord = self
+            .cloudflare_api_request(
+                Method::PUT,
+                format!("zones/{}/dns_records/{}", record.zone_id, record.id),
+                Some(update_body(content, ttl, proxied, comment)),
+            )
+            .await?;
+        check_updated_record(&updated_record, content)?;
+        Ok(updated_record.to_dns_record(&record.zone_id))
+    }
+
+    fn cached_dns_record(&self, domain: &str, record_type: &str) -> Option<DnsRecord> {
+        self.cache
+            .read()
+            .unwrap()
+            .get_dns_record(domain, record_type)
+            .cloned()
+    }
+
+    fn unexpired_dns_record(&self, domain: &str, record_type: &str) -> Option<DnsRecord> {
+        // A disabled cache counts every record as expired
+        let cache = self.cache.read().unwrap();
+        match cache.is_expired(domain, record_type) {
+            true => None,
+            false => cache.get_dns_record(domain, record_type).cloned(),
+        }
+    }
+
+    fn cached_zone_id(&self, domain: &str) -> Result<String, CloudflareError> {
+        self.cache
+            .read()
+            .unwrap()
+            .find_zone_id(domain)
+            .cloned()
+            .ok_or_else(|| zone_not_found(domain))
+    }
+
+    fn save_cache(&self) {
+        // The write lock keeps concurrent tasks from writing the cache file at the same time
+        if let Some(ref path) = self.cache_file {
+            if let Err(e) = self.cache.write().unwrap().save(path) {
+                warn!("{}", display_chain(&e));
+            }
+        }
+    }
+
+    async fn load_cloudflare_zones(&self) -> Result<Vec<String>, CloudflareError> {
+        // Fetch all zones from Cloudflare API or return cached response, the caller holds the fetch lock
+        if !self.cache.read().unwrap().zones_cached() {
+            let zones: Vec<CloudflareZone> = self.fetch_all_cloudflare_pages("zones").await?;
+
+            let mut cache = self.cache.write().unwrap();
+            zones
+                .iter()
+                .for_each(|zone| cache.add_zone(zone.name.clone(), zone.id.clone()));
+            drop(cache);
+            self.save_cache();
+        }
+        Ok(self.cache.read().unwrap().get_zones())
+    }
+
+    async fn load_zone_dns_records(
+        &self,
+        zone_id: &str,
+    ) -> Result<Vec<DnsRecord>, CloudflareError> {
+        // Fetch the dns records of a zone and add them to the cache, the caller holds the fetch lock
+        let dns_records: Vec<CloudflareDnsRecord> = self
+            .fetch_all_cloudflare_pages(dns_records_path(zone_id, &SUPPORTED_RECORD_TYPES).as_str())
+            .await?;
+        let dns_records: Vec<DnsRecord> = dns_records
+            .iter()
+            .map(|record| record.to_dns_record(zone_id))
+            .collect();
+
+        self.cache.write().unwrap().set_dns_records(&dns_records);
+        Ok(dns_records)
+    }
+
+    async fn fetch_all_cloudflare_pages<V: for<'a> Deserialize<'a>>(
+        &self,
+        path: &str,
+    ) -> Result<Vec<V>, CloudflareError> {
+        // Request the pages of a list endpoint one after another and collect all results
+        let mut results = Vec::new();
+        let mut page = 1;
+        loop {
+            let (mut page_results, result_info): (Vec<V>, _) = self
+                .cloudflare_api_page_request(Method::GET, paginated_path(path, page), None)
+                .await?;
+            results.append(&mut page_results);
+            match next_page(&result_info) {
+                Some(next) => page = next,
+                None => return Ok(results),
+            }
+        }
+    }
+
+    async fn cloudflare_api_request<V: for<'a> Deserialize<'a>>(
+        &self,
+        method: Method,
+        path: String,
+        body: Option<String>,
+    ) -> Result<V, CloudflareError> {
+        self.cloudflare_api_page_request(method, path, body)
+            .await
+            .map(|(result, _)| result)
+    }
+
+    async fn cloudflare_api_page_request<V: for<'a> Deserialize<'a>>(
+        &self,
+        method: Method,
+        path: String,
+        body: Option<String>,
+    ) -> Result<(V, Option<ResultInfo>), CloudflareError> {
+        // Make Request to Cloudflare API with the given method and path and return the result as json
+        let url = format!("{}/{}", self.api_url, path);
+        let authorization_header = format!("Bearer {}", self.token);
+
+        let method_name = method.as_str().to_lowercase();
+        if self.insecure {
+            warn!("Not verifying the TLS certificate of {}", url);
+        }
+
+        let span = debug_span!("cloudflare_request", method = method.as_str());
+        with_retry_async(&self.retry_config, || {
+            async {
+                let mut request = self
+                    .client
+                    .request(method.clone(), url.as_str())
+                    .header("Authorization", authorization_header.as_str());
+                if let Some(ref body) = body {
+                    request = request.body(body.clone());
+                }
+                if tracing::enabled!(Level::DEBUG) {
+                    if let Some(request) = request.try_clone().and_then(|r| r.build().ok()) {
+                        log_request(
+                            request.method(),
+                            request.url(),
+                            request.headers(),
+                            request.body().and_then(|b| b.as_bytes()),
+                        );
+                    }
+                }
+
+                let response = request.send().await.map_err(request_error)?;
+                let status = response.status();
+                debug!("{} {} -> {}", method, response.url(), status);
+                let retry_after = retry_after(response.headers());
+                let body = response.text().await.map_err(request_error)?;
+                debug!("Response body: {}", body);
+                parse_cloudflare_page(status, retry_after, body.as_str(), method_name.as_str())
+            }
+            .instrument(span.clone())
+        })
+        .await
+    }
+}
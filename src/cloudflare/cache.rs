@@ -1,15 +1,60 @@
+use crate::error::CloudflareError;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
 use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::NamedTempFile;
 
+pub const DEFAULT_CACHE_TTL_SECS: u64 = 3600;
+/// TTL Cloudflare uses for records with automatic TTL (1)
+pub const AUTOMATIC_TTL_SECS: u64 = 300;
+
+#[derive(Serialize, Deserialize)]
 pub struct Cache {
-    zones: Vec<String>,
-    dns_records: HashMap<String, DnsRecord>,
+    zones: Vec<CacheEntry<(String, String)>>,
+    dns_records: HashMap<String, CacheEntry<DnsRecord>>,
+    #[serde(skip, default = "default_ttl")]
+    ttl: u64,
+    /// Every lookup misses, so all DNS records are fetched again
+    #[serde(skip)]
+    disabled: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<V> {
+    value: V,
+    expires_at: u64,
+}
+
+impl<V> CacheEntry<V> {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at <= now
+    }
 }
 
-#[derive(Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct DnsRecord {
     pub id: String,
     pub zone_id: String,
+    /// Missing in cache files of older versions
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub record_type: String,
     pub content: String,
+    pub ttl: u32,
+    pub proxied: bool,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cache {
@@ -17,30 +62,311 @@ impl Cache {
         Self {
             zones: Vec::new(),
             dns_records: HashMap::new(),
+            ttl: DEFAULT_CACHE_TTL_SECS,
+            disabled: false,
         }
     }
 
+    pub fn load(path: &Path) -> Result<Cache, CloudflareError> {
+        // Read a cache file, dropping all entries that have already expired
+        let contents = fs::read_to_string(path).map_err(|source| CloudflareError::CacheIo {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let mut cache: Cache =
+            from_str(contents.as_str()).map_err(|source| CloudflareError::CacheParse {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        cache.evict_expired();
+        Ok(cache)
+    }
+
+    pub fn evict_expired(&mut self) {
+        let now = now();
+        self.zones.retain(|entry| !entry.is_expired(now));
+        self.dns_records.retain(|_, entry| !entry.is_expired(now));
+    }
+
+    pub fn is_expired(&self, domain: &str, record_type: &str) -> bool {
+        // A record that is not cached at all has to be fetched just like an expired one
+        if self.disabled {
+            return true;
+        }
+        self.dns_records
+            .get(format!("{}_{}", record_type, domain).as_str())
+            .is_none_or(|entry| entry.is_expired(now()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CloudflareError> {
+        // Write to a temporary file and rename it over the cache file, so a crash never leaves
+        // truncated JSON behind
+        let contents = to_string(self).expect("Unable to serialize Cache");
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        NamedTempFile::new_in(directory)
+            .and_then(|mut temp_file| {
+                temp_file.write_all(contents.as_bytes())?;
+                temp_file.persist(path).map_err(|e| e.error)?;
+                Ok(())
+            })
+            .map_err(|source| CloudflareError::CacheIo {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+
+    pub fn set_ttl(&mut self, ttl: u64) {
+        self.ttl = ttl;
+    }
+
+    pub fn disable(&mut self) {
+        // Fetched records are still stored, they are read back right after fetching them
+        self.disabled = true;
+    }
+
     pub fn zones_cached(&self) -> bool {
-        !self.zones.is_empty()
+        // The zone list is fetched again as well, just like every DNS record
+        !self.disabled && !self.zones.is_empty()
+    }
+
+    pub fn zone_count(&self) -> usize {
+        self.zones.len()
+    }
+
+    pub fn dns_record_count(&self) -> usize {
+        self.dns_records.len()
     }
 
     pub fn get_zones(&self) -> Vec<String> {
-        self.zones.clone()
+        self.zones
+            .iter()
+            .map(|entry| entry.value.1.clone())
+            .collect()
+    }
+
+    pub fn get_zone_names(&self) -> Vec<(String, String)> {
+        self.zones.iter().map(|entry| entry.value.clone()).collect()
+    }
+
+    pub fn get_zone_id(&self, zone_name: &str) -> Option<&String> {
+        self.zones
+            .iter()
+            .find(|entry| entry.value.0 == zone_name)
+            .map(|entry| &entry.value.1)
+    }
+
+    pub fn get_zone_name(&self, zone_id: &str) -> Option<&String> {
+        self.zones
+            .iter()
+            .find(|entry| entry.value.1 == zone_id)
+            .map(|entry| &entry.value.0)
+    }
+
+    pub fn find_zone_id(&self, domain: &str) -> Option<&String> {
+        // Find the zone of a domain by stripping labels until a known zone name matches
+        let mut zone_name = domain;
+        loop {
+            if let Some(zone_id) = self.get_zone_id(zone_name) {
+                return Some(zone_id);
+            }
+            zone_name = zone_name.split_once('.')?.1;
+        }
+    }
+
+    pub fn find_zone_ids(&self, domain: &str) -> Vec<String> {
+        // Ids of all zones containing the domain, the most specific zone first
+        let mut zone_ids = Vec::new();
+        let mut zone_name = domain;
+        loop {
+            if let Some(zone_id) = self.get_zone_id(zone_name) {
+                zone_ids.push(zone_id.clone());
+            }
+            match zone_name.split_once('.') {
+                Some((_, parent)) => zone_name = parent,
+                None => return zone_ids,
+            }
+        }
     }
 
     pub fn get_dns_record(&self, domain: &str, record_type: &str) -> Option<&DnsRecord> {
-        self.dns_records.get(format!("{}_{}", record_type, domain).as_str())
+        self.dns_records
+            .get(format!("{}_{}", record_type, domain).as_str())
+            .filter(|entry| !entry.is_expired(now()))
+            .map(|entry| &entry.value)
+    }
+
+    pub fn add_zone(&mut self, zone_name: String, zone_id: String) {
+        // A zone fetched again replaces its previous entry
+        self.zones.retain(|entry| entry.value.0 != zone_name);
+        self.zones.push(CacheEntry {
+            value: (zone_name, zone_id),
+            expires_at: now() + self.ttl,
+        });
+    }
+
+    pub fn set_dns_record(&mut self, domain: &str, record_type: &str, record: DnsRecord) {
+        // Resolvers may serve the record for its TTL anyway, so it is not cached any longer
+        let record_ttl = match record.ttl {
+            1 => AUTOMATIC_TTL_SECS,
+            ttl => ttl as u64,
+        };
+        self.dns_records.insert(
+            format!("{}_{}", record_type, domain),
+            CacheEntry {
+                value: record,
+                expires_at: now() + record_ttl.min(self.ttl),
+            },
+        );
     }
 
-    pub fn add_zone(&mut self, zone_id: String) {
-        self.zones.push(zone_id);
+    pub fn set_dns_records(&mut self, records: &[DnsRecord]) {
+        for record in records.iter() {
+            self.set_dns_record(&record.name, &record.record_type, record.clone());
+        }
     }
 
-    pub fn set_dns_record(&mut self, domain: &str, record_type: &str, record_id: &str, zone_id: &str, content: &str) {
-        self.dns_records.insert(format!("{}_{}", record_type, domain), DnsRecord {
-            id: record_id.to_string(),
-            zone_id: zone_id.to_string(),
-            content: content.to_string(),
+    pub fn remove_dns_record(&mut self, domain: &str, record_type: &str) {
+        self.dns_records
+            .remove(format!("{}_{}", record_type, domain).as_str());
+    }
+
+    pub fn evict_domain(&mut self, domain: &str) {
+        // Keys are <record type>_<domain>, record types never contain an underscore
+        self.dns_records.retain(|key, _| {
+            key.split_once('_')
+                .is_none_or(|(_, cached_domain)| cached_domain != domain)
         });
     }
-}
\ No newline at end of file
+
+    pub fn evict_zone(&mut self, zone_id: &str) {
+        self.zones.retain(|entry| entry.value.1 != zone_id);
+        self.dns_records
+            .retain(|_, entry| entry.value.zone_id != zone_id);
+    }
+}
+
+fn default_ttl() -> u64 {
+    DEFAULT_CACHE_TTL_SECS
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dns_record(ttl: u32) -> DnsRecord {
+        DnsRecord {
+            id: "record".to_string(),
+            zone_id: "zone".to_string(),
+            name: "a.example.com".to_string(),
+            record_type: "A".to_string(),
+            content: "192.0.2.1".to_string(),
+            ttl,
+            proxied: false,
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn expires_record_after_its_ttl() {
+        let mut cache = Cache::new();
+        cache.set_dns_record("a.example.com", "A", dns_record(1));
+        cache.set_dns_record("b.example.com", "A", dns_record(120));
+        let expires_at = |domain: &str| cache.dns_records[&format!("A_{}", domain)].expires_at;
+        assert_eq!(expires_at("a.example.com") - now(), AUTOMATIC_TTL_SECS);
+        assert_eq!(expires_at("b.example.com") - now(), 120);
+        assert!(!cache.is_expired("a.example.com", "A"));
+        assert!(cache.is_expired("c.example.com", "A"));
+    }
+
+    #[test]
+    fn evicts_expired_records() {
+        let mut cache = Cache::new();
+        cache.set_ttl(0);
+        cache.set_dns_record("a.example.com", "A", dns_record(300));
+        assert!(cache.is_expired("a.example.com", "A"));
+        assert!(cache.get_dns_record("a.example.com", "A").is_none());
+
+        cache.evict_expired();
+        assert_eq!(cache.dns_record_count(), 0);
+    }
+
+    #[test]
+    fn saves_cache_file_in_place() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("cache.json");
+        let mut cache = Cache::new();
+        cache.set_dns_record("a.example.com", "A", dns_record(300));
+        cache.save(&path).unwrap();
+        cache.save(&path).unwrap();
+
+        assert_eq!(Cache::load(&path).unwrap().dns_record_count(), 1);
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn misses_every_lookup_when_disabled() {
+        let mut cache = Cache::new();
+        cache.disable();
+        cache.set_dns_record("a.example.com", "A", dns_record(300));
+        assert!(cache.is_expired("a.example.com", "A"));
+        assert!(cache.get_dns_record("a.example.com", "A").is_some());
+
+        cache.add_zone("example.com".to_string(), "zone".to_string());
+        cache.add_zone("example.com".to_string(), "zone".to_string());
+        assert!(!cache.zones_cached());
+        assert_eq!(cache.get_zones(), vec!["zone"]);
+    }
+
+    #[test]
+    fn evicts_domains_and_zones() {
+        let mut cache = Cache::new();
+        cache.add_zone("example.com".to_string(), "zone".to_string());
+        cache.add_zone("example.org".to_string(), "z2".to_string());
+        cache.set_dns_record("a.example.com", "A", dns_record(300));
+        cache.set_dns_record("a.example.com", "AAAA", dns_record(300));
+        cache.set_dns_record("b.a.example.com", "A", dns_record(300));
+        cache.set_dns_record(
+            "a.example.org",
+            "A",
+            DnsRecord {
+                zone_id: "z2".to_string(),
+                ..dns_record(300)
+            },
+        );
+
+        cache.evict_domain("a.example.com");
+        assert!(cache.get_dns_record("a.example.com", "A").is_none());
+        assert!(cache.get_dns_record("a.example.com", "AAAA").is_none());
+        assert!(cache.get_dns_record("b.a.example.com", "A").is_some());
+
+        cache.evict_zone("zone");
+        assert_eq!(
+            cache.get_zone_names(),
+            vec![("example.org".to_string(), "z2".to_string())]
+        );
+        assert_eq!(cache.dns_record_count(), 1);
+        assert!(cache.get_dns_record("a.example.org", "A").is_some());
+    }
+
+    #[test]
+    fn finds_all_zones_of_domain() {
+        let mut cache = Cache::new();
+        cache.add_zone("example.com".to_string(), "z1".to_string());
+        cache.add_zone("foo.example.com".to_string(), "z2".to_string());
+        cache.add_zone("example.org".to_string(), "z3".to_string());
+        assert_eq!(cache.find_zone_ids("a.foo.example.com"), vec!["z2", "z1"]);
+        assert_eq!(cache.find_zone_ids("bar.example.com"), vec!["z1"]);
+        assert!(cache.find_zone_ids("example.net").is_empty());
+    }
+}
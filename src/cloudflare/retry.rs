@@ -0,0 +1,202 @@
+use crate::error::{display_chain, CloudflareError};
+use rand::Rng;
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+pub const DEFAULT_BASE_DELAY_MS: u64 = 500;
+pub const DEFAULT_MAX_DELAY_MS: u64 = 30_000;
+
+#[derive(Clone)]
+pub struct RetryConfig {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: DEFAULT_MAX_RETRIES + 1,
+            base_delay_ms: DEFAULT_BASE_DELAY_MS,
+            max_delay_ms: DEFAULT_MAX_DELAY_MS,
+        }
+    }
+}
+
+impl RetryConfig {
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        // Honor Retry-After if the server sent one, otherwise back off exponentially with full jitter
+        if let Some(retry_after) = retry_after {
+            // Some jitter keeps throttled clients from all retrying at the same moment,
+            // the cap keeps a bogus Retry-After from stalling the update for hours
+            let jitter = rand::thread_rng().gen_range(0..=self.base_delay_ms);
+            return (retry_after + Duration::from_millis(jitter))
+                .min(Duration::from_millis(self.max_delay_ms));
+        }
+        let max_delay = self
+            .base_delay_ms
+            .saturating_mul(2u64.saturating_pow(attempt))
+            .min(self.max_delay_ms);
+        Duration::from_millis(rand::thread_rng().gen_range(0..=max_delay))
+    }
+}
+
+pub(crate) enum RequestError {
+    /// The request might succeed when sent again, e.g. network errors, 429 and 5xx responses
+    Transient {
+        error: CloudflareError,
+        retry_after: Option<Duration>,
+    },
+    /// Sending the request again will not help, e.g. invalid credentials
+    Permanent(CloudflareError),
+}
+
+fn warn_retry(error: &CloudflareError, delay: Duration, attempt: u32, retry_config: &RetryConfig) {
+    warn!(
+        "{}, retrying in {} ms (attempt {} of {})",
+        display_chain(error),
+        delay.as_millis(),
+        attempt + 1,
+        retry_config.max_attempts
+    );
+}
+
+pub(crate) fn with_retry<V, F: FnMut() -> Result<V, RequestError>>(
+    retry_config: &RetryConfig,
+    mut request: F,
+) -> Result<V, CloudflareError> {
+    let mut attempt = 1;
+    loop {
+        match request() {
+            Ok(v) => return Ok(v),
+            Err(RequestError::Permanent(error)) => return Err(error),
+            Err(RequestError::Transient { error, retry_after }) => {
+                if attempt >= retry_config.max_attempts {
+                    return Err(error);
+                }
+                let delay = retry_config.delay(attempt - 1, retry_after);
+                warn_retry(&error, delay, attempt, retry_config);
+                thread::sleep(delay);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) async fn with_retry_async<V, F, Fut>(
+    retry_config: &RetryConfig,
+    mut request: F,
+) -> Result<V, CloudflareError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<V, RequestError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Ok(v) => return Ok(v),
+            Err(RequestError::Permanent(error)) => return Err(error),
+            Err(RequestError::Transient { error, retry_after }) => {
+                if attempt >= retry_config.max_attempts {
+                    return Err(error);
+                }
+                let delay = retry_config.delay(attempt - 1, retry_after);
+                warn_retry(&error, delay, attempt, retry_config);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    fn retry_config(max_attempts: u32, base_delay_ms: u64, max_delay_ms: u64) -> RetryConfig {
+        RetryConfig {
+            max_attempts,
+            base_delay_ms,
+            max_delay_ms,
+        }
+    }
+
+    fn transient() -> RequestError {
+        RequestError::Transient {
+            error: CloudflareError::RateLimited { retry_after: 0 },
+            retry_after: None,
+        }
+    }
+
+    fn permanent(status: StatusCode) -> RequestError {
+        RequestError::Permanent(CloudflareError::StatusError {
+            method: "get".to_string(),
+            status,
+        })
+    }
+
+    #[test]
+    fn doubles_delay_per_attempt_up_to_max_delay() {
+        let config = retry_config(4, 100, 1_000);
+        for _ in 0..100 {
+            assert!(config.delay(0, None) <= Duration::from_millis(100));
+            assert!(config.delay(2, None) <= Duration::from_millis(400));
+            assert!(config.delay(40, None) <= Duration::from_millis(1_000));
+        }
+        assert_eq!(retry_config(4, 0, 1_000).delay(3, None), Duration::ZERO);
+    }
+
+    #[test]
+    fn prefers_retry_after_capped_at_max_delay() {
+        let config = retry_config(4, 0, 10_000);
+        assert_eq!(
+            config.delay(0, Some(Duration::from_secs(5))),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            config.delay(0, Some(Duration::from_secs(3_600))),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn stops_retrying_after_max_attempts() {
+        let mut attempts = 0;
+        let result: Result<(), _> = with_retry(&retry_config(3, 0, 0), || {
+            attempts += 1;
+            Err(transient())
+        });
+        assert!(matches!(result, Err(CloudflareError::RateLimited { .. })));
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn returns_response_after_transient_errors() {
+        let mut attempts = 0;
+        let result = with_retry(&retry_config(3, 0, 0), || {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(transient()),
+                _ => Ok(attempts),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn never_retries_permanent_errors() {
+        for status in [StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN] {
+            let mut attempts = 0;
+            let result: Result<(), _> = with_retry(&retry_config(3, 0, 0), || {
+                attempts += 1;
+                Err(permanent(status))
+            });
+            assert!(matches!(result, Err(CloudflareError::StatusError { .. })));
+            assert_eq!(attempts, 1);
+        }
+    }
+}
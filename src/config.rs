@@ -1,25 +1,416 @@
-use crate::cloudflare::DomainRegistration;
+use crate::cloudflare::cache::DEFAULT_CACHE_TTL_SECS;
+use crate::cloudflare::retry::{RetryConfig, DEFAULT_BASE_DELAY_MS, DEFAULT_MAX_RETRIES};
+use crate::cloudflare::{
+    redact_token, to_ascii_domain, ClientConfig, DomainRegistration, API_URL,
+    DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_READ_TIMEOUT_SECS,
+};
+use crate::daemon::DEFAULT_INTERVAL;
+use crate::error::{ConfigError, IpDetectionError, IpError};
+use crate::ip_detection::{interface_address, ip_detection_client, IpVersion, DEFAULT_STUN_SERVER};
+use crate::metrics::DEFAULT_METRICS_PORT;
+use crate::notification::{EmailConfig, DEFAULT_SMTP_PORT};
+use crate::proxy::{proxy_url, ProxyConfig};
 use crate::Args;
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use reqwest::blocking::Client;
+use reqwest::Url;
 use serde_json::{from_str, to_string_pretty};
 use std::collections::HashMap;
+use std::env;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::process;
+use std::io::{self, BufRead, Read, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::str::Lines;
+use std::sync::OnceLock;
+use std::time::Duration;
+use toml::{Table, Value};
 
 include!(concat!(env!("OUT_DIR"), "/constants.rs"));
 
+/// Profile using the config, domains and cache files without a profile suffix
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Format of the config file, detected by its extension
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// `key=value` lines with `#` comments (`.conf`)
+    KeyValue,
+    /// TOML, which can also contain the domains in a `domains` array (`.toml`)
+    Toml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> ConfigFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::KeyValue,
+        }
+    }
+}
+
+/// Type a config value has to parse to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValueType {
+    Text,
+    Number,
+    Port,
+    Bool,
+    Url,
+    /// URL of a proxy, the scheme may be omitted
+    ProxyUrl,
+    Path,
+}
+
+impl ValueType {
+    pub fn check(&self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let expected = match self {
+            ValueType::Text | ValueType::Path => return Ok(()),
+            ValueType::Number => value.parse::<u64>().err().map(|_| "a number"),
+            ValueType::Port => value.parse::<u16>().err().map(|_| "a port"),
+            ValueType::Bool => value.parse::<bool>().err().map(|_| "true or false"),
+            ValueType::Url => Url::parse(value).err().map(|_| "a URL"),
+            ValueType::ProxyUrl => proxy_url(value).err().map(|_| "a proxy URL"),
+        };
+        match expected {
+            Some(expected) => Err(ConfigError::InvalidValue {
+                key: key.to_string(),
+                value: value.to_string(),
+                expected,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A key read from the config file
+pub struct ConfigEntry {
+    pub key: &'static str,
+    pub value_type: ValueType,
+    /// Required keys may also be given on the command line
+    pub required: bool,
+    pub description: &'static str,
+}
+
+/// Every key read from the config file
+pub const CONFIG_SCHEMA: &[ConfigEntry] = &[
+    ConfigEntry {
+        key: "cloudflare_token",
+        value_type: ValueType::Text,
+        required: true,
+        description: "Cloudflare API token, file:<path> or an absolute path",
+    },
+    ConfigEntry {
+        key: "domains_file",
+        value_type: ValueType::Path,
+        required: false,
+        description: "File the registered domains are stored in",
+    },
+    ConfigEntry {
+        key: "ip_provider_v4",
+        value_type: ValueType::Text,
+        required: false,
+        description: "Comma separated providers of the public IPv4 address",
+    },
+    ConfigEntry {
+        key: "ip_provider_v6",
+        value_type: ValueType::Text,
+        required: false,
+        description: "Comma separated providers of the public IPv6 address",
+    },
+    ConfigEntry {
+        key: "stun_server",
+        value_type: ValueType::Text,
+        required: false,
+        description: "STUN server detecting the public ip",
+    },
+    ConfigEntry {
+        key: "cloudflare_api_url",
+        value_type: ValueType::Url,
+        required: false,
+        description: "Base url of the Cloudflare API",
+    },
+    ConfigEntry {
+        key: "http_timeout_secs",
+        value_type: ValueType::Number,
+        required: false,
+        description: "Timeout of all HTTP requests",
+    },
+    ConfigEntry {
+        key: "connect_timeout_secs",
+        value_type: ValueType::Number,
+        required: false,
+        description: "Timeout connecting to Cloudflare and the ip detection services",
+    },
+    ConfigEntry {
+        key: "read_timeout_secs",
+        value_type: ValueType::Number,
+        required: false,
+        description: "Timeout reading their responses",
+    },
+    ConfigEntry {
+        key: "max_retries",
+        value_type: ValueType::Number,
+        required: false,
+        description: "Retries of failed Cloudflare requests",
+    },
+    ConfigEntry {
+        key: "retry_base_delay_ms",
+        value_type: ValueType::Number,
+        required: false,
+        description: "Delay before the first retry, doubled for each further one",
+    },
+    ConfigEntry {
+        key: "cache_file",
+        value_type: ValueType::Path,
+        required: false,
+        description: "File DNS records of Cloudflare are cached in",
+    },
+    ConfigEntry {
+        key: "cache_ttl_secs",
+        value_type: ValueType::Number,
+        required: false,
+        description: "Seconds cached DNS records are used for",
+    },
+    ConfigEntry {
+        key: "lock_file",
+        value_type: ValueType::Path,
+        required: false,
+        description: "File locked while updating",
+    },
+    ConfigEntry {
+        key: "history_file",
+        value_type: ValueType::Path,
+        required: false,
+        description: "File every change of a DNS record is appended to",
+    },
+    ConfigEntry {
+        key: "default_ttl",
+        value_type: ValueType::Number,
+        required: false,
+        description: "TTL of records without their own TTL",
+    },
+    ConfigEntry {
+        key: "min_ttl",
+        value_type: ValueType::Number,
+        required: false,
+        description: "Lowest TTL sent to Cloudflare",
+    },
+    ConfigEntry {
+        key: "max_ttl",
+        value_type: ValueType::Number,
+        required: false,
+        description: "Highest TTL sent to Cloudflare",
+    },
+    ConfigEntry {
+        key: "interval",
+        value_type: ValueType::Number,
+        required: false,
+        description: "Seconds between updates of the daemon",
+    },
+    ConfigEntry {
+        key: "update_on_startup",
+        value_type: ValueType::Bool,
+        required: false,
+        description: "Update all records on the first run of the daemon",
+    },
+    ConfigEntry {
+        key: "pid_file",
+        value_type: ValueType::Path,
+        required: false,
+        description: "File the daemon writes its pid to",
+    },
+    ConfigEntry {
+        key: "metrics_port",
+        value_type: ValueType::Port,
+        required: false,
+        description: "Port the daemon serves metrics on",
+    },
+    ConfigEntry {
+        key: "webhook_url",
+        value_type: ValueType::Url,
+        required: false,
+        description: "Url notified about changed ips",
+    },
+    ConfigEntry {
+        key: "smtp_host",
+        value_type: ValueType::Text,
+        required: false,
+        description: "SMTP server sending summary emails of updates",
+    },
+    ConfigEntry {
+        key: "smtp_port",
+        value_type: ValueType::Port,
+        required: false,
+        description: "Port of the SMTP server",
+    },
+    ConfigEntry {
+        key: "smtp_user",
+        value_type: ValueType::Text,
+        required: false,
+        description: "User logging in to the SMTP server",
+    },
+    ConfigEntry {
+        key: "smtp_password",
+        value_type: ValueType::Text,
+        required: false,
+        description: "Password of the SMTP user",
+    },
+    ConfigEntry {
+        key: "smtp_from",
+        value_type: ValueType::Text,
+        required: false,
+        description: "Sender of the summary emails",
+    },
+    ConfigEntry {
+        key: "smtp_to",
+        value_type: ValueType::Text,
+        required: false,
+        description: "Recipient of the summary emails",
+    },
+    ConfigEntry {
+        key: "telegram_bot_token",
+        value_type: ValueType::Text,
+        required: false,
+        description: "Token of the Telegram bot messaging about updates",
+    },
+    ConfigEntry {
+        key: "telegram_chat_id",
+        value_type: ValueType::Text,
+        required: false,
+        description: "Chat the Telegram bot messages",
+    },
+    ConfigEntry {
+        key: "notification_log_file",
+        value_type: ValueType::Path,
+        required: false,
+        description: "File a line is appended to for every changed or failed record",
+    },
+    ConfigEntry {
+        key: "http_proxy",
+        value_type: ValueType::ProxyUrl,
+        required: false,
+        description: "Proxy of all HTTP requests, replaces HTTP_PROXY and HTTPS_PROXY",
+    },
+    ConfigEntry {
+        key: "danger_accept_invalid_certs",
+        value_type: ValueType::Bool,
+        required: false,
+        description: "Accept invalid TLS certificates of Cloudflare and the ip detection services",
+    },
+    ConfigEntry {
+        key: "allow_private_ips",
+        value_type: ValueType::Bool,
+        required: false,
+        description: "Send private, loopback and link local addresses to Cloudflare",
+    },
+    ConfigEntry {
+        key: "zone_filter",
+        value_type: ValueType::Text,
+        required: false,
+        description: "Only update records whose Cloudflare zone has this name",
+    },
+    ConfigEntry {
+        key: "bind_interface",
+        value_type: ValueType::Text,
+        required: false,
+        description: "Network interface ip detection and Cloudflare API requests are sent from",
+    },
+    ConfigEntry {
+        key: "last_ipv4",
+        value_type: ValueType::Text,
+        required: false,
+        description: "Last IPv4 address, written by older versions",
+    },
+    ConfigEntry {
+        key: "last_ipv6",
+        value_type: ValueType::Text,
+        required: false,
+        description: "Last IPv6 address, written by older versions",
+    },
+    ConfigEntry {
+        key: "last_update",
+        value_type: ValueType::Number,
+        required: false,
+        description: "Time of the last update, written by older versions",
+    },
+];
+
 pub struct Config {
     config_file: PathBuf,
+    format: ConfigFormat,
     domains_file: Option<PathBuf>,
+    domains_file_source: ConfigSource,
     cloudflare_token: String,
+    cloudflare_token_source: ConfigSource,
+    ip_providers: Vec<String>,
     config_entries: HashMap<String, String>,
+    embedded_domains: Option<Vec<DomainRegistration>>,
+    profile: String,
+    stun: bool,
+    insecure: bool,
+    allow_private_ips: bool,
+    zone_filter: Option<String>,
+    bind_interface: Option<String>,
+    /// Addresses used instead of detecting the ip of any source
+    ip_overrides: HashMap<IpVersion, String>,
+    timeout: Option<u64>,
+    /// Shared by all ip detections of a version, built on first use
+    ip_detection_clients: HashMap<IpVersion, OnceLock<Client>>,
+}
+
+/// Where an effective config value comes from
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ConfigSource {
+    Cli,
+    Env,
+    Stdin,
+    File,
+    #[default]
+    Default,
+}
+
+impl ConfigSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigSource::Cli => "cli",
+            ConfigSource::Env => "env",
+            ConfigSource::Stdin => "stdin",
+            ConfigSource::File => "file",
+            ConfigSource::Default => "default",
+        }
+    }
+
+    pub fn of_arg(matches: &ArgMatches, id: &str) -> ConfigSource {
+        // Arguments neither given on the command line nor in the environment keep their default
+        match matches.value_source(id) {
+            Some(ValueSource::CommandLine) => ConfigSource::Cli,
+            Some(ValueSource::EnvVariable) => ConfigSource::Env,
+            _ => ConfigSource::Default,
+        }
+    }
+}
+
+/// Value of a config entry as it is used, see `Config::effective_values`
+pub struct ConfigValue {
+    pub key: String,
+    pub value: String,
+    pub source: ConfigSource,
+}
+
+/// Config entries and domains merged into a TOML config, see `Config::migrate_to_toml`
+pub struct TomlMigration {
+    /// Keys of the schema written to the TOML config
+    pub migrated: Vec<String>,
+    /// Unknown keys, they are not written
+    pub skipped: Vec<String>,
+    pub domains: usize,
 }
 
 trait ConfigProcessor {
-    fn process_comment(self: &mut Self, line: &str);
-    fn process_config_entry(self: &mut Self, key: &str, value: &str);
+    fn process_comment(&mut self, line: &str);
+    fn process_config_entry(&mut self, key: &str, value: &str);
 }
 
 struct ConfigReader<'a> {
@@ -33,9 +424,9 @@ impl<'a> ConfigReader<'a> {
 }
 
 impl<'a> ConfigProcessor for ConfigReader<'a> {
-    fn process_comment(self: &mut Self, _line: &str) {}
+    fn process_comment(&mut self, _line: &str) {}
 
-    fn process_config_entry(self: &mut Self, key: &str, value: &str) {
+    fn process_config_entry(&mut self, key: &str, value: &str) {
         self.config
             .config_entries
             .insert(key.to_string(), value.to_string());
@@ -45,17 +436,23 @@ impl<'a> ConfigProcessor for ConfigReader<'a> {
 struct ConfigWriter {
     pub new_content: String,
     new_key: String,
-    new_value: String,
+    /// None removes the entry
+    new_value: Option<String>,
+    key_written: bool,
 }
 
 impl ConfigProcessor for ConfigWriter {
-    fn process_comment(self: &mut Self, line: &str) {
+    fn process_comment(&mut self, line: &str) {
         self.new_content.push_str(line);
-        self.new_content.push_str("\n");
+        self.new_content.push('\n');
     }
-    fn process_config_entry(self: &mut Self, key: &str, value: &str) {
+    fn process_config_entry(&mut self, key: &str, value: &str) {
         let value = if self.new_key.as_str() == key {
-            self.new_value.as_str()
+            self.key_written = true;
+            match self.new_value {
+                Some(ref new_value) => new_value.as_str(),
+                None => return,
+            }
         } else {
             value
         };
@@ -66,33 +463,354 @@ impl ConfigProcessor for ConfigWriter {
 }
 
 impl Config {
-    pub fn new(args: &Args) -> Config {
-        let config_file_path = args
-            .config_file
-            .clone()
-            .unwrap_or_else(|| DEFAULT_CONF_FILE.into());
+    pub fn new(args: &Args) -> Result<Config, ConfigError> {
+        let config_file_path = profile_path(&base_config_file(args), &args.profile);
 
         let mut config = Config {
+            format: ConfigFormat::from_path(&config_file_path),
             config_file: config_file_path,
             domains_file: args.domains_file.clone(),
+            domains_file_source: args.domains_file_source,
             cloudflare_token: args.cloudflare_token.clone(),
+            cloudflare_token_source: args.cloudflare_token_source,
+            ip_providers: args.ip_provider.clone(),
             config_entries: HashMap::new(),
+            embedded_domains: None,
+            profile: args.profile.clone(),
+            stun: false,
+            insecure: args.insecure,
+            allow_private_ips: args.allow_private_ips,
+            zone_filter: args.zone_filter.clone(),
+            bind_interface: args.bind_interface.clone(),
+            ip_overrides: HashMap::new(),
+            timeout: args.timeout,
+            ip_detection_clients: ip_detection_clients(),
         };
-        config.read_config();
-        config
+        config.read_config()?;
+        Ok(config)
     }
 
-    pub fn read_cloudflare_token(self: &Self) -> String {
-        if self.cloudflare_token.len() > 0 {
-            return self.cloudflare_token.clone();
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        // Only looks at the files, the token might still be rejected by Cloudflare
+        if self.read_cloudflare_token()?.trim().is_empty() {
+            return Err(ConfigError::MissingToken);
+        }
+
+        if let Some(e) = self.check_entries().into_iter().next() {
+            return Err(e);
+        }
+
+        if !self.domains_embedded() {
+            let path = self.read_domains_file_path();
+            if let Some(directory) = path.parent() {
+                if !directory.as_os_str().is_empty() && !directory.is_dir() {
+                    return Err(ConfigError::MissingDirectory { path });
+                }
+            }
+        }
+        self.read_domains().map(|_| ())
+    }
+
+    pub fn check_entries(&self) -> Vec<ConfigError> {
+        // Every missing required key and every value not parsing to the type of its key
+        let mut errors = Vec::new();
+        for entry in CONFIG_SCHEMA.iter() {
+            match self.read_config_entry(entry.key) {
+                Some(value) => {
+                    if let Err(e) = entry.value_type.check(entry.key, value) {
+                        errors.push(e);
+                    }
+                }
+                None if entry.required && !self.given_as_argument(entry.key) => {
+                    errors.push(ConfigError::MissingEntry(entry.key))
+                }
+                None => {}
+            }
+        }
+        errors
+    }
+
+    fn given_as_argument(&self, key: &str) -> bool {
+        match key {
+            "cloudflare_token" => !self.cloudflare_token.is_empty(),
+            _ => false,
+        }
+    }
+
+    pub fn unknown_keys(&self) -> Vec<&str> {
+        self.list_config_entries()
+            .into_iter()
+            .map(|(key, _)| key)
+            .filter(|key| !CONFIG_SCHEMA.iter().any(|entry| entry.key == *key))
+            .collect()
+    }
+
+    pub fn migrate_to_toml(&self, output_file: &Path) -> Result<TomlMigration, ConfigError> {
+        // domains_file is left out, otherwise the TOML config would ignore its own domains
+        let mut table = Table::new();
+        let mut migration = TomlMigration {
+            migrated: Vec::new(),
+            skipped: Vec::new(),
+            domains: 0,
+        };
+        for (key, value) in self.list_config_entries() {
+            let entry = CONFIG_SCHEMA.iter().find(|entry| entry.key == key);
+            let value = match entry {
+                Some(entry) if entry.key == "domains_file" => continue,
+                Some(entry) => toml_value(&entry.value_type, value),
+                None => {
+                    migration.skipped.push(key.to_string());
+                    continue;
+                }
+            };
+            table.insert(key.to_string(), value);
+            migration.migrated.push(key.to_string());
+        }
+
+        let domains = self.read_domains()?;
+        migration.domains = domains.len();
+        table.insert(
+            "domains".to_string(),
+            Value::try_from(&domains).map_err(ConfigError::TomlSerialize)?,
+        );
+
+        let contents = toml::to_string(&table).map_err(ConfigError::TomlSerialize)?;
+        write_file_atomically(output_file, contents.as_bytes())?;
+        Ok(migration)
+    }
+
+    pub fn read_cloudflare_token(&self) -> Result<String, ConfigError> {
+        if !self.cloudflare_token.is_empty() {
+            return resolve_token(&self.cloudflare_token);
         }
 
         self.read_config_entry("cloudflare_token")
-            .expect("No Cloudflare Token found")
-            .to_string()
+            .ok_or(ConfigError::MissingToken)
+            .and_then(|token| resolve_token(token))
+    }
+
+    pub fn read_ip_providers(&self, ip_version: IpVersion) -> Vec<String> {
+        if !self.ip_providers.is_empty() {
+            return self.ip_providers.clone();
+        }
+
+        let key = match ip_version {
+            IpVersion::V4 => "ip_provider_v4",
+            IpVersion::V6 => "ip_provider_v6",
+        };
+        self.read_config_entry(key)
+            .map(|v| v.split(',').map(|p| p.trim().to_string()).collect())
+            .unwrap_or_else(|| ip_version.default_providers())
+    }
+
+    /// Detect ip addresses via STUN even if no stun_server is configured
+    pub fn enable_stun(&mut self) {
+        self.stun = true;
+    }
+
+    pub fn override_ip(&mut self, ip_version: IpVersion, ip: String) {
+        self.ip_overrides.insert(ip_version, ip);
+    }
+
+    pub fn read_ip_override(&self, ip_version: IpVersion) -> Option<&String> {
+        self.ip_overrides.get(&ip_version)
+    }
+
+    pub fn read_stun_server(&self) -> Option<String> {
+        // A configured STUN server replaces the HTTP ip detection services
+        let server = self.read_config_entry("stun_server").cloned();
+        match self.stun {
+            true => server.or_else(|| Some(DEFAULT_STUN_SERVER.to_string())),
+            false => server,
+        }
+    }
+
+    pub fn read_retry_config(&self) -> RetryConfig {
+        let max_retries = self
+            .read_config_entry("max_retries")
+            .and_then(|v| v.parse::<u32>().ok())
+            .unwrap_or(DEFAULT_MAX_RETRIES);
+        let base_delay_ms = self
+            .read_config_entry("retry_base_delay_ms")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_BASE_DELAY_MS);
+
+        RetryConfig {
+            max_attempts: max_retries.saturating_add(1),
+            base_delay_ms,
+            ..RetryConfig::default()
+        }
     }
 
-    fn read_domains_file_path(self: &Self) -> PathBuf {
+    pub fn read_allow_private_ips(&self) -> bool {
+        self.allow_private_ips
+            || self
+                .read_config_entry("allow_private_ips")
+                .is_some_and(|v| v == "true")
+    }
+
+    pub fn read_zone_filter(&self) -> Option<String> {
+        // Zone names are compared in their lowercase ASCII form, like the names of the domains
+        self.zone_filter
+            .as_ref()
+            .or(self.read_config_entry("zone_filter"))
+            .map(|zone| to_ascii_domain(zone.trim_end_matches('.')))
+    }
+
+    pub fn read_bind_interface(&self) -> Option<&String> {
+        self.bind_interface
+            .as_ref()
+            .or(self.read_config_entry("bind_interface"))
+    }
+
+    pub fn read_bind_address(&self, ip_version: IpVersion) -> Result<Option<IpAddr>, IpError> {
+        self.read_bind_interface()
+            .map(|name| interface_address(name, ip_version))
+            .transpose()
+    }
+
+    fn read_cloudflare_bind_address(&self) -> Result<Option<IpAddr>, IpError> {
+        // The Cloudflare API is reachable over both versions, ipv4 is preferred
+        self.read_bind_address(IpVersion::V4)
+            .or_else(|_| self.read_bind_address(IpVersion::V6))
+    }
+
+    pub fn read_insecure(&self) -> bool {
+        self.insecure
+            || self
+                .read_config_entry("danger_accept_invalid_certs")
+                .is_some_and(|v| v == "true")
+    }
+
+    pub fn read_email_config(&self, notify_email: &Option<String>) -> Option<EmailConfig> {
+        // Emails are only sent with a server and a recipient, the sender defaults to the SMTP user
+        let host = self.read_config_entry("smtp_host")?;
+        let to = notify_email
+            .as_ref()
+            .or(self.read_config_entry("smtp_to"))?;
+        let user = self.read_config_entry("smtp_user").cloned();
+        let from = self
+            .read_config_entry("smtp_from")
+            .cloned()
+            .or_else(|| user.clone().filter(|user| user.contains('@')))
+            .unwrap_or_else(|| to.clone());
+
+        Some(EmailConfig {
+            host: host.clone(),
+            port: self
+                .read_config_entry("smtp_port")
+                .and_then(|v| v.parse::<u16>().ok())
+                .unwrap_or(DEFAULT_SMTP_PORT),
+            user,
+            password: self.read_config_entry("smtp_password").cloned(),
+            from,
+            to: to.clone(),
+            insecure: self.read_insecure(),
+        })
+    }
+
+    pub fn read_proxy_config(&self) -> ProxyConfig {
+        ProxyConfig::new(self.read_config_entry("http_proxy"))
+    }
+
+    fn read_timeout_secs(&self, key: &str, default: u64) -> u64 {
+        // --timeout wins over the specific timeout of the config file, which wins over the general one
+        let read_secs = |key: &str| {
+            self.read_config_entry(key)
+                .and_then(|v| v.parse::<u64>().ok())
+        };
+        self.timeout
+            .or_else(|| read_secs(key))
+            .or_else(|| read_secs("http_timeout_secs"))
+            .unwrap_or(default)
+    }
+
+    pub fn ip_detection_client(&self, ip_version: IpVersion) -> Result<&Client, IpDetectionError> {
+        // A client bound to an interface only reaches the services over the version of its address
+        let cached_client = &self.ip_detection_clients[&ip_version];
+        if let Some(client) = cached_client.get() {
+            return Ok(client);
+        }
+        let client = ip_detection_client(
+            Duration::from_secs(
+                self.read_timeout_secs("connect_timeout_secs", DEFAULT_CONNECT_TIMEOUT_SECS),
+            ),
+            Duration::from_secs(
+                self.read_timeout_secs("read_timeout_secs", DEFAULT_READ_TIMEOUT_SECS),
+            ),
+            &self.read_insecure(),
+            &self.read_proxy_config(),
+            self.read_bind_address(ip_version)?,
+        )?;
+        Ok(cached_client.get_or_init(|| client))
+    }
+
+    pub fn read_client_config(&self, token: String) -> Result<ClientConfig, ConfigError> {
+        let connect_timeout =
+            self.read_timeout_secs("connect_timeout_secs", DEFAULT_CONNECT_TIMEOUT_SECS);
+        let read_timeout = self.read_timeout_secs("read_timeout_secs", DEFAULT_READ_TIMEOUT_SECS);
+
+        let cache_ttl = self
+            .read_config_entry("cache_ttl_secs")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_CACHE_TTL_SECS);
+
+        Ok(ClientConfig {
+            retry_config: self.read_retry_config(),
+            connect_timeout: Duration::from_secs(connect_timeout),
+            read_timeout: Duration::from_secs(read_timeout),
+            cache_file: Some(self.read_cache_file_path()),
+            cache_ttl,
+            api_url: self.read_config_entry("cloudflare_api_url").cloned(),
+            insecure: self.read_insecure(),
+            proxy: self.read_proxy_config(),
+            local_address: self.read_cloudflare_bind_address()?,
+            ..ClientConfig::new(token)
+        })
+    }
+
+    pub fn read_cache_file_path(&self) -> PathBuf {
+        // The cache is stored next to the config file unless configured otherwise
+        self.read_config_entry("cache_file")
+            .map(|v| v.into())
+            .unwrap_or_else(|| {
+                profile_path(
+                    &self.config_file.with_file_name("cf-dynamic.cache.json"),
+                    &self.profile,
+                )
+            })
+    }
+
+    pub fn read_history_file_path(&self) -> PathBuf {
+        // Like the cache, the history is kept next to the config file unless configured otherwise
+        self.read_config_entry("history_file")
+            .map(|v| v.into())
+            .unwrap_or_else(|| {
+                profile_path(
+                    &self.config_file.with_file_name("cf-dynamic.log.jsonl"),
+                    &self.profile,
+                )
+            })
+    }
+
+    pub fn read_notification_log_file_path(&self) -> Option<PathBuf> {
+        self.read_config_entry("notification_log_file")
+            .map(|v| v.into())
+    }
+
+    pub fn read_lock_file_path(&self) -> PathBuf {
+        // Like the cache, the lock file is next to the config file unless configured otherwise
+        self.read_config_entry("lock_file")
+            .map(|v| v.into())
+            .unwrap_or_else(|| {
+                profile_path(
+                    &self.config_file.with_file_name("cf-dynamic.lock"),
+                    &self.profile,
+                )
+            })
+    }
+
+    fn read_domains_file_path(&self) -> PathBuf {
         self.domains_file
             .clone()
             .or_else(|| {
@@ -100,99 +818,544 @@ impl Config {
                     .map(|v| v.into())
                     .clone()
             })
-            .unwrap_or_else(|| "domains.json".into())
+            .unwrap_or_else(|| profile_path(Path::new("domains.json"), &self.profile))
+    }
+
+    fn domains_embedded(&self) -> bool {
+        // Domains in the TOML config are only used if no domains file is configured
+        self.domains_file.is_none()
+            && self.read_config_entry("domains_file").is_none()
+            && self.embedded_domains.is_some()
     }
 
-    pub fn read_domains(self: &Self) -> Vec<DomainRegistration> {
+    pub fn read_domains(&self) -> Result<Vec<DomainRegistration>, ConfigError> {
+        if self.domains_embedded() {
+            return Ok(self.embedded_domains.clone().unwrap_or_default());
+        }
+
+        // A missing domains file means that no domains are registered yet
         let file_name = self.read_domains_file_path();
-        let contents: String = File::open(file_name.clone())
-            .map(|mut file| {
+        let contents: String = match File::open(file_name.clone()) {
+            Ok(mut file) => {
                 let mut contents = String::new();
-                match file.read_to_string(&mut contents) {
-                    Ok(_) => match contents.len() {
-                        0 => "[]".to_string(),
-                        _ => contents,
-                    },
-                    Err(e) => {
-                        println!("Unable to read {:?} (Error: {})", file_name, e);
-                        process::exit(1);
-                    }
+                file.read_to_string(&mut contents)
+                    .map_err(|source| ConfigError::Io {
+                        path: file_name.clone(),
+                        source,
+                    })?;
+                match contents.len() {
+                    0 => "[]".to_string(),
+                    _ => contents,
                 }
-            })
-            .unwrap_or("[]".to_string());
+            }
+            Err(_) => "[]".to_string(),
+        };
 
-        let result = from_str(contents.as_str());
-        if result.is_err() {
-            println!(
-                "Unable to parse {:#?} (Error: {})",
-                file_name,
-                result.err().unwrap()
-            );
-            process::exit(1);
+        from_str(contents.as_str()).map_err(|source| ConfigError::Json {
+            path: file_name,
+            source,
+        })
+    }
+
+    pub fn write_domains(&self, domains: &Vec<DomainRegistration>) -> Result<(), ConfigError> {
+        if self.domains_embedded() {
+            let domains = Value::try_from(domains).map_err(ConfigError::TomlSerialize)?;
+            return self.update_toml_config(|table| {
+                table.insert("domains".to_string(), domains);
+            });
         }
-        result.unwrap()
+
+        let domains_json = to_string_pretty(&domains).map_err(ConfigError::JsonSerialize)?;
+        write_file_atomically(&self.read_domains_file_path(), domains_json.as_bytes())
     }
 
-    pub fn write_domains(self: &Self, domains: &Vec<DomainRegistration>) -> Result<(), String> {
-        let domains_json =
-            to_string_pretty(&domains).expect("Unable to serialize DomainRegistrations");
-        let file_name = self.read_domains_file_path();
-        File::create(file_name.clone())
-            .and_then(|mut file| file.write_all(domains_json.as_bytes()))
-            .map_err(|e| format!("Unable to write {:#?} (Error: {})", file_name, e))
+    pub fn config_file(&self) -> &Path {
+        &self.config_file
     }
 
-    pub fn read_config_entry(self: &Self, key: &str) -> Option<&String> {
-        self.config_entries.get(key).clone()
+    pub fn effective_values(&self) -> Vec<ConfigValue> {
+        // Every known key with its value in effect, followed by unknown keys of the config file
+        let value = |key: &str, value: String, source: ConfigSource| ConfigValue {
+            key: key.to_string(),
+            value,
+            source,
+        };
+        let from_file = |key: &str, default: String| match self.read_config_entry(key) {
+            Some(v) => value(key, v.clone(), ConfigSource::File),
+            None => value(key, default, ConfigSource::Default),
+        };
+
+        let mut values = vec![match self.cloudflare_token.is_empty() {
+            false => value(
+                "cloudflare_token",
+                redact_token(&self.cloudflare_token),
+                self.cloudflare_token_source,
+            ),
+            true => match self.read_config_entry("cloudflare_token") {
+                Some(token) => value("cloudflare_token", redact_token(token), ConfigSource::File),
+                None => value("cloudflare_token", "-".to_string(), ConfigSource::Default),
+            },
+        }];
+
+        values.push(match (&self.domains_file, self.domains_embedded()) {
+            (Some(domains_file), _) => {
+                let domains_file = domains_file.display().to_string();
+                value("domains_file", domains_file, self.domains_file_source)
+            }
+            (None, true) => value(
+                "domains_file",
+                "domains of the config file".to_string(),
+                ConfigSource::File,
+            ),
+            (None, false) => from_file(
+                "domains_file",
+                self.read_domains_file_path().display().to_string(),
+            ),
+        });
+
+        for ip_version in [IpVersion::V4, IpVersion::V6] {
+            let key = match ip_version {
+                IpVersion::V4 => "ip_provider_v4",
+                IpVersion::V6 => "ip_provider_v6",
+            };
+            values.push(match self.ip_providers.is_empty() {
+                false => value(key, self.ip_providers.join(","), ConfigSource::Cli),
+                true => from_file(key, ip_version.default_providers().join(",")),
+            });
+        }
+
+        values.push(match self.read_config_entry("stun_server") {
+            Some(server) => value("stun_server", server.clone(), ConfigSource::File),
+            None if self.stun => value(
+                "stun_server",
+                DEFAULT_STUN_SERVER.to_string(),
+                ConfigSource::Cli,
+            ),
+            None => value("stun_server", "-".to_string(), ConfigSource::Default),
+        });
+
+        values.push(match env::var("CLOUDFLARE_API_URL") {
+            Ok(url) if self.read_config_entry("cloudflare_api_url").is_none() => {
+                value("cloudflare_api_url", url, ConfigSource::Env)
+            }
+            _ => from_file("cloudflare_api_url", API_URL.to_string()),
+        });
+
+        let defaults = [
+            ("http_timeout_secs", "-".to_string()),
+            (
+                "connect_timeout_secs",
+                DEFAULT_CONNECT_TIMEOUT_SECS.to_string(),
+            ),
+            ("read_timeout_secs", DEFAULT_READ_TIMEOUT_SECS.to_string()),
+            ("max_retries", DEFAULT_MAX_RETRIES.to_string()),
+            ("retry_base_delay_ms", DEFAULT_BASE_DELAY_MS.to_string()),
+            (
+                "cache_file",
+                self.read_cache_file_path().display().to_string(),
+            ),
+            ("cache_ttl_secs", DEFAULT_CACHE_TTL_SECS.to_string()),
+            (
+                "lock_file",
+                self.read_lock_file_path().display().to_string(),
+            ),
+            (
+                "history_file",
+                self.read_history_file_path().display().to_string(),
+            ),
+            ("default_ttl", "-".to_string()),
+            ("min_ttl", "-".to_string()),
+            ("max_ttl", "-".to_string()),
+            ("interval", DEFAULT_INTERVAL.to_string()),
+            ("update_on_startup", "false".to_string()),
+            ("pid_file", "-".to_string()),
+            ("metrics_port", DEFAULT_METRICS_PORT.to_string()),
+            ("webhook_url", "-".to_string()),
+            ("smtp_host", "-".to_string()),
+            ("smtp_port", DEFAULT_SMTP_PORT.to_string()),
+            ("smtp_user", "-".to_string()),
+            ("smtp_password", "-".to_string()),
+            ("smtp_from", "-".to_string()),
+            ("smtp_to", "-".to_string()),
+            ("telegram_bot_token", "-".to_string()),
+            ("telegram_chat_id", "-".to_string()),
+            ("notification_log_file", "-".to_string()),
+            ("http_proxy", "-".to_string()),
+            ("danger_accept_invalid_certs", "false".to_string()),
+            ("allow_private_ips", "false".to_string()),
+            ("zone_filter", "-".to_string()),
+            ("bind_interface", "-".to_string()),
+        ];
+        for (key, default) in defaults {
+            values.push(match (self.timeout, key) {
+                (
+                    Some(timeout),
+                    "http_timeout_secs" | "connect_timeout_secs" | "read_timeout_secs",
+                ) => value(key, timeout.to_string(), ConfigSource::Cli),
+                (_, "smtp_password" | "telegram_bot_token") => match self.read_config_entry(key) {
+                    Some(password) => value(key, redact_token(password), ConfigSource::File),
+                    None => from_file(key, default),
+                },
+                _ => from_file(key, default),
+            });
+        }
+
+        let mut unknown: Vec<&String> = self
+            .config_entries
+            .keys()
+            .filter(|key| !values.iter().any(|value| value.key == **key))
+            .collect();
+        unknown.sort();
+        for key in unknown {
+            values.push(from_file(key, String::new()));
+        }
+        values
     }
 
-    pub fn set_config_entry(self: &Self, key: &str, value: &str) -> Result<(), String> {
-        let contents: String = read_file(self.config_file.clone()).unwrap_or("".to_string());
+    pub fn list_config_entries(&self) -> Vec<(&str, &str)> {
+        let mut entries: Vec<(&str, &str)> = self
+            .config_entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    pub fn read_config_entry(&self, key: &str) -> Option<&String> {
+        self.config_entries.get(key)
+    }
+
+    pub fn set_config_entry(&self, key: &str, value: &str) -> Result<(), ConfigError> {
+        match self.format {
+            ConfigFormat::KeyValue => self.set_key_value_config_entry(key, Some(value)),
+            ConfigFormat::Toml => self.update_toml_config(|table| {
+                let value = match CONFIG_SCHEMA.iter().find(|entry| entry.key == key) {
+                    Some(entry) => toml_value(&entry.value_type, value),
+                    None => Value::String(value.to_string()),
+                };
+                table.insert(key.to_string(), value);
+            }),
+        }
+    }
+
+    /// Removes an entry from the config file, returns whether it was set
+    pub fn delete_config_entry(&self, key: &str) -> Result<bool, ConfigError> {
+        if !self.config_entries.contains_key(key) {
+            return Ok(false);
+        }
+        match self.format {
+            ConfigFormat::KeyValue => self.set_key_value_config_entry(key, None)?,
+            ConfigFormat::Toml => self.update_toml_config(|table| {
+                table.remove(key);
+            })?,
+        }
+        Ok(true)
+    }
+
+    fn set_key_value_config_entry(
+        &self,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), ConfigError> {
+        let contents: String = read_file(&self.config_file).unwrap_or_default();
 
         let mut config_writer = ConfigWriter {
             new_content: String::new(),
             new_key: key.to_string(),
-            new_value: value.to_string(),
+            new_value: value.map(|v| v.to_string()),
+            key_written: false,
         };
 
-        if !parse_config(contents.lines(), &mut config_writer) {
-            return Err("Unable to parse config file".to_string());
+        parse_config(contents.lines(), &mut config_writer)?;
+
+        // Append the entry if the config file did not contain the key yet
+        if let (false, Some(value)) = (config_writer.key_written, value) {
+            config_writer
+                .new_content
+                .push_str(format!("{}={}\n", key, value).as_str());
         }
 
-        let mut file = File::create(self.config_file.clone()).unwrap();
-        file.write_all(config_writer.new_content.as_bytes())
-            .map_err(|e| {
-                format!(
-                    "Unable to write config file {:#?} (Error: {})",
-                    self.config_file, e
-                )
-            })
+        self.write_config_file(config_writer.new_content.as_str())
+    }
+
+    fn update_toml_config<F: FnOnce(&mut Table)>(&self, update: F) -> Result<(), ConfigError> {
+        let contents: String = read_file(&self.config_file).unwrap_or_default();
+        let mut table: Table = contents.parse().map_err(|source| ConfigError::Toml {
+            path: self.config_file.clone(),
+            source,
+        })?;
+
+        update(&mut table);
+
+        let contents = toml::to_string(&table).map_err(ConfigError::TomlSerialize)?;
+        self.write_config_file(contents.as_str())
+    }
+
+    fn write_config_file(&self, contents: &str) -> Result<(), ConfigError> {
+        // The config file can hold the domains too, so it must never be left half written
+        write_file_atomically(&self.config_file, contents.as_bytes())
+    }
+
+    fn read_config(&mut self) -> Result<(), ConfigError> {
+        // A missing config file is fine, everything can be given on the command line
+        let contents: String = read_file(&self.config_file).unwrap_or_default();
+
+        match self.format {
+            ConfigFormat::KeyValue => {
+                let reader = &mut ConfigReader::new(self);
+                parse_config(contents.lines(), reader)
+            }
+            ConfigFormat::Toml => self.read_toml_config(contents.as_str()),
+        }
+    }
+
+    fn read_toml_config(&mut self, contents: &str) -> Result<(), ConfigError> {
+        let toml_error = |source| ConfigError::Toml {
+            path: self.config_file.clone(),
+            source,
+        };
+        let table: Table = contents.parse().map_err(toml_error)?;
+
+        for (key, value) in table.into_iter() {
+            if key == "domains" {
+                self.embedded_domains = Some(value.try_into().map_err(toml_error)?);
+                continue;
+            }
+            if let Some(value) = toml_value_to_string(&value) {
+                self.config_entries.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn toml_value_to_string(value: &Value) -> Option<String> {
+    // Config entries are strings, arrays are joined like a comma separated key=value entry
+    match value {
+        Value::String(v) => Some(v.clone()),
+        Value::Integer(v) => Some(v.to_string()),
+        Value::Float(v) => Some(v.to_string()),
+        Value::Boolean(v) => Some(v.to_string()),
+        Value::Array(values) => Some(
+            values
+                .iter()
+                .filter_map(toml_value_to_string)
+                .collect::<Vec<String>>()
+                .join(","),
+        ),
+        _ => None,
+    }
+}
+
+fn toml_value(value_type: &ValueType, value: &str) -> Value {
+    // Only numbers and flags get a TOML type, text like a token of digits keeps its exact form
+    match value_type {
+        ValueType::Number | ValueType::Port => match value.parse::<i64>() {
+            Ok(v) => Value::Integer(v),
+            Err(_) => Value::String(value.to_string()),
+        },
+        ValueType::Bool => match value.parse::<bool>() {
+            Ok(v) => Value::Boolean(v),
+            Err(_) => Value::String(value.to_string()),
+        },
+        _ => Value::String(value.to_string()),
     }
+}
 
-    fn read_config(self: &mut Self) {
-        let contents: String = read_file(self.config_file.clone()).unwrap_or("".to_string());
+fn write_file_atomically(path: &Path, contents: &[u8]) -> Result<(), ConfigError> {
+    // Write to a temporary file next to the target and rename it over the target, so a crash
+    // never leaves a truncated file behind. The temporary file is removed if anything fails
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or_default();
 
-        let reader = &mut ConfigReader { config: self };
+    let temp_file = tempfile::Builder::new()
+        .prefix(file_name)
+        .suffix(".tmp")
+        .tempfile_in(directory)
+        .and_then(|mut temp_file| {
+            temp_file.write_all(contents)?;
+            temp_file.as_file().sync_all()?;
+            Ok(temp_file)
+        })
+        .map_err(|source| ConfigError::TempFile {
+            path: directory.to_path_buf(),
+            source,
+        })?;
 
-        parse_config(contents.lines(), reader);
+    // Keep the previous version in case the new one is not what the user wanted
+    if path.exists() {
+        let backup_path = path.with_file_name(format!("{}.bak", file_name));
+        std::fs::copy(path, &backup_path).map_err(|source| ConfigError::Backup {
+            path: backup_path,
+            source,
+        })?;
     }
+
+    temp_file
+        .persist(path)
+        .map(|_| ())
+        .map_err(|e| ConfigError::Rename {
+            path: path.to_path_buf(),
+            source: e.error,
+        })
 }
 
-fn read_file(path: PathBuf) -> Result<String, String> {
-    File::open(path)
-        .and_then(|mut file| {
-            let mut contents = String::new();
-            match file.read_to_string(&mut contents) {
-                Ok(_) => Ok(contents),
-                Err(e) => Err(e),
+fn base_config_file(args: &Args) -> PathBuf {
+    args.config_file
+        .clone()
+        .unwrap_or_else(|| DEFAULT_CONF_FILE.into())
+}
+
+fn ip_detection_clients() -> HashMap<IpVersion, OnceLock<Client>> {
+    [IpVersion::V4, IpVersion::V6]
+        .into_iter()
+        .map(|ip_version| (ip_version, OnceLock::new()))
+        .collect()
+}
+
+fn profile_path(path: &Path, profile: &str) -> PathBuf {
+    // Profiles use their own files next to the default ones, e.g. cf-dynamic-work.conf
+    if profile == DEFAULT_PROFILE {
+        return path.to_path_buf();
+    }
+
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let file_name = match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!("{}-{}.{}", stem, profile, extension),
+        None => format!("{}-{}", stem, profile),
+    };
+    path.with_file_name(file_name)
+}
+
+pub fn list_profiles(args: &Args) -> Result<Vec<String>, ConfigError> {
+    // Every config file named like the default one with a suffix is a profile
+    let config_file = base_config_file(args);
+    let stem = config_file
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let extension = config_file
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| format!(".{}", e))
+        .unwrap_or_default();
+    let prefix = format!("{}-", stem);
+
+    let directory = match config_file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let entries = std::fs::read_dir(&directory).map_err(|source| ConfigError::Io {
+        path: directory.clone(),
+        source,
+    })?;
+
+    let mut profiles: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter_map(|file_name| {
+            let profile = file_name.strip_prefix(&prefix)?.strip_suffix(&extension)?;
+            match profile.is_empty() || profile.contains('.') {
+                true => None,
+                false => Some(profile.to_string()),
             }
         })
-        .map_err(|e| format!("Unable to read config file (Error: {})", e))
+        .collect();
+    profiles.sort();
+    profiles.insert(0, DEFAULT_PROFILE.to_string());
+    Ok(profiles)
+}
+
+/// Domain registrations of a batch file, each entry is parsed on its own so one invalid entry
+/// does not hide the others
+pub type BatchEntries = Vec<Result<DomainRegistration, ConfigError>>;
+
+pub fn read_batch_file(path: &Path) -> Result<BatchEntries, ConfigError> {
+    // TOML files contain a `domains` array like the TOML config, all other files a JSON array
+    let contents = read_file(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let toml_error = |source| ConfigError::Toml {
+        path: path.to_path_buf(),
+        source,
+    };
+    let json_error = |source| ConfigError::Json {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    match ConfigFormat::from_path(path) {
+        ConfigFormat::Toml => {
+            let mut table: Table = contents.parse().map_err(toml_error)?;
+            let entries: Vec<Value> = match table.remove("domains") {
+                Some(domains) => domains.try_into().map_err(toml_error)?,
+                None => Vec::new(),
+            };
+            Ok(entries
+                .into_iter()
+                .map(|entry| entry.try_into().map_err(toml_error))
+                .collect())
+        }
+        ConfigFormat::KeyValue => {
+            let entries: Vec<serde_json::Value> = from_str(&contents).map_err(json_error)?;
+            Ok(entries
+                .into_iter()
+                .map(|entry| serde_json::from_value(entry).map_err(json_error))
+                .collect())
+        }
+    }
+}
+
+pub fn resolve_token(token: &str) -> Result<String, ConfigError> {
+    // Tokens can be kept in a file, e.g. a Docker secret, given as file:<path> or as absolute path
+    let path = match token.strip_prefix("file:") {
+        Some(path) => Path::new(path),
+        None if Path::new(token).is_absolute() && Path::new(token).is_file() => Path::new(token),
+        None => return Ok(token.to_string()),
+    };
+    read_file(path)
+        .map(|contents| contents.trim().to_string())
+        .map_err(|source| ConfigError::TokenFile {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+pub fn read_token_line(mut reader: impl BufRead) -> Result<String, ConfigError> {
+    // Only the first line is the token, secret managers often pipe a trailing newline
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .map_err(ConfigError::TokenStdin)?;
+    match line.trim() {
+        "" => Err(ConfigError::MissingToken),
+        token => Ok(token.to_string()),
+    }
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    File::open(path).and_then(|mut file| {
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).map(|_| contents)
+    })
 }
 
-fn parse_config(mut lines: Lines, config_processor: &mut dyn ConfigProcessor) -> bool {
+fn parse_config(
+    mut lines: Lines,
+    config_processor: &mut dyn ConfigProcessor,
+) -> Result<(), ConfigError> {
     // Extensive Support for comments and empty lines
-    // Print very descriptive error message if config file is not valid
+    // Return a very descriptive error if config file is not valid
     let mut line_number = 0;
     loop {
         line_number += 1;
@@ -203,7 +1366,7 @@ fn parse_config(mut lines: Lines, config_processor: &mut dyn ConfigProcessor) ->
 
         let line = orig_line.trim();
 
-        if line.len() == 0 || line.starts_with('#') {
+        if line.is_empty() || line.starts_with('#') {
             config_processor.process_comment(orig_line);
             continue;
         }
@@ -213,32 +1376,437 @@ fn parse_config(mut lines: Lines, config_processor: &mut dyn ConfigProcessor) ->
             None => ("", line),
         };
 
-        let mut parts = line.trim().split('=');
-        let key = match parts.next() {
-            Some(k) => k.trim(),
-            None => {
-                println!("Config file is not valid (Line {}: {})", line_number, line);
-                return false;
-            }
-        };
-        let value = match parts.next() {
-            Some(v) => v.trim(),
-            None => {
-                println!("Config file is not valid (Line {}: {})", line_number, line);
-                return false;
+        // Only the first = separates key and value, e.g. base64 tokens end with =
+        let (key, value) = match line.trim().split_once('=') {
+            Some((k, v)) if !k.trim().is_empty() => (k.trim(), v.trim()),
+            _ => {
+                return Err(invalid_line(line_number, line));
             }
         };
 
-        if parts.next().is_some() {
-            println!("Config file is not valid (Line {}: {})", line_number, line);
-            return false;
-        }
-
         config_processor.process_config_entry(key, value);
 
-        if comment.len() > 0 {
+        if !comment.is_empty() {
             config_processor.process_comment(comment);
         }
     }
-    return true;
+    Ok(())
+}
+
+fn invalid_line(line_number: usize, line: &str) -> ConfigError {
+    ConfigError::InvalidLine {
+        line_number,
+        line: line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Entries(Vec<(String, String)>);
+
+    impl ConfigProcessor for Entries {
+        fn process_comment(&mut self, _line: &str) {}
+
+        fn process_config_entry(&mut self, key: &str, value: &str) {
+            self.0.push((key.to_string(), value.to_string()));
+        }
+    }
+
+    #[test]
+    fn splits_on_first_equals_sign() {
+        let mut entries = Entries::default();
+        parse_config(
+            "cloudflare_token=c2VjcmV0LXRva2Vu==\nwebhook_url = https://x/?a=b".lines(),
+            &mut entries,
+        )
+        .unwrap();
+        assert_eq!(
+            entries.0,
+            vec![
+                (
+                    "cloudflare_token".to_string(),
+                    "c2VjcmV0LXRva2Vu==".to_string()
+                ),
+                ("webhook_url".to_string(), "https://x/?a=b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrites_value_containing_equals_sign() {
+        let mut config_writer = ConfigWriter {
+            new_content: String::new(),
+            new_key: "cloudflare_token".to_string(),
+            new_value: Some("bmV3LXRva2Vu=".to_string()),
+            key_written: false,
+        };
+        parse_config(
+            "cloudflare_token=b2xk==\ninterval=60".lines(),
+            &mut config_writer,
+        )
+        .unwrap();
+        assert_eq!(
+            config_writer.new_content,
+            "cloudflare_token=bmV3LXRva2Vu=\ninterval=60\n"
+        );
+    }
+
+    #[test]
+    fn omits_deleted_entry() {
+        let mut config_writer = ConfigWriter {
+            new_content: String::new(),
+            new_key: "cloudflare_token".to_string(),
+            new_value: None,
+            key_written: false,
+        };
+        parse_config(
+            "#Token\ncloudflare_token=b2xk==\ninterval=60".lines(),
+            &mut config_writer,
+        )
+        .unwrap();
+        assert_eq!(config_writer.new_content, "#Token\ninterval=60\n");
+    }
+
+    #[test]
+    fn rejects_line_without_equals_sign() {
+        assert!(matches!(
+            parse_config("interval".lines(), &mut Entries::default()),
+            Err(ConfigError::InvalidLine { line_number: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_line_without_key() {
+        assert!(matches!(
+            parse_config("interval=60\n = 300".lines(), &mut Entries::default()),
+            Err(ConfigError::InvalidLine { line_number: 2, .. })
+        ));
+    }
+
+    fn parse_entries(content: &str) -> Vec<(String, String)> {
+        let mut entries = Entries::default();
+        parse_config(content.lines(), &mut entries).unwrap();
+        entries.0
+    }
+
+    fn entry(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn accepts_empty_input() {
+        assert!(parse_entries("").is_empty());
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        assert!(parse_entries("# cf-dynamic\n\n   \n  # interval=60\n").is_empty());
+    }
+
+    #[test]
+    fn keeps_spaces_inside_value() {
+        assert_eq!(
+            parse_entries("  telegram_message =  IP of {domain} changed  "),
+            vec![entry("telegram_message", "IP of {domain} changed")]
+        );
+    }
+
+    #[test]
+    fn strips_inline_comment() {
+        assert_eq!(
+            parse_entries("interval=60 # seconds\nmax_retries=3#"),
+            vec![entry("interval", "60"), entry("max_retries", "3")]
+        );
+    }
+
+    #[test]
+    fn keeps_every_equals_sign_after_the_first() {
+        assert_eq!(
+            parse_entries("cloudflare_token==a=b=="),
+            vec![entry("cloudflare_token", "=a=b==")]
+        );
+    }
+
+    #[test]
+    fn reads_utf8_values() {
+        assert_eq!(
+            parse_entries("telegram_message=IP von {domain} geändert ✓"),
+            vec![entry("telegram_message", "IP von {domain} geändert ✓")]
+        );
+    }
+
+    #[test]
+    fn ignores_trailing_newlines() {
+        assert_eq!(
+            parse_entries("interval=60\r\n\n"),
+            vec![entry("interval", "60")]
+        );
+    }
+
+    #[test]
+    fn accepts_empty_value() {
+        assert_eq!(
+            parse_entries("webhook_url="),
+            vec![entry("webhook_url", "")]
+        );
+    }
+
+    fn validate_config(content: &str) -> Result<(), ConfigError> {
+        use clap::Parser;
+
+        let directory = tempfile::tempdir().unwrap();
+        let config_file = directory.path().join("cf-dynamic.conf");
+        std::fs::write(&config_file, content).unwrap();
+        let args = Args::parse_from([
+            "cf-dynamic",
+            "--config-check",
+            "-c",
+            config_file.to_str().unwrap(),
+        ]);
+        Config::new(&args)?.validate()
+    }
+
+    #[test]
+    fn accepts_valid_config() {
+        assert!(validate_config("cloudflare_token=secret\ninterval=300\n").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        assert!(matches!(
+            validate_config("cloudflare_token=\n"),
+            Err(ConfigError::MissingToken)
+        ));
+        assert!(matches!(
+            validate_config("cloudflare_token=secret\ninterval=soon\n"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            validate_config("cloudflare_token=secret\ndomains_file=/nonexistent/domains.json\n"),
+            Err(ConfigError::MissingDirectory { .. })
+        ));
+    }
+
+    fn parsed_config(contents: &str) -> Config {
+        let mut config = Config {
+            config_file: PathBuf::from("cf-dynamic.conf"),
+            format: ConfigFormat::KeyValue,
+            domains_file: None,
+            domains_file_source: ConfigSource::Default,
+            cloudflare_token: String::new(),
+            cloudflare_token_source: ConfigSource::Default,
+            ip_providers: Vec::new(),
+            config_entries: HashMap::new(),
+            embedded_domains: None,
+            profile: String::new(),
+            stun: false,
+            insecure: false,
+            allow_private_ips: false,
+            zone_filter: None,
+            bind_interface: None,
+            ip_overrides: HashMap::new(),
+            timeout: None,
+            ip_detection_clients: ip_detection_clients(),
+        };
+        parse_config(contents.lines(), &mut ConfigReader::new(&mut config)).unwrap();
+        config
+    }
+
+    #[test]
+    fn reads_first_line_as_token() {
+        assert_eq!(
+            read_token_line("  c2VjcmV0==\nsecond line\n".as_bytes()).unwrap(),
+            "c2VjcmV0=="
+        );
+        assert!(matches!(
+            read_token_line("\n".as_bytes()),
+            Err(ConfigError::MissingToken)
+        ));
+        assert!(matches!(
+            read_token_line("".as_bytes()),
+            Err(ConfigError::MissingToken)
+        ));
+    }
+
+    #[test]
+    fn migrates_config_and_domains_to_toml() {
+        use clap::Parser;
+
+        let directory = tempfile::tempdir().unwrap();
+        let domains_file = directory.path().join("domains.json");
+        std::fs::write(
+            &domains_file,
+            r#"[{"domain": "home.example.com", "v6_disabled": true}]"#,
+        )
+        .unwrap();
+        let config = parsed_config(&format!(
+            "cloudflare_token=0123\ninterval=300\nallow_private_ips=true\ntelegram_chat_id=+100\ndomains_path=/old\ndomains_file={}\n",
+            domains_file.display()
+        ));
+        let output_file = directory.path().join("cf-dynamic.toml");
+
+        let migration = config.migrate_to_toml(&output_file).unwrap();
+
+        assert_eq!(
+            migration.migrated,
+            vec![
+                "allow_private_ips",
+                "cloudflare_token",
+                "interval",
+                "telegram_chat_id"
+            ]
+        );
+        assert_eq!(migration.skipped, vec!["domains_path"]);
+        assert_eq!(migration.domains, 1);
+        let contents = std::fs::read_to_string(&output_file).unwrap();
+        assert!(contents.contains("interval = 300"), "{}", contents);
+        assert!(
+            contents.contains("allow_private_ips = true"),
+            "{}",
+            contents
+        );
+
+        let args = Args::parse_from(["cf-dynamic", "-c", output_file.to_str().unwrap()]);
+        let migrated = Config::new(&args).unwrap();
+        assert_eq!(migrated.read_cloudflare_token().unwrap(), "0123");
+        assert_eq!(
+            migrated.read_config_entry("telegram_chat_id").unwrap(),
+            "+100"
+        );
+        assert!(migrated.read_allow_private_ips());
+        let domains = migrated.read_domains().unwrap();
+        assert_eq!(domains.len(), 1);
+        assert_eq!(domains[0].domain, "home.example.com");
+        assert!(domains[0].v6_disabled);
+    }
+
+    #[test]
+    fn keeps_backup_of_config_with_embedded_domains() {
+        use clap::Parser;
+
+        let directory = tempfile::tempdir().unwrap();
+        let config_file = directory.path().join("cf-dynamic.toml");
+        let contents =
+            "cloudflare_token = \"secret\"\n\n[[domains]]\ndomain = \"home.example.com\"\n";
+        std::fs::write(&config_file, contents).unwrap();
+        let args = Args::parse_from(["cf-dynamic", "-c", config_file.to_str().unwrap()]);
+        let config = Config::new(&args).unwrap();
+
+        config
+            .write_domains(&vec![DomainRegistration::new("nas.example.com")])
+            .unwrap();
+
+        let backup = directory.path().join("cf-dynamic.toml.bak");
+        assert_eq!(std::fs::read_to_string(backup).unwrap(), contents);
+        let domains = Config::new(&args).unwrap().read_domains().unwrap();
+        assert_eq!(domains[0].domain, "nas.example.com");
+    }
+
+    #[test]
+    fn sets_toml_entries_with_their_schema_type() {
+        use clap::Parser;
+
+        let directory = tempfile::tempdir().unwrap();
+        let config_file = directory.path().join("cf-dynamic.toml");
+        let args = Args::parse_from(["cf-dynamic", "-c", config_file.to_str().unwrap()]);
+        let config = Config::new(&args).unwrap();
+
+        config.set_config_entry("smtp_password", "0123").unwrap();
+        config.set_config_entry("interval", "300").unwrap();
+
+        let contents = std::fs::read_to_string(&config_file).unwrap();
+        assert!(
+            contents.contains("smtp_password = \"0123\""),
+            "{}",
+            contents
+        );
+        assert!(contents.contains("interval = 300"), "{}", contents);
+        let config = Config::new(&args).unwrap();
+        assert_eq!(config.read_config_entry("smtp_password").unwrap(), "0123");
+    }
+
+    #[test]
+    fn lists_entries_sorted_by_key() {
+        let config = parsed_config("interval=60\ncloudflare_token=secret\nintervall=5\n");
+
+        assert_eq!(
+            config.list_config_entries(),
+            vec![
+                ("cloudflare_token", "secret"),
+                ("interval", "60"),
+                ("intervall", "5")
+            ]
+        );
+        assert_eq!(config.unknown_keys(), vec!["intervall"]);
+        for config_value in config.effective_values() {
+            let known = CONFIG_SCHEMA
+                .iter()
+                .any(|entry| entry.key == config_value.key);
+            assert_eq!(known, config_value.key != "intervall");
+        }
+    }
+
+    #[test]
+    fn reads_max_retries_without_overflow() {
+        let retry_config = parsed_config("max_retries=4294967295\n").read_retry_config();
+        assert_eq!(retry_config.max_attempts, u32::MAX);
+    }
+
+    #[test]
+    fn checks_entries_against_schema() {
+        assert!(parsed_config("cloudflare_token=secret\ninterval=60\n")
+            .check_entries()
+            .is_empty());
+
+        let errors =
+            parsed_config("interval=soon\nmetrics_port=99999\nwebhook_url=nope\n").check_entries();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Missing required config entry cloudflare_token",
+                "Invalid value 'soon' for interval, expected a number",
+                "Invalid value '99999' for metrics_port, expected a port",
+                "Invalid value 'nope' for webhook_url, expected a URL",
+            ]
+        );
+    }
+
+    #[test]
+    fn normalizes_zone_filter() {
+        assert_eq!(parsed_config("").read_zone_filter(), None);
+        assert_eq!(
+            parsed_config("zone_filter=Example.COM.\n").read_zone_filter(),
+            Some("example.com".to_string())
+        );
+
+        let mut config = parsed_config("zone_filter=example.com\n");
+        config.zone_filter = Some("münchen.de".to_string());
+        assert_eq!(
+            config.read_zone_filter(),
+            Some("xn--mnchen-3ya.de".to_string())
+        );
+    }
+
+    #[test]
+    fn reads_token_from_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("cf_token");
+        std::fs::write(&path, "secret-token\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        assert_eq!(
+            resolve_token(&format!("file:{}", path)).unwrap(),
+            "secret-token"
+        );
+        assert_eq!(resolve_token(path).unwrap(), "secret-token");
+        assert_eq!(resolve_token("secret-token").unwrap(), "secret-token");
+        assert!(matches!(
+            resolve_token("file:/nonexistent/cf_token"),
+            Err(ConfigError::TokenFile { .. })
+        ));
+    }
 }
@@ -0,0 +1,81 @@
+use crate::error::DaemonError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+pub const DEFAULT_INTERVAL: u64 = 300;
+
+pub struct Daemon {
+    interval: Duration,
+    pid_file: Option<PathBuf>,
+    running: Arc<AtomicBool>,
+    reload: Arc<AtomicBool>,
+}
+
+impl Daemon {
+    pub fn new(interval: u64, pid_file: Option<PathBuf>) -> Result<Daemon, DaemonError> {
+        let running = Arc::new(AtomicBool::new(true));
+        let reload = Arc::new(AtomicBool::new(false));
+
+        // SIGINT and SIGTERM stop the daemon after the current update has finished
+        let handler_running = running.clone();
+        ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst))
+            .map_err(DaemonError::SignalHandler)?;
+
+        // SIGHUP cuts the current sleep short, so the config is reloaded by the next update
+        #[cfg(unix)]
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, reload.clone())
+            .map_err(DaemonError::Hangup)?;
+
+        if let Some(ref pid_file) = pid_file {
+            write_pid_file(pid_file)?;
+        }
+
+        Ok(Daemon {
+            interval: Duration::from_secs(interval),
+            pid_file,
+            running,
+            reload,
+        })
+    }
+
+    pub fn run<F: FnMut()>(&self, mut update: F) {
+        while self.running.load(Ordering::SeqCst) {
+            update();
+            self.sleep();
+        }
+        info!("Received shutdown signal, stopping daemon");
+    }
+
+    fn sleep(&self) {
+        // Sleep in small steps so that signals are handled promptly
+        let wake_up = Instant::now() + self.interval;
+        while self.running.load(Ordering::SeqCst) && Instant::now() < wake_up {
+            if self.reload.swap(false, Ordering::SeqCst) {
+                info!("Received SIGHUP, reloading config");
+                return;
+            }
+            thread::sleep(Duration::from_millis(250));
+        }
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        if let Some(ref pid_file) = self.pid_file {
+            let _ = fs::remove_file(pid_file);
+        }
+    }
+}
+
+fn write_pid_file(path: &Path) -> Result<(), DaemonError> {
+    fs::write(path, format!("{}\n", process::id())).map_err(|source| DaemonError::PidFile {
+        path: path.to_path_buf(),
+        source,
+    })
+}
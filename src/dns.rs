@@ -0,0 +1,175 @@
+use crate::error::ResolverError;
+use crate::ip_detection::IpVersion;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// Resolver used by `verify` if none is given
+pub const DEFAULT_RESOLVER: &str = "1.1.1.1:53";
+
+const DNS_CLASS_IN: u16 = 1;
+/// Flags of a standard query asking the resolver to recurse
+const DNS_FLAGS_RECURSION_DESIRED: u16 = 0x0100;
+
+fn query_type(ip_version: IpVersion) -> u16 {
+    match ip_version {
+        IpVersion::V4 => 1,
+        IpVersion::V6 => 28,
+    }
+}
+
+pub fn resolve(
+    resolver: &SocketAddr,
+    domain: &str,
+    ip_version: IpVersion,
+    timeout: Duration,
+) -> Result<Vec<IpAddr>, ResolverError> {
+    // Send a single A or AAAA query over UDP and return all addresses of the answer
+    let resolver_error = |source| ResolverError::Io {
+        resolver: *resolver,
+        source,
+    };
+    let local_addr: SocketAddr = match resolver {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local_addr).map_err(resolver_error)?;
+    socket
+        .set_read_timeout(Some(timeout))
+        .map_err(resolver_error)?;
+
+    let id: u16 = rand::random();
+    let query = dns_query(id, domain, ip_version).ok_or_else(|| ResolverError::InvalidName {
+        domain: domain.to_string(),
+    })?;
+    socket.send_to(&query, resolver).map_err(resolver_error)?;
+
+    let mut response = [0u8; 4096];
+    let length = socket.recv(&mut response).map_err(resolver_error)?;
+    parse_dns_response(&response[..length], id, ip_version)
+}
+
+fn dns_query(id: u16, domain: &str, ip_version: IpVersion) -> Option<Vec<u8>> {
+    // Header with one question, followed by the question itself
+    let mut query = Vec::with_capacity(18 + domain.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&DNS_FLAGS_RECURSION_DESIRED.to_be_bytes());
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in domain.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&query_type(ip_version).to_be_bytes());
+    query.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+    Some(query)
+}
+
+fn parse_dns_response(
+    response: &[u8],
+    id: u16,
+    ip_version: IpVersion,
+) -> Result<Vec<IpAddr>, ResolverError> {
+    let read_u16 = |offset: usize| -> Result<u16, ResolverError> {
+        match response.get(offset..offset + 2) {
+            Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+            None => Err(ResolverError::InvalidResponse),
+        }
+    };
+    if read_u16(0)? != id || response[2] & 0x80 == 0 {
+        return Err(ResolverError::InvalidResponse);
+    }
+    let response_code = response[3] & 0x0f;
+    // NXDOMAIN just means that there are no addresses
+    match response_code {
+        0 => {}
+        3 => return Ok(Vec::new()),
+        _ => return Err(ResolverError::ResponseCode(response_code)),
+    }
+
+    let question_count = read_u16(4)?;
+    let answer_count = read_u16(6)?;
+    let mut offset = 12;
+    for _ in 0..question_count {
+        offset = skip_name(response, offset)? + 4;
+    }
+
+    let mut addresses = Vec::new();
+    for _ in 0..answer_count {
+        offset = skip_name(response, offset)?;
+        let record_type = read_u16(offset)?;
+        let data_length = read_u16(offset + 8)? as usize;
+        let data = response
+            .get(offset + 10..offset + 10 + data_length)
+            .ok_or(ResolverError::InvalidResponse)?;
+        // Other records, e.g. the CNAMEs leading to the address, are skipped
+        if record_type == query_type(ip_version) {
+            match ip_version {
+                IpVersion::V4 => <[u8; 4]>::try_from(data).map(IpAddr::from),
+                IpVersion::V6 => <[u8; 16]>::try_from(data).map(IpAddr::from),
+            }
+            .map(|address| addresses.push(address))
+            .map_err(|_| ResolverError::InvalidResponse)?;
+        }
+        offset += 10 + data_length;
+    }
+    Ok(addresses)
+}
+
+fn skip_name(response: &[u8], mut offset: usize) -> Result<usize, ResolverError> {
+    // Names end with an empty label or a pointer to a previous name (two highest bits set)
+    loop {
+        let length = *response.get(offset).ok_or(ResolverError::InvalidResponse)?;
+        match length {
+            0 => return Ok(offset + 1),
+            length if length & 0xc0 == 0xc0 => return Ok(offset + 2),
+            length => offset += 1 + length as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dns_response(id: u16, answers: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut response = dns_query(id, "example.com", IpVersion::V4).unwrap();
+        response[2] |= 0x80;
+        response[7] = answers.len() as u8;
+        for (record_type, data) in answers {
+            // Pointer to the name of the question
+            response.extend_from_slice(&[0xc0, 12]);
+            response.extend_from_slice(&record_type.to_be_bytes());
+            response.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+            response.extend_from_slice(&300u32.to_be_bytes());
+            response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            response.extend_from_slice(data);
+        }
+        response
+    }
+
+    #[test]
+    fn encodes_query() {
+        let query = dns_query(0x1234, "example.com", IpVersion::V6).unwrap();
+        assert_eq!(&query[..4], &[0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(&query[12..25], b"\x07example\x03com\x00");
+        assert_eq!(&query[25..], &[0, 28, 0, 1]);
+    }
+
+    #[test]
+    fn parses_addresses_of_answer() {
+        let response = dns_response(7, &[(5, b"\x03www\xc0\x0c"), (1, &[192, 0, 2, 1])]);
+        assert_eq!(
+            parse_dns_response(&response, 7, IpVersion::V4).unwrap(),
+            vec![IpAddr::from([192, 0, 2, 1])]
+        );
+    }
+
+    #[test]
+    fn rejects_response_with_other_id() {
+        let response = dns_response(7, &[(1, &[192, 0, 2, 1])]);
+        assert!(parse_dns_response(&response, 8, IpVersion::V4).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+use crate::cloudflare::CloudflareApiError;
+use reqwest::StatusCode;
+use std::error::Error as StdError;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Error of any command, printed with its causes by `main`
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Cloudflare(#[from] CloudflareError),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    IpDetection(#[from] IpDetectionError),
+    #[error(transparent)]
+    Daemon(#[from] DaemonError),
+    #[error(transparent)]
+    Resolver(#[from] ResolverError),
+    #[error(transparent)]
+    Lock(#[from] LockError),
+    #[error(transparent)]
+    Metrics(#[from] MetricsError),
+    #[error(transparent)]
+    History(#[from] HistoryError),
+    #[error("Domain '{0}' is not registered")]
+    DomainNotRegistered(String),
+    #[error("Domain '{0}' is already registered")]
+    DomainAlreadyRegistered(String),
+    #[error("Invalid domain '{domain}': {reason}")]
+    InvalidDomain { domain: String, reason: String },
+    #[error("Invalid record types of '{domain}': {reason}")]
+    InvalidRecordTypes { domain: String, reason: String },
+    #[error("'{content}' is not a valid {record_type} record content")]
+    InvalidRecordContent {
+        content: String,
+        record_type: String,
+    },
+    #[error("{name} does not belong to zone {zone}")]
+    RecordOutsideZone { name: String, zone: String },
+    #[error("{record_type} record of {name} already exists, change it with cloud update or add another one with --allow-duplicate")]
+    RecordExists { name: String, record_type: String },
+    #[error("{name} has {count} {record_type} records, pick one with its content")]
+    AmbiguousRecord {
+        name: String,
+        record_type: String,
+        count: usize,
+    },
+    #[error("Failed to login")]
+    LoginFailed(#[source] CloudflareError),
+    #[error("{0} checks failed")]
+    ChecksFailed(usize),
+    #[error("{0} records have not propagated")]
+    NotPropagated(usize),
+    #[error("{0} records would be changed by an update")]
+    ChangesPending(usize),
+    #[error("{0} config entries are invalid")]
+    InvalidConfig(usize),
+    #[error("{0} test notifications failed")]
+    NotificationsFailed(usize),
+    #[error("{0:?} already exists, migrate never overwrites a file")]
+    MigrationTargetExists(PathBuf),
+    #[error("{0:?} is not read as TOML config, its name has to end with .toml")]
+    MigrationTargetNotToml(PathBuf),
+    #[error("Minimum TTL {min} is higher than maximum TTL {max}")]
+    InvalidTtlRange { min: u32, max: u32 },
+    #[error("Unable to read answer")]
+    Prompt(#[source] io::Error),
+    #[error("Unable to write completions to {path:?}")]
+    Completions {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error(
+        "Installing completions for {0} is not supported, redirect the printed script instead"
+    )]
+    CompletionsNotInstallable(String),
+    #[error("HOME is not set")]
+    NoHomeDirectory,
+}
+
+#[derive(Debug, Error)]
+pub enum CloudflareError {
+    #[error("Cloudflare API returned errors: {}", errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", "))]
+    ApiError { errors: Vec<CloudflareApiError> },
+    #[error("HTTP request to Cloudflare API failed")]
+    HttpError(#[source] reqwest::Error),
+    #[error("Unable to build HTTP client")]
+    Client(#[source] reqwest::Error),
+    #[error("Error in {method} request to Cloudflare API: {status}")]
+    StatusError { method: String, status: StatusCode },
+    #[error("Unable to parse Cloudflare API response")]
+    ParseError(#[source] serde_json::Error),
+    #[error("Cloudflare API response to {method} request has no result")]
+    MissingResult { method: String },
+    #[error("Unable to find {record_type} record for {domain}")]
+    RecordNotFound { domain: String, record_type: String },
+    #[error("Unable to find zone for {domain}")]
+    ZoneNotFound { domain: String },
+    #[error("Cloudflare API did not update the {record_type} record for {domain}")]
+    RecordNotUpdated { domain: String, record_type: String },
+    #[error("Unsupported record type '{record_type}', expected A, AAAA or CNAME")]
+    UnsupportedRecordType { record_type: String },
+    #[error("Rate limited by Cloudflare API, retry after {retry_after} seconds")]
+    RateLimited { retry_after: u64 },
+    #[error("Unable to access cache file {path:?}")]
+    CacheIo {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Unable to parse cache file {path:?}")]
+    CacheParse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Unable to access {path:?}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Unable to parse {path:?}")]
+    Json {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Unable to parse {path:?}")]
+    Toml {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+    #[error("Unable to serialize config")]
+    TomlSerialize(#[source] toml::ser::Error),
+    #[error("Unable to serialize domains")]
+    JsonSerialize(#[source] serde_json::Error),
+    #[error("Unable to write temporary file in {path:?}")]
+    TempFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Unable to back up to {path:?}")]
+    Backup {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Unable to replace {path:?}")]
+    Rename {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Config file is not valid (Line {line_number}: {line})")]
+    InvalidLine { line_number: usize, line: String },
+    #[error("No Cloudflare Token found")]
+    MissingToken,
+    #[error("Unable to read Cloudflare token from stdin")]
+    TokenStdin(#[source] io::Error),
+    #[error("Unable to read Cloudflare token from {path:?}")]
+    TokenFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Missing required config entry {0}")]
+    MissingEntry(&'static str),
+    #[error("Invalid value '{value}' for {key}, expected {expected}")]
+    InvalidValue {
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+    #[error("Directory of {path:?} does not exist")]
+    MissingDirectory { path: PathBuf },
+    #[error(transparent)]
+    BindInterface(#[from] IpError),
+}
+
+#[derive(Debug, Error)]
+pub enum IpDetectionError {
+    #[error("Invalid ip source '{0}', expected interface:<name>")]
+    InvalidSource(String),
+    #[error("Invalid prefix source '{0}', expected pd:<interface>")]
+    InvalidPrefixSource(String),
+    #[error("Unable to list network interfaces")]
+    ListInterfaces(#[source] io::Error),
+    #[error("Interface {0} not found")]
+    InterfaceNotFound(String),
+    #[error("Interface {interface} has no global {version} address")]
+    NoGlobalAddress {
+        interface: String,
+        version: &'static str,
+    },
+    #[error("Unable to build HTTP client")]
+    Client(#[source] reqwest::Error),
+    #[error(transparent)]
+    BindInterface(#[from] IpError),
+    #[error("Request to ip detection service failed")]
+    Http(#[source] reqwest::Error),
+    #[error("Unexpected response '{0}'")]
+    UnexpectedResponse(String),
+    #[error("STUN request to {server} failed")]
+    Stun {
+        server: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("STUN server {server} has no {version} address")]
+    StunUnreachable {
+        server: String,
+        version: &'static str,
+    },
+    #[error("Invalid STUN response from {0}")]
+    InvalidStunResponse(String),
+    #[error("Unable to detect {version} address, all providers failed: {}", errors.join(", "))]
+    AllProvidersFailed {
+        version: &'static str,
+        errors: Vec<String>,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum IpError {
+    #[error("'{0}' is not a valid ipv4 address")]
+    InvalidIpv4(String),
+    #[error("'{0}' is not a valid ipv6 address")]
+    InvalidIpv6(String),
+    #[error("Suffix '{0}' has more parts than the address")]
+    SuffixTooLong(String),
+    #[error("'{0}' is not a valid octet, expected 0-255")]
+    InvalidOctet(String),
+    #[error("'{0}' is not a valid group, expected 0-ffff")]
+    InvalidGroup(String),
+    #[error("'{0}' is not a valid MAC address, expected six hex octets like 00:11:22:33:44:55")]
+    InvalidMac(String),
+    #[error("Unable to read the addresses of the network interfaces")]
+    ReadInterfaceAddresses(#[source] io::Error),
+    #[error("Interface {0} has no delegated IPv6 prefix")]
+    NoDelegatedPrefix(String),
+    #[error("A delegated prefix needs a v6_suffix as host part")]
+    NoHostSuffix,
+    #[error("{ip} is not a global {version} address (use --allow-private-ips to send it anyway)")]
+    NotGlobal { ip: String, version: &'static str },
+    #[error("Unable to list network interfaces")]
+    ListInterfaces(#[source] io::Error),
+    #[error("Interface {0} not found")]
+    InterfaceNotFound(String),
+    #[error("Interface {interface} has no {version} address to bind to")]
+    NoInterfaceAddress {
+        interface: String,
+        version: &'static str,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum TimestampError {
+    #[error("'{0}' is neither a unix timestamp nor an RFC 3339 time like 2024-01-01T00:00:00Z")]
+    Invalid(String),
+}
+
+#[derive(Debug, Error)]
+pub enum DaemonError {
+    #[error("Unable to register signal handler")]
+    SignalHandler(#[source] ctrlc::Error),
+    #[error("Unable to register SIGHUP handler")]
+    Hangup(#[source] io::Error),
+    #[error("Unable to write pid file {path:?}")]
+    PidFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum ResolverError {
+    #[error("DNS query to {resolver} failed")]
+    Io {
+        resolver: SocketAddr,
+        #[source]
+        source: io::Error,
+    },
+    #[error("'{domain}' can not be queried")]
+    InvalidName { domain: String },
+    #[error("Invalid DNS response")]
+    InvalidResponse,
+    #[error("DNS query failed with response code {0}")]
+    ResponseCode(u8),
+}
+
+#[derive(Debug, Error)]
+pub enum LockError {
+    #[error("Unable to lock {path:?}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{path:?} is locked by another process{}", pid.map(|pid| format!(" (pid {})", pid)).unwrap_or_default())]
+    Held { path: PathBuf, pid: Option<u32> },
+}
+
+#[derive(Debug, Error)]
+pub enum HistoryError {
+    #[error("Unable to access history file {path:?}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum NotificationError {
+    #[error("Unable to build HTTP client")]
+    Client(#[source] reqwest::Error),
+    #[error("Request to webhook failed")]
+    Http(#[source] reqwest::Error),
+    #[error("Request to Telegram failed")]
+    Telegram(#[source] reqwest::Error),
+    #[error("Webhook {url} responded with {status}")]
+    Status { url: String, status: StatusCode },
+    #[error("Telegram responded with {0}")]
+    TelegramStatus(StatusCode),
+    #[error("Invalid email address {address}")]
+    Address {
+        address: String,
+        #[source]
+        source: lettre::address::AddressError,
+    },
+    #[error("Unable to build email")]
+    Email(#[source] lettre::error::Error),
+    #[error("Unable to write to {path:?}")]
+    LogFile {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{} notifications failed: {}", .0.len(), .0.iter().map(|e| display_chain(e)).collect::<Vec<_>>().join(", "))]
+    Several(Vec<NotificationError>),
+    #[error("Unable to send email via {host}")]
+    Smtp {
+        host: String,
+        #[source]
+        source: lettre::transport::smtp::Error,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum MetricsError {
+    #[error("Unable to serve metrics on port {port}")]
+    Bind {
+        port: u16,
+        #[source]
+        source: Box<dyn StdError + Send + Sync>,
+    },
+}
+
+pub fn display_chain(error: &dyn StdError) -> String {
+    // Join the error with all its causes, e.g. "Unable to parse file: expected value at line 1"
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(cause.to_string().as_str());
+        source = cause.source();
+    }
+    message
+}
@@ -0,0 +1,267 @@
+use crate::error::HistoryError;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, to_string};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use tracing::warn;
+
+/// One change of a DNS record, stored as a line of the history file
+///
+/// The history file is JSON Lines, one object per line with the fields below, `old_ip` is null
+/// if there was none. Lines are only ever appended and fields only ever added, so other tools
+/// can rely on this format.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub timestamp: u64,
+    pub domain: String,
+    pub record_type: String,
+    /// Ip the record had before, none for created records and failed updates
+    pub old_ip: Option<String>,
+    pub new_ip: String,
+    pub success: bool,
+}
+
+pub fn append_history(path: &Path, entries: &[HistoryEntry]) -> Result<(), HistoryError> {
+    // One json object per line, so the file is only ever appended to
+    let io_error = |source| HistoryError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut lines = String::new();
+    for entry in entries.iter() {
+        lines.push_str(&to_string(entry).expect("Unable to serialize history entry"));
+        lines.push('\n');
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(lines.as_bytes()))
+        .map_err(io_error)
+}
+
+/// Which entries of the history are read
+#[derive(Default)]
+pub struct HistoryFilter {
+    pub domain: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    /// Only keep the latest this many matching entries
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.domain
+            .as_ref()
+            .is_none_or(|domain| entry.domain == *domain)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp <= until)
+    }
+}
+
+pub fn read_history(
+    path: &Path,
+    filter: &HistoryFilter,
+) -> Result<Vec<HistoryEntry>, HistoryError> {
+    // Without any update there is no file yet, broken lines (e.g. of an interrupted write) are skipped.
+    // The file is read line by line and only matching entries are kept, logs of years stay cheap
+    let io_error = |source| HistoryError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(source) => return Err(io_error(source)),
+    };
+
+    let mut entries = VecDeque::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(io_error)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: HistoryEntry = match from_str(&line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Skipping line {} of {:?}: {}", index + 1, path, e);
+                continue;
+            }
+        };
+        if !filter.matches(&entry) {
+            continue;
+        }
+        entries.push_back(entry);
+        if filter.limit.is_some_and(|limit| entries.len() > limit) {
+            entries.pop_front();
+        }
+    }
+    Ok(entries.into())
+}
+
+/// Summary of the history of one domain
+#[derive(Debug, PartialEq)]
+pub struct DomainStats {
+    pub domain: String,
+    pub updates: usize,
+    pub successful: usize,
+    /// Average seconds between successful changes, none without at least two
+    pub average_interval: Option<u64>,
+    /// Latest ip of each record type, A before AAAA
+    pub current_ips: Vec<String>,
+    pub last_update: Option<u64>,
+}
+
+impl DomainStats {
+    pub fn failure_rate(&self) -> Option<f64> {
+        match self.updates {
+            0 => None,
+            updates => Some((updates - self.successful) as f64 / updates as f64),
+        }
+    }
+}
+
+pub fn domain_stats(entries: &[HistoryEntry], domain: &str) -> DomainStats {
+    let entries: Vec<&HistoryEntry> = entries.iter().filter(|e| e.domain == domain).collect();
+    let mut changes: Vec<u64> = entries
+        .iter()
+        .filter(|e| e.success)
+        .map(|e| e.timestamp)
+        .collect();
+    changes.sort();
+    let average_interval = match (changes.first(), changes.last()) {
+        (Some(first), Some(last)) if changes.len() > 1 => {
+            Some((last - first) / (changes.len() as u64 - 1))
+        }
+        _ => None,
+    };
+
+    // The file is in order of the updates, so the last successful entry has the current ip
+    let mut record_types: Vec<&str> = entries.iter().map(|e| e.record_type.as_str()).collect();
+    record_types.sort();
+    record_types.dedup();
+    let current_ips = record_types
+        .into_iter()
+        .filter_map(|record_type| {
+            entries
+                .iter()
+                .rev()
+                .find(|e| e.success && e.record_type == record_type)
+                .map(|e| e.new_ip.clone())
+        })
+        .collect();
+
+    DomainStats {
+        domain: domain.to_string(),
+        updates: entries.len(),
+        successful: changes.len(),
+        average_interval,
+        current_ips,
+        last_update: entries.iter().map(|e| e.timestamp).max(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: u64, domain: &str) -> HistoryEntry {
+        HistoryEntry {
+            timestamp,
+            domain: domain.to_string(),
+            record_type: "A".to_string(),
+            old_ip: Some("192.0.2.1".to_string()),
+            new_ip: "192.0.2.2".to_string(),
+            success: true,
+        }
+    }
+
+    #[test]
+    fn appends_and_reads_entries() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("cf-dynamic.log.jsonl");
+        assert!(read_history(&path, &HistoryFilter::default())
+            .unwrap()
+            .is_empty());
+
+        append_history(&path, &[entry(1, "a.example.com")]).unwrap();
+        append_history(&path, &[entry(2, "b.example.com")]).unwrap();
+        assert_eq!(
+            read_history(&path, &HistoryFilter::default()).unwrap(),
+            vec![entry(1, "a.example.com"), entry(2, "b.example.com")]
+        );
+    }
+
+    #[test]
+    fn summarizes_domain_history() {
+        let mut failed = entry(7, "a.example.com");
+        failed.success = false;
+        let mut v6 = entry(4, "a.example.com");
+        v6.record_type = "AAAA".to_string();
+        v6.new_ip = "2001:db8::2".to_string();
+        let entries = vec![
+            entry(1, "a.example.com"),
+            entry(2, "b.example.com"),
+            v6,
+            entry(5, "a.example.com"),
+            failed,
+        ];
+
+        let stats = domain_stats(&entries, "a.example.com");
+        assert_eq!(
+            stats,
+            DomainStats {
+                domain: "a.example.com".to_string(),
+                updates: 4,
+                successful: 3,
+                average_interval: Some(2),
+                current_ips: vec!["192.0.2.2".to_string(), "2001:db8::2".to_string()],
+                last_update: Some(7),
+            }
+        );
+        assert_eq!(stats.failure_rate(), Some(0.25));
+        assert_eq!(domain_stats(&entries, "c.example.com").failure_rate(), None);
+    }
+
+    #[test]
+    fn filters_by_domain_time_and_limit() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("cf-dynamic.log.jsonl");
+        append_history(
+            &path,
+            &[
+                entry(1, "a.example.com"),
+                entry(2, "b.example.com"),
+                entry(3, "a.example.com"),
+                entry(4, "a.example.com"),
+            ],
+        )
+        .unwrap();
+        let read = |filter: HistoryFilter| read_history(&path, &filter).unwrap();
+
+        assert_eq!(
+            read(HistoryFilter {
+                domain: Some("a.example.com".to_string()),
+                limit: Some(2),
+                ..HistoryFilter::default()
+            }),
+            vec![entry(3, "a.example.com"), entry(4, "a.example.com")]
+        );
+        assert_eq!(
+            read(HistoryFilter {
+                since: Some(2),
+                until: Some(3),
+                ..HistoryFilter::default()
+            }),
+            vec![entry(2, "b.example.com"), entry(3, "a.example.com")]
+        );
+        assert!(read(HistoryFilter {
+            limit: Some(0),
+            ..HistoryFilter::default()
+        })
+        .is_empty());
+    }
+}
@@ -0,0 +1,528 @@
+use crate::error::{display_chain, IpDetectionError, IpError};
+use crate::proxy::ProxyConfig;
+use reqwest::blocking::Client;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::warn;
+
+/// STUN server used if none is configured
+pub const DEFAULT_STUN_SERVER: &str = "stun.l.google.com:19302";
+
+/// Magic cookie of every STUN message (RFC 5389)
+const STUN_MAGIC_COOKIE: u32 = 0x2112_A442;
+const STUN_BINDING_REQUEST: u16 = 0x0001;
+const STUN_BINDING_RESPONSE: u16 = 0x0101;
+const STUN_MAPPED_ADDRESS: u16 = 0x0001;
+const STUN_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+/// Binding requests are sent this many times, as UDP packets might get lost
+const STUN_ATTEMPTS: usize = 3;
+
+/// Addresses of all interfaces with prefix length, scope and flags, as listed by Linux
+const IF_INET6_PATH: &str = "/proc/net/if_inet6";
+/// Flag of addresses whose prefix is no longer valid, e.g. after the prefix changed
+const IFA_F_DEPRECATED: u32 = 0x20;
+
+static DEFAULT_V4_PROVIDERS: &[&str] = &[
+    "https://{version}.icanhazip.com",
+    "https://api4.my-ip.io/ip",
+    "https://api4.ipify.org",
+];
+
+static DEFAULT_V6_PROVIDERS: &[&str] = &[
+    "https://{version}.icanhazip.com",
+    "https://api6.my-ip.io/ip",
+    "https://api6.ipify.org",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+impl IpVersion {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IpVersion::V4 => "ipv4",
+            IpVersion::V6 => "ipv6",
+        }
+    }
+
+    pub fn record_type(&self) -> &'static str {
+        match self {
+            IpVersion::V4 => "A",
+            IpVersion::V6 => "AAAA",
+        }
+    }
+
+    pub fn from_record_type(record_type: &str) -> Option<IpVersion> {
+        [IpVersion::V4, IpVersion::V6]
+            .into_iter()
+            .find(|ip_version| ip_version.record_type() == record_type)
+    }
+
+    pub fn default_providers(&self) -> Vec<String> {
+        let providers = match self {
+            IpVersion::V4 => DEFAULT_V4_PROVIDERS,
+            IpVersion::V6 => DEFAULT_V6_PROVIDERS,
+        };
+        providers.iter().map(|p| p.to_string()).collect()
+    }
+
+    fn matches(&self, ip: &IpAddr) -> bool {
+        match self {
+            IpVersion::V4 => ip.is_ipv4(),
+            IpVersion::V6 => ip.is_ipv6(),
+        }
+    }
+}
+
+/// Where the IP address of a DNS record is taken from
+#[derive(PartialEq, Eq)]
+pub enum IpSource {
+    /// Ask the configured IP detection services
+    Service,
+    /// Read the address of a local network interface (`interface:<name>`)
+    Interface(String),
+}
+
+impl IpSource {
+    pub fn parse(source: &Option<String>) -> Result<IpSource, IpDetectionError> {
+        let source = match source {
+            Some(source) => source,
+            None => return Ok(IpSource::Service),
+        };
+        match source.split_once(':') {
+            Some(("interface", iface)) if !iface.is_empty() => {
+                Ok(IpSource::Interface(iface.to_string()))
+            }
+            _ => Err(IpDetectionError::InvalidSource(source.clone())),
+        }
+    }
+}
+
+pub fn get_ip_from_interface(
+    iface: &str,
+    ip_version: IpVersion,
+) -> Result<String, IpDetectionError> {
+    // Return the first global address of the requested version assigned to the interface
+    let interfaces = if_addrs::get_if_addrs().map_err(IpDetectionError::ListInterfaces)?;
+
+    let mut found_interface = false;
+    for interface in interfaces.iter().filter(|i| i.name == iface) {
+        found_interface = true;
+        let ip = interface.ip();
+        if ip_version.matches(&ip) && is_global(&ip) {
+            return Ok(ip.to_string());
+        }
+    }
+
+    match found_interface {
+        true => Err(IpDetectionError::NoGlobalAddress {
+            interface: iface.to_string(),
+            version: ip_version.name(),
+        }),
+        false => Err(IpDetectionError::InterfaceNotFound(iface.to_string())),
+    }
+}
+
+pub fn interface_address(name: &str, ip_version: IpVersion) -> Result<IpAddr, IpError> {
+    // Link local ipv6 addresses are skipped, binding to them needs the scope id of the interface
+    let interfaces = if_addrs::get_if_addrs().map_err(IpError::ListInterfaces)?;
+
+    let mut found_interface = false;
+    for interface in interfaces.iter().filter(|i| i.name == name) {
+        found_interface = true;
+        let ip = interface.ip();
+        if ip_version.matches(&ip) && !is_link_local_v6(&ip) {
+            return Ok(ip);
+        }
+    }
+
+    match found_interface {
+        true => Err(IpError::NoInterfaceAddress {
+            interface: name.to_string(),
+            version: ip_version.name(),
+        }),
+        false => Err(IpError::InterfaceNotFound(name.to_string())),
+    }
+}
+
+fn is_link_local_v6(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(_) => false,
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+pub fn prefix_source_interface(source: &str) -> Result<&str, IpDetectionError> {
+    match source.split_once(':') {
+        Some(("pd", iface)) if !iface.is_empty() => Ok(iface),
+        _ => Err(IpDetectionError::InvalidPrefixSource(source.to_string())),
+    }
+}
+
+pub fn get_delegated_prefix(iface: &str) -> Result<Ipv6Addr, IpError> {
+    // The prefix of the first global address the router announced to the interface
+    let addresses = fs::read_to_string(IF_INET6_PATH).map_err(IpError::ReadInterfaceAddresses)?;
+    parse_if_inet6(&addresses, iface).ok_or_else(|| IpError::NoDelegatedPrefix(iface.to_string()))
+}
+
+fn parse_if_inet6(addresses: &str, iface: &str) -> Option<Ipv6Addr> {
+    // Lines look like "20010db8000100020000000000000001 02 40 00 80 eth0"
+    addresses.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [address, _, prefix_length, scope, flags, name] = fields[..] else {
+            return None;
+        };
+        let address = Ipv6Addr::from(u128::from_str_radix(address, 16).ok()?);
+        let prefix_length = u32::from_str_radix(prefix_length, 16).ok()?;
+        let flags = u32::from_str_radix(flags, 16).ok()?;
+        if name != iface
+            || scope != "00"
+            || flags & IFA_F_DEPRECATED != 0
+            || prefix_length > 128
+            || !is_global_v6(&address)
+        {
+            return None;
+        }
+        let mask = u128::MAX.checked_shl(128 - prefix_length).unwrap_or(0);
+        Some(Ipv6Addr::from(u128::from(address) & mask))
+    })
+}
+
+pub fn validate_global_ip(ip: &str, ip_version: IpVersion) -> Result<(), IpError> {
+    match ip.parse::<IpAddr>() {
+        Ok(address) if is_global(&address) => Ok(()),
+        _ => Err(IpError::NotGlobal {
+            ip: ip.to_string(),
+            version: ip_version.name(),
+        }),
+    }
+}
+
+pub fn is_global(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_global_v4(ip),
+        IpAddr::V6(ip) => is_global_v6(ip),
+    }
+}
+
+fn is_global_v4(ip: &Ipv4Addr) -> bool {
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_unspecified()
+        || ip.is_documentation()
+        // Shared address space for carrier-grade NAT (100.64.0.0/10)
+        || (ip.octets()[0] == 100 && (ip.octets()[1] & 0b1100_0000) == 0b0100_0000))
+}
+
+fn is_global_v6(ip: &Ipv6Addr) -> bool {
+    let first_segment = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local addresses (fc00::/7)
+        || (first_segment & 0xfe00) == 0xfc00
+        // Link local addresses (fe80::/10)
+        || (first_segment & 0xffc0) == 0xfe80)
+}
+
+pub fn ip_detection_client(
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    insecure: &bool,
+    proxy: &ProxyConfig,
+    local_address: Option<IpAddr>,
+) -> Result<Client, IpDetectionError> {
+    let client = Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(read_timeout)
+        .danger_accept_invalid_certs(*insecure)
+        .local_address(local_address);
+    match proxy.proxy() {
+        Some(proxy) => client.proxy(proxy),
+        None => client.no_proxy(),
+    }
+    .build()
+    .map_err(IpDetectionError::Client)
+}
+
+pub fn get_ip(
+    client: &Client,
+    ip_version: IpVersion,
+    providers: &[String],
+    insecure: &bool,
+) -> Result<String, IpDetectionError> {
+    // Get the public ip address of the machine from the first provider that answers with a valid address
+    let mut errors: Vec<String> = Vec::new();
+    for provider in providers.iter() {
+        let url = provider.replace("{version}", ip_version.name());
+        if *insecure {
+            warn!("Not verifying the TLS certificate of {}", url);
+        }
+        match get_ip_from_provider(client, &url, ip_version) {
+            Ok(ip) => return Ok(ip),
+            Err(e) => errors.push(format!("{} ({})", url, display_chain(&e))),
+        }
+    }
+
+    Err(IpDetectionError::AllProvidersFailed {
+        version: ip_version.name(),
+        errors,
+    })
+}
+
+fn get_ip_from_provider(
+    client: &Client,
+    url: &str,
+    ip_version: IpVersion,
+) -> Result<String, IpDetectionError> {
+    let response = client
+        .get(url)
+        .send()
+        .and_then(|res| res.error_for_status())
+        .and_then(|res| res.text())
+        .map_err(IpDetectionError::Http)?;
+
+    let ip = response.trim();
+    match IpAddr::from_str(ip) {
+        Ok(addr) if ip_version.matches(&addr) => Ok(ip.to_string()),
+        _ => Err(IpDetectionError::UnexpectedResponse(ip.to_string())),
+    }
+}
+
+pub fn get_ip_via_stun(
+    server: &str,
+    ip_version: IpVersion,
+    local_address: Option<IpAddr>,
+) -> Result<String, IpDetectionError> {
+    // Ask the STUN server for the address our binding request was sent from
+    let stun_error = |source| IpDetectionError::Stun {
+        server: server.to_string(),
+        source,
+    };
+    let server_addr = server
+        .to_socket_addrs()
+        .map_err(stun_error)?
+        .find(|addr| ip_version.matches(&addr.ip()))
+        .ok_or_else(|| IpDetectionError::StunUnreachable {
+            server: server.to_string(),
+            version: ip_version.name(),
+        })?;
+
+    let local_addr: SocketAddr = match (local_address, ip_version) {
+        (Some(local_address), _) => (local_address, 0).into(),
+        (None, IpVersion::V4) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        (None, IpVersion::V6) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local_addr).map_err(stun_error)?;
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .map_err(stun_error)?;
+
+    let transaction_id: [u8; 12] = rand::random();
+    let request = stun_binding_request(&transaction_id);
+    let mut response = [0u8; 512];
+    let mut last_error = None;
+    for _ in 0..STUN_ATTEMPTS {
+        socket.send_to(&request, server_addr).map_err(stun_error)?;
+        match socket.recv(&mut response) {
+            Ok(length) => {
+                return match parse_stun_response(&response[..length], &transaction_id) {
+                    Some(ip) if ip_version.matches(&ip) => Ok(ip.to_string()),
+                    _ => Err(IpDetectionError::InvalidStunResponse(server.to_string())),
+                };
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(stun_error(last_error.unwrap()))
+}
+
+fn stun_binding_request(transaction_id: &[u8; 12]) -> Vec<u8> {
+    // Header only: message type, message length 0, magic cookie and transaction id
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&STUN_BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(transaction_id);
+    request
+}
+
+fn parse_stun_response(response: &[u8], transaction_id: &[u8; 12]) -> Option<IpAddr> {
+    // Prefer the XOR-MAPPED-ADDRESS, old servers only send a MAPPED-ADDRESS
+    if response.len() < 20
+        || u16::from_be_bytes([response[0], response[1]]) != STUN_BINDING_RESPONSE
+        || response[4..8] != STUN_MAGIC_COOKIE.to_be_bytes()
+        || response[8..20] != transaction_id[..]
+    {
+        return None;
+    }
+    let length = u16::from_be_bytes([response[2], response[3]]) as usize;
+    let attributes = response.get(20..20 + length)?;
+
+    let mut mapped_address = None;
+    let mut offset = 0;
+    while offset + 4 <= attributes.len() {
+        let attribute_type = u16::from_be_bytes([attributes[offset], attributes[offset + 1]]);
+        let attribute_length =
+            u16::from_be_bytes([attributes[offset + 2], attributes[offset + 3]]) as usize;
+        let value = attributes.get(offset + 4..offset + 4 + attribute_length)?;
+        match attribute_type {
+            STUN_XOR_MAPPED_ADDRESS => return parse_stun_address(value, Some(&response[4..20])),
+            STUN_MAPPED_ADDRESS => mapped_address = parse_stun_address(value, None),
+            _ => {}
+        }
+        // Attributes are padded to a multiple of 4 bytes
+        offset += 4 + attribute_length.div_ceil(4) * 4;
+    }
+    mapped_address
+}
+
+fn parse_stun_address(value: &[u8], xor_key: Option<&[u8]>) -> Option<IpAddr> {
+    // Family 1 is ipv4 and 2 is ipv6, the XOR key is the magic cookie followed by the transaction id
+    let family = *value.get(1)?;
+    let length = match family {
+        1 => 4,
+        2 => 16,
+        _ => return None,
+    };
+    let mut address: Vec<u8> = value.get(4..4 + length)?.to_vec();
+    if let Some(xor_key) = xor_key {
+        address
+            .iter_mut()
+            .zip(xor_key.iter())
+            .for_each(|(byte, key)| *byte ^= key);
+    }
+    match family {
+        1 => Some(IpAddr::from(<[u8; 4]>::try_from(address).ok()?)),
+        _ => Some(IpAddr::from(<[u8; 16]>::try_from(address).ok()?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IF_INET6: &str = "\
+00000000000000000000000000000001 01 80 10 80       lo
+fe80000000000000021122fffe334455 02 40 20 80     eth0
+fd00000000000000021122fffe334455 02 40 00 00     eth0
+20010db8000100010000000000000001 02 40 00 20     eth0
+20010db8000200030000000000000001 02 40 00 00     eth0
+20010db8000900000000000000000001 03 38 00 00     eth1
+";
+
+    #[test]
+    fn reads_prefix_of_global_address() {
+        assert_eq!(
+            parse_if_inet6(IF_INET6, "eth0"),
+            Some("2001:db8:2:3::".parse().unwrap())
+        );
+        assert_eq!(
+            parse_if_inet6(IF_INET6, "eth1"),
+            Some("2001:db8:9::".parse().unwrap())
+        );
+        assert_eq!(parse_if_inet6(IF_INET6, "lo"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn finds_address_to_bind_to() {
+        assert_eq!(
+            interface_address("lo", IpVersion::V4).unwrap(),
+            IpAddr::from([127, 0, 0, 1])
+        );
+        assert!(matches!(
+            interface_address("cf-dynamic0", IpVersion::V4),
+            Err(IpError::InterfaceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn skips_link_local_ipv6_addresses() {
+        assert!(is_link_local_v6(&"fe80::1".parse().unwrap()));
+        assert!(!is_link_local_v6(&"2001:db8::1".parse().unwrap()));
+        assert!(!is_link_local_v6(&"169.254.1.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn parses_prefix_source() {
+        assert_eq!(prefix_source_interface("pd:eth0").unwrap(), "eth0");
+        assert!(prefix_source_interface("interface:eth0").is_err());
+        assert!(prefix_source_interface("pd:").is_err());
+    }
+
+    #[test]
+    fn rejects_addresses_that_are_not_global() {
+        for ip in ["10.1.2.3", "192.168.0.1", "127.0.0.1", "169.254.1.1"] {
+            assert!(validate_global_ip(ip, IpVersion::V4).is_err(), "{}", ip);
+        }
+        for ip in ["fe80::1", "fd00::1", "::1", "ff02::1"] {
+            assert!(validate_global_ip(ip, IpVersion::V6).is_err(), "{}", ip);
+        }
+        assert!(validate_global_ip("8.8.8.8", IpVersion::V4).is_ok());
+        assert!(validate_global_ip("2606:4700::1111", IpVersion::V6).is_ok());
+    }
+
+    const TRANSACTION_ID: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+    fn stun_response(attribute_type: u16, value: &[u8]) -> Vec<u8> {
+        let mut response = Vec::new();
+        response.extend_from_slice(&STUN_BINDING_RESPONSE.to_be_bytes());
+        response.extend_from_slice(&((value.len() + 4) as u16).to_be_bytes());
+        response.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+        response.extend_from_slice(&TRANSACTION_ID);
+        response.extend_from_slice(&attribute_type.to_be_bytes());
+        response.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        response.extend_from_slice(value);
+        response
+    }
+
+    #[test]
+    fn parses_xor_mapped_ipv4_address() {
+        // 203.0.113.7 xor 0x2112A442
+        let value = [0, 1, 0, 0, 203 ^ 0x21, 0x12, 113 ^ 0xA4, 7 ^ 0x42];
+        let response = stun_response(STUN_XOR_MAPPED_ADDRESS, &value);
+        assert_eq!(
+            parse_stun_response(&response, &TRANSACTION_ID),
+            Some(IpAddr::from([203, 0, 113, 7]))
+        );
+    }
+
+    #[test]
+    fn parses_xor_mapped_ipv6_address() {
+        let ip: [u8; 16] = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets();
+        let mut xor_key = STUN_MAGIC_COOKIE.to_be_bytes().to_vec();
+        xor_key.extend_from_slice(&TRANSACTION_ID);
+        let mut value = vec![0, 2, 0, 0];
+        value.extend(ip.iter().zip(xor_key.iter()).map(|(byte, key)| byte ^ key));
+        let response = stun_response(STUN_XOR_MAPPED_ADDRESS, &value);
+        assert_eq!(
+            parse_stun_response(&response, &TRANSACTION_ID),
+            Some(IpAddr::from(ip))
+        );
+    }
+
+    #[test]
+    fn parses_mapped_address() {
+        let value = [0, 1, 0, 0, 198, 51, 100, 1];
+        let response = stun_response(STUN_MAPPED_ADDRESS, &value);
+        assert_eq!(
+            parse_stun_response(&response, &TRANSACTION_ID),
+            Some(IpAddr::from([198, 51, 100, 1]))
+        );
+    }
+
+    #[test]
+    fn rejects_response_of_other_transaction() {
+        let value = [0, 1, 0, 0, 198, 51, 100, 1];
+        let response = stun_response(STUN_MAPPED_ADDRESS, &value);
+        assert_eq!(parse_stun_response(&response, &[0; 12]), None);
+    }
+}
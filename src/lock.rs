@@ -0,0 +1,73 @@
+use crate::error::LockError;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::Path;
+use std::process;
+
+/// Exclusive lock on a file holding the pid of its owner, released when dropped
+pub struct LockFile {
+    file: File,
+}
+
+impl LockFile {
+    pub fn acquire(path: &Path) -> Result<LockFile, LockError> {
+        // The file stays in place, removing it would let a waiting process lock a stale inode
+        let io_error = |source| LockError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(io_error)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(LockError::Held {
+                    path: path.to_path_buf(),
+                    pid: read_pid(path),
+                })
+            }
+            Err(TryLockError::Error(e)) => return Err(io_error(e)),
+        }
+
+        file.set_len(0)
+            .and_then(|_| writeln!(file, "{}", process::id()))
+            .map_err(io_error)?;
+        Ok(LockFile { file })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_lock_fails_while_first_is_held() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("cf-dynamic.lock");
+
+        let lock = LockFile::acquire(&path).unwrap();
+        match LockFile::acquire(&path) {
+            Err(LockError::Held { pid, .. }) => assert_eq!(pid, Some(process::id())),
+            _ => panic!("Lock was acquired twice"),
+        }
+
+        drop(lock);
+        assert!(LockFile::acquire(&path).is_ok());
+    }
+}
@@ -1,32 +1,153 @@
 extern crate core;
 
-use crate::cloudflare::{CloudflareApi, DomainRegistration};
-use crate::config::Config;
-use clap::Parser;
+use crate::cloudflare::cache::{Cache, DnsRecord, AUTOMATIC_TTL_SECS};
+#[cfg(feature = "tokio")]
+use crate::cloudflare::AsyncCloudflareApi;
+use crate::cloudflare::{
+    parse_record_type, to_ascii_domain, ClientConfig, CloudflareApi, DomainRegistration,
+    ZoneDnsRecord, MAX_COMMENT_LENGTH, SUPPORTED_RECORD_TYPES,
+};
+use crate::config::{
+    read_batch_file, read_token_line, resolve_token, Config, ConfigFormat, ConfigSource,
+    BUILD_TIMESTAMP, DEFAULT_PROFILE, GIT_HASH, VERSION,
+};
+use crate::daemon::{Daemon, DEFAULT_INTERVAL};
+use crate::dns::{resolve, DEFAULT_RESOLVER};
+use crate::error::{
+    display_chain, CloudflareError, ConfigError, Error, IpDetectionError, IpError, LockError,
+    NotificationError,
+};
+use crate::history::{append_history, domain_stats, read_history, HistoryEntry, HistoryFilter};
+use crate::ip_detection::{
+    get_delegated_prefix, get_ip, get_ip_from_interface, get_ip_via_stun, prefix_source_interface,
+    validate_global_ip, IpSource, IpVersion,
+};
+use crate::lock::LockFile;
+use crate::metrics::{Metrics, DEFAULT_METRICS_PORT};
+use crate::notification::{
+    EmailNotifier, LogFileNotifier, Notifier, TelegramNotifier, UpdateEvent, WebhookNotifier,
+};
+use crate::time::{format_timestamp, parse_timestamp};
+use clap::error::ErrorKind;
 use clap::Subcommand;
+use clap::ValueEnum;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+use clap_complete::{generate, Shell};
+use csv::{Terminator, WriterBuilder};
 use prettytable::{format, row, Cell, Table};
-use reqwest::blocking::Client;
-use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use serde_json::to_string_pretty;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Debug;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::iter;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "tokio")]
+use tokio::sync::Semaphore;
+#[cfg(feature = "tokio")]
+use tokio::task::JoinSet;
+#[cfg(feature = "tokio")]
+use tracing::Instrument;
+use tracing::{error, info, info_span, warn, Level};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::EnvFilter;
 
 pub mod cloudflare;
 pub mod config;
+pub mod daemon;
+pub mod dns;
+pub mod error;
+pub mod history;
+pub mod ip_detection;
+pub mod lock;
+pub mod metrics;
+pub mod notification;
+pub mod proxy;
+pub mod time;
+
+/// Name the completion scripts are generated for
+const BINARY_NAME: &str = "cf-dynamic";
+/// Domain of the synthetic event sent by test-notify, .test is reserved and never registered
+const TEST_NOTIFY_DOMAIN: &str = "cf-dynamic.test";
 
 /// Simple program to greet a person
 #[derive(Parser)]
 #[command(name = "CloudflareDynDns")]
 #[command(author = "Maximilian Kling <an@maximilian-kling.de>")]
-#[command(version = "1.0")]
+#[command(version = version_string())]
 #[command(about = "Dynamic DNS Updates for Cloudflare Domains", long_about = None)]
 pub struct Args {
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
+    /// Only validate the config and domains files and exit, without contacting any server
+    #[arg(long)]
+    config_check: bool,
     #[arg(short, long, env = "CONFIG_PATH")]
     config_file: Option<PathBuf>,
     #[arg(short, long, env = "DOMAINS_PATH")]
     domains_file: Option<PathBuf>,
+    /// Cloudflare API token, or file:<path> or the absolute path of a file containing it, e.g. /run/secrets/cf_token
     #[arg(long, env = "CLOUDFLARE_TOKEN", default_value = "")]
     cloudflare_token: String,
+    /// Read the Cloudflare API token from the first line of stdin, e.g. piped from a password manager
+    #[arg(long, conflicts_with = "cloudflare_token")]
+    cloudflare_token_stdin: bool,
+    /// Where cloudflare_token comes from, see `Args::from_matches`
+    #[arg(skip)]
+    cloudflare_token_source: ConfigSource,
+    /// Where domains_file comes from, see `Args::from_matches`
+    #[arg(skip)]
+    domains_file_source: ConfigSource,
+    /// URL of a service returning the public IP, tried in the given order. {version} is replaced by ipv4 or ipv6
+    #[arg(long, value_delimiter = ',')]
+    ip_provider: Vec<String>,
+    /// Minimum level of log messages (error, warn, info, debug, trace), overrides RUST_LOG
+    #[arg(long, global = true)]
+    log_level: Option<Level>,
+    /// Log every Cloudflare API request and response, same as --log-level debug
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Only log errors, e.g. for cron jobs. Same as --log-level error
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Append log messages to this file instead of writing them to stderr
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+    /// Never print ANSI colors or styles, same as setting NO_COLOR
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Seconds to wait for a connection and for a response of any HTTP request, overrides http_timeout_secs of the config file
+    #[arg(long, global = true)]
+    timeout: Option<u64>,
+    /// UNSAFE: Accept invalid TLS certificates of Cloudflare and the ip detection services, e.g. of a local proxy
+    #[arg(long, global = true)]
+    insecure: bool,
+    /// Also send private, loopback and link local addresses to Cloudflare, e.g. for DNS records of a LAN
+    #[arg(long, global = true)]
+    allow_private_ips: bool,
+    /// Only update records whose Cloudflare zone has this name, overrides zone_filter of the config file.
+    /// Updates that exclude every registered domain change nothing and succeed
+    #[arg(long, global = true)]
+    zone_filter: Option<String>,
+    /// Send ip detection and Cloudflare API requests from this network interface, e.g. eth1 of a second ISP,
+    /// overrides bind_interface of the config file
+    #[arg(long, global = true, value_name = "NAME")]
+    bind_interface: Option<String>,
+    /// Profile with its own config, domains and cache files, e.g. cf-dynamic-<profile>.conf
+    #[arg(long, global = true, env = "CF_DYNAMIC_PROFILE", default_value = DEFAULT_PROFILE)]
+    profile: String,
 }
 
 #[derive(Subcommand)]
@@ -35,315 +156,4380 @@ enum Commands {
     List {
         #[arg(long)]
         debug: bool,
+        /// Format the domains are printed in
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+        output: OutputFormat,
+        /// Only list domains within this zone
+        #[arg(long)]
+        zone: Option<String>,
+        /// Only list domains with any of these tags
+        #[arg(long, value_delimiter = ',')]
+        tags: Vec<String>,
     },
     /// Shows the status of a registered domain
-    Status { domain: String },
+    Status {
+        domain: String,
+        /// Format the status is printed in
+        #[arg(long, value_enum, default_value_t = DetailFormat::Table)]
+        format: DetailFormat,
+    },
+    /// Shows the full configuration and the Cloudflare records of a registered domain
+    Show {
+        domain: String,
+        /// Format the domain is printed in
+        #[arg(long, value_enum, default_value_t = DetailFormat::Table)]
+        format: DetailFormat,
+    },
     /// Deletes a registered domain
-    Delete { domain: String },
+    Delete {
+        domain: String,
+    },
+    /// Renames a registered domain, keeping its settings
+    Rename {
+        old_domain: String,
+        new_domain: String,
+    },
+    /// Deletes the DNS records of a registered domain from Cloudflare and the domain itself
+    Purge {
+        domain: String,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
     /// Registers a new domain
     Register {
         domain: String,
         /// Suffix for IPv4
         #[arg(short = '4', long)]
         v4_suffix: Option<String>,
-        /// Suffix for IPv6
+        /// Suffix for IPv6, or eui64:<mac> to derive it from a MAC address
         #[arg(short = '6', long)]
         v6_suffix: Option<String>,
         #[arg(long)]
         disable_v4: bool,
         #[arg(long)]
         disable_v6: bool,
+        /// Where to take the IPv4 address from instead of the ip detection services (interface:<name>)
+        #[arg(long, value_parser = parse_ip_source)]
+        v4_source: Option<String>,
+        /// Where to take the IPv6 address from instead of the ip detection services (interface:<name>)
+        #[arg(long, value_parser = parse_ip_source)]
+        v6_source: Option<String>,
+        /// Append the IPv6 suffix to the prefix delegated to this interface (pd:<name>)
+        #[arg(
+            long,
+            value_parser = parse_prefix_source,
+            requires = "v6_suffix",
+            conflicts_with = "v6_source"
+        )]
+        v6_prefix_source: Option<String>,
+        /// Only manage these record types (A, AAAA, CNAME) instead of choosing them by the disable flags
+        #[arg(long, value_delimiter = ',', value_parser = parse_record_type)]
+        force_record_type: Vec<String>,
+        /// Name the CNAME record points to, e.g. a CDN origin. Manages only the CNAME record
+        /// unless --force-record-type is given
+        #[arg(long, value_name = "NAME")]
+        cname_target: Option<String>,
+        /// TTL of the DNS records in seconds (1 for automatic)
+        #[arg(long)]
+        ttl: Option<u32>,
+        /// Proxy traffic to the domain through Cloudflare
+        #[arg(long, overrides_with = "no_proxied")]
+        proxied: bool,
+        /// Expose the real IP in the DNS records (default)
+        #[arg(long, overrides_with = "proxied")]
+        no_proxied: bool,
+        /// Comment of the DNS records, e.g. the device they belong to (at most 50 characters)
+        #[arg(long)]
+        comment: Option<String>,
+        /// Tag to select the domain with --tags, can be given multiple times
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Only update the record in the domain's own zone, not the ones of parent or child zones
+        #[arg(long)]
+        single_zone: bool,
     },
-    Update {
-        #[arg(short, long)]
-        force: bool,
-    },
+    /// Changes the settings of a registered domain
+    Edit(EditArgs),
+    Update(UpdateArgs),
+    /// Sends a test notification through every configured notification channel, exits with 1 if any fails
+    TestNotify(NotifyArgs),
     Login {
-        /// The token to store as authentication for the cloudflare api
+        /// The token to store as authentication for the cloudflare api. A file:<path> or absolute path of a
+        /// file containing the token is stored as is, so the file is read again on every run
         cloudflare_token: String,
     },
+    /// Removes the stored Cloudflare token from the config file
+    Logout,
+    /// Registers the existing A, AAAA and CNAME records of the Cloudflare zones
+    Import {
+        /// Only import records of this zone
+        #[arg(long)]
+        zone: Option<String>,
+        /// Only show which domains would be registered
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Lists the A, AAAA and CNAME records of the Cloudflare zones that are not registered
+    Orphan {
+        /// Only look at records of this zone
+        #[arg(long)]
+        zone: Option<String>,
+        /// Register the orphaned records
+        #[arg(long)]
+        adopt: bool,
+    },
+    /// Registers all domains of a JSON file, or of the `domains` array of a TOML file
+    BatchRegister {
+        file: PathBuf,
+        /// Only show which domains would be registered
+        #[arg(long)]
+        dry_run: bool,
+        /// Register nothing if any entry is invalid
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Lists all zones accessible with the Cloudflare token
+    Zones,
+    /// Checks the config, token, domains and ip detection without changing anything
+    Check,
+    /// Checks that a public resolver returns the addresses of the domain's Cloudflare records
+    Verify {
+        domain: String,
+        /// Resolver queried for the A and AAAA records
+        #[arg(long, default_value = DEFAULT_RESOLVER, value_parser = parse_resolver)]
+        resolver: SocketAddr,
+        /// Seconds to wait for an answer of the resolver
+        #[arg(long, default_value_t = 5)]
+        timeout_secs: u64,
+    },
+    /// Shows what update would change without changing anything, exits with 1 if anything would change
+    Diff {
+        /// Only compare domains within this zone
+        #[arg(long)]
+        zone: Option<String>,
+        /// Only compare domains with any of these tags
+        #[arg(long, value_delimiter = ',')]
+        tags: Vec<String>,
+    },
+    /// Shows the changes of the DNS records made by updates, oldest first
+    History {
+        /// Only show changes of this domain
+        domain: Option<String>,
+        /// Only show the last this many changes
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Only show changes since this time, a unix timestamp or RFC 3339 like 2024-01-01T00:00:00Z
+        #[arg(long, value_parser = parse_timestamp)]
+        since: Option<u64>,
+        /// Only show changes until this time, a unix timestamp or RFC 3339
+        #[arg(long, value_parser = parse_timestamp)]
+        until: Option<u64>,
+    },
+    /// Shows how often the DNS records of the registered domains were changed by updates
+    Stats {
+        /// Only show the statistics of this domain
+        domain: Option<String>,
+    },
+    /// Lists all profiles with a config file
+    Profiles,
+    /// Shows the cache of zones and DNS records
+    Cache {
+        /// Delete the cache file
+        #[arg(long)]
+        clear: bool,
+        /// Remove this zone and all of its DNS records from the cache
+        #[arg(long)]
+        evict_zone: Option<String>,
+    },
+    /// Shows or changes entries of the config file
+    #[command(subcommand)]
+    Config(ConfigCommands),
+    /// Lists and changes DNS records directly on Cloudflare, without registering them as domains
+    #[command(subcommand)]
+    Cloud(CloudCommands),
+    /// Writes the config file and the domains to a new TOML config, the original files are kept
+    ///
+    /// The config entries become top-level keys of the TOML file, e.g. `zone_filter = "..."`,
+    /// and every domain becomes a `[[domains]]` table. This is the layout the TOML config is
+    /// read with, so there is no separate `[config]` table. domains_file is left out, as the
+    /// domains are part of the new file.
+    Migrate {
+        /// TOML config to create, e.g. /etc/cf-dynamic/cf-dynamic.toml
+        output_file: PathBuf,
+    },
+    /// Prints the shell completion script for cf-dynamic
+    ///
+    /// To load the completions in every new shell, use --install-completions or write the
+    /// script to a file your shell reads, e.g. add `source <(cf-dynamic completions bash)`
+    /// to ~/.bashrc. For zsh the directory of _cf-dynamic has to be part of $fpath.
+    Completions {
+        /// Shell to generate the script for
+        shell: Shell,
+        /// Write the script to the per-user completion directory of the shell instead of printing it
+        #[arg(long)]
+        install_completions: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Shows every config value in effect and where it comes from
+    Show,
+    /// Writes an entry to the config file
+    Set { key: String, value: String },
+    /// Removes an entry from the config file
+    Unset { key: String },
+    /// Checks that required entries are set and every value has the type of its key
+    Validate,
+}
+
+#[derive(Subcommand)]
+enum CloudCommands {
+    /// Lists the records of every type of the zone, or of all zones
+    List {
+        /// Name of the Cloudflare zone, e.g. example.com
+        zone: Option<String>,
+    },
+    /// Creates a record in the zone
+    ///
+    /// A name never gets a second record of the same type, unless --allow-duplicate is given
+    Add {
+        /// Name of the Cloudflare zone, e.g. example.com
+        zone: String,
+        /// Name of the record, relative to the zone unless it ends with it, @ for the zone itself
+        name: String,
+        /// Type of the record, e.g. A, AAAA, CNAME or TXT
+        #[arg(value_parser = parse_cloud_record_type)]
+        record_type: String,
+        /// Content of the record, an address for A and AAAA records
+        content: String,
+        /// TTL of the record in seconds (default: automatic)
+        #[arg(long)]
+        ttl: Option<u32>,
+        /// Proxy traffic to the record through Cloudflare
+        #[arg(long)]
+        proxied: bool,
+        /// Also create the record if the name already has one of this type, e.g. a second TXT record.
+        /// Remove and update only change the first of them
+        #[arg(long)]
+        allow_duplicate: bool,
+    },
+    /// Deletes a record from the zone
+    Remove {
+        /// Name of the Cloudflare zone, e.g. example.com
+        zone: String,
+        /// Name of the record, relative to the zone unless it ends with it, @ for the zone itself
+        name: String,
+        /// Type of the record, e.g. A, AAAA, CNAME or TXT
+        #[arg(value_parser = parse_cloud_record_type)]
+        record_type: String,
+        /// Only remove the record with this content, needed if the name has several of the type
+        #[arg(long)]
+        content: Option<String>,
+    },
+    /// Changes the content of a record, keeping its TTL, proxy setting and comment
+    Update {
+        /// Name of the Cloudflare zone, e.g. example.com
+        zone: String,
+        /// Name of the record, relative to the zone unless it ends with it, @ for the zone itself
+        name: String,
+        /// Type of the record, e.g. A, AAAA, CNAME or TXT
+        #[arg(value_parser = parse_cloud_record_type)]
+        record_type: String,
+        /// New content of the record, an address for A and AAAA records
+        content: String,
+        /// Only update the record with this content, needed if the name has several of the type
+        #[arg(long)]
+        old_content: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Output format of the commands describing a single domain
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DetailFormat {
+    Table,
+    Json,
+    Yaml,
+}
+
+#[derive(clap::Args)]
+struct EditArgs {
+    domain: String,
+    /// Suffix for IPv4, an empty suffix removes it
+    #[arg(short = '4', long)]
+    v4_suffix: Option<String>,
+    /// Suffix for IPv6, or eui64:<mac> to derive it from a MAC address, an empty suffix removes it
+    #[arg(short = '6', long)]
+    v6_suffix: Option<String>,
+    /// Interface whose delegated prefix the IPv6 suffix is appended to (pd:<name>), an empty value removes it
+    #[arg(long)]
+    v6_prefix_source: Option<String>,
+    #[arg(long)]
+    disable_v4: Option<bool>,
+    #[arg(long)]
+    disable_v6: Option<bool>,
+    /// TTL of the DNS records in seconds (1 for automatic)
+    #[arg(long)]
+    ttl: Option<u32>,
+    /// Proxy traffic to the domain through Cloudflare
+    #[arg(long)]
+    proxied: Option<bool>,
+    /// Comment of the DNS records (at most 50 characters), an empty comment removes it
+    #[arg(long)]
+    comment: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct UpdateArgs {
+    #[arg(short, long)]
+    force: bool,
+    /// Do not create missing DNS records
+    #[arg(long)]
+    no_create: bool,
+    /// Only show what would be changed, without changing any DNS records
+    #[arg(long, visible_alias = "no-op")]
+    dry_run: bool,
+    /// Update the DNS records in parallel (default)
+    #[arg(long, overrides_with = "no_parallel")]
+    parallel: bool,
+    /// Update the DNS records one after another
+    #[arg(long, overrides_with = "parallel")]
+    no_parallel: bool,
+    /// Maximum number of DNS records updated at the same time
+    #[arg(short, long, default_value_t = 8)]
+    jobs: usize,
+    /// Keep running and update the DNS records periodically
+    #[arg(long)]
+    daemon: bool,
+    /// Repeat the update every this many seconds. Stored in the config file in daemon mode
+    #[arg(long)]
+    interval: Option<u64>,
+    /// Update all DNS records on the first run even if the ips did not change, later runs detect changes as usual
+    #[arg(long)]
+    update_on_startup: bool,
+    /// File the process id is written to in daemon mode
+    #[arg(long, requires = "daemon")]
+    pid_file: Option<PathBuf>,
+    /// Local port Prometheus metrics are served on at /metrics in daemon mode (default: 9909)
+    #[arg(long, requires = "daemon")]
+    metrics_port: Option<u16>,
+    /// Seconds to wait for a connection to the Cloudflare API (default: 10)
+    #[arg(long)]
+    connect_timeout: Option<u64>,
+    /// Seconds to wait for a response of the Cloudflare API (default: 30)
+    #[arg(long)]
+    read_timeout: Option<u64>,
+    /// Always fetch the DNS records from Cloudflare instead of the cache, e.g. after changes in the dashboard
+    #[arg(long)]
+    no_cache: bool,
+    /// Only update domains within this zone
+    #[arg(long)]
+    zone: Option<String>,
+    /// Only update domains with any of these tags
+    #[arg(long, value_delimiter = ',')]
+    tags: Vec<String>,
+    /// Lowest TTL in seconds sent to Cloudflare, overrides min_ttl of the config file
+    #[arg(long)]
+    min_ttl: Option<u32>,
+    /// Highest TTL in seconds sent to Cloudflare, overrides max_ttl of the config file
+    #[arg(long)]
+    max_ttl: Option<u32>,
+    /// Detect the public IP via STUN, using stun_server of the config file or stun.l.google.com:19302
+    #[arg(long)]
+    stun: bool,
+    /// IPv4 address used instead of detecting it, e.g. with --dry-run to see what a new ip would change
+    #[arg(long, visible_alias = "ip-v4-override")]
+    ip_v4: Option<Ipv4Addr>,
+    /// IPv6 address used instead of detecting it, suffixes of the domains are still applied
+    #[arg(long, visible_alias = "ip-v6-override")]
+    ip_v6: Option<Ipv6Addr>,
+    #[command(flatten)]
+    notify_args: NotifyArgs,
+}
+
+#[derive(clap::Args)]
+struct NotifyArgs {
+    /// URL notified about every changed DNS record, overrides webhook_url of the config file
+    #[arg(long)]
+    webhook_url: Option<String>,
+    /// Address a summary email is sent to if a DNS record changed or failed, overrides smtp_to of the config file
+    #[arg(long, value_name = "ADDRESS")]
+    notify_email: Option<String>,
+    /// Token of the Telegram bot messaging about changed and failed DNS records, overrides telegram_bot_token of the config file
+    #[arg(long)]
+    telegram_token: Option<String>,
+    /// Chat the Telegram bot messages, overrides telegram_chat_id of the config file
+    #[arg(long)]
+    telegram_chat_id: Option<String>,
+}
+
+impl Args {
+    fn from_matches(matches: &ArgMatches) -> Result<Args, clap::Error> {
+        // Only the matches know whether a value was given as argument or in the environment
+        let mut args = Args::from_arg_matches(matches)?;
+        args.cloudflare_token_source = match args.cloudflare_token_stdin {
+            true => ConfigSource::Stdin,
+            false => ConfigSource::of_arg(matches, "cloudflare_token"),
+        };
+        args.domains_file_source = ConfigSource::of_arg(matches, "domains_file");
+        Ok(args)
+    }
+
+    fn resolve_token(&self) -> Result<String, ConfigError> {
+        // Stdin can only be read once, so this is resolved before any config is read
+        match self.cloudflare_token_stdin {
+            true => read_token_line(io::stdin().lock()),
+            false => Ok(self.cloudflare_token.clone()),
+        }
+    }
 }
 
 fn main() {
-    let args = Args::parse();
+    let mut args = Args::from_matches(&Args::command().get_matches()).unwrap_or_else(|e| e.exit());
+    if let Err(e) = init_logging(
+        &args.log_level,
+        &args.verbose,
+        &args.quiet,
+        &args.log_file,
+        &color_enabled(&args),
+    ) {
+        eprintln!("{}", display_chain(&e));
+        process::exit(1);
+    }
+    match args.resolve_token() {
+        Ok(token) => args.cloudflare_token = token,
+        Err(e) => {
+            error!("{}", display_chain(&e));
+            process::exit(1);
+        }
+    }
+
+    if args.config_check {
+        match Config::new(&args).and_then(|config| config.validate()) {
+            Ok(()) => println!("Config OK"),
+            Err(e) => {
+                error!("{}", display_chain(&e));
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    let Some(command) = &args.command else {
+        Args::command()
+            .error(
+                ErrorKind::MissingSubcommand,
+                "A subcommand is required unless --config-check is given",
+            )
+            .exit()
+    };
 
-    match &args.command {
+    let result = match command {
         Commands::Register {
             domain,
             v4_suffix,
             disable_v4,
             v6_suffix,
             disable_v6,
+            v4_source,
+            v6_source,
+            v6_prefix_source,
+            force_record_type,
+            cname_target,
+            ttl,
+            proxied,
+            no_proxied,
+            comment,
+            tags,
+            single_zone,
+        } => {
+            let _span = info_span!("register").entered();
+            // Forced record types decide which ip versions are enabled
+            let excluded = |record_type: &str| {
+                !force_record_type.is_empty() && !force_record_type.iter().any(|t| t == record_type)
+            };
+            let mut new_domain = DomainRegistration {
+                v4_disabled: *disable_v4 || excluded("A"),
+                v4_suffix: v4_suffix.clone(),
+                v4_source: v4_source.clone(),
+                v6_disabled: *disable_v6 || excluded("AAAA"),
+                v6_suffix: v6_suffix.clone(),
+                v6_source: v6_source.clone(),
+                v6_prefix_source: v6_prefix_source.clone(),
+                ttl: *ttl,
+                proxied: *proxied && !*no_proxied,
+                record_types: force_record_type.clone(),
+                cname_target: cname_target
+                    .as_deref()
+                    .map(|target| to_ascii_domain(target.trim_end_matches('.'))),
+                comment: comment.as_deref().map(truncate_comment),
+                tags: tags.clone(),
+                single_zone: *single_zone,
+                ..DomainRegistration::new(domain)
+            };
+            if new_domain.record_types.is_empty() {
+                new_domain.record_types = match new_domain.cname_target {
+                    Some(_) => vec!["CNAME".to_string()],
+                    None => new_domain.default_record_types(),
+                };
+            }
+            register_domain(&args, new_domain)
+        }
+        Commands::List {
+            debug,
+            output,
+            zone,
+            tags,
         } => {
-            register_domain(&args, domain, disable_v4, v4_suffix, disable_v6, v6_suffix);
+            let _span = info_span!("list").entered();
+            list_domains(&args, debug, output, zone, tags)
+        }
+        Commands::Edit(edit_args) => {
+            let _span = info_span!("edit").entered();
+            edit_domain(&args, edit_args)
         }
-        Commands::List { debug } => {
-            list_domains(&args, debug);
+        Commands::TestNotify(notify_args) => {
+            let _span = info_span!("test-notify").entered();
+            test_notify(&args, notify_args)
         }
-        Commands::Update { force } => {
-            update_domains(&args, force);
+        Commands::Update(update_args) => {
+            let _span = info_span!("update").entered();
+            match (update_args.daemon, update_args.interval) {
+                (true, _) => run_daemon(&args, update_args),
+                (false, Some(interval)) => run_periodically(&args, update_args, interval),
+                (false, None) => update_domains(
+                    &args,
+                    update_args,
+                    &(update_args.force || update_args.update_on_startup),
+                    None,
+                ),
+            }
         }
         Commands::Delete { domain } => {
-            delete_domain(&args, domain);
+            let _span = info_span!("delete").entered();
+            delete_domain(&args, domain)
+        }
+        Commands::Rename {
+            old_domain,
+            new_domain,
+        } => {
+            let _span = info_span!("rename").entered();
+            rename_domain(&args, old_domain, new_domain)
+        }
+        Commands::Purge { domain, force } => {
+            let _span = info_span!("purge").entered();
+            purge_domain(&args, domain, force)
+        }
+        Commands::Status { domain, format } => {
+            let _span = info_span!("status").entered();
+            domain_status(&args, domain, format)
+        }
+        Commands::Show { domain, format } => {
+            let _span = info_span!("show").entered();
+            show_domain(&args, domain, format)
         }
         Commands::Login { cloudflare_token } => {
-            login(&args, cloudflare_token);
+            let _span = info_span!("login").entered();
+            login(&args, cloudflare_token)
+        }
+        Commands::Logout => {
+            let _span = info_span!("logout").entered();
+            logout(&args)
+        }
+        Commands::BatchRegister {
+            file,
+            dry_run,
+            strict,
+        } => {
+            let _span = info_span!("batch_register").entered();
+            batch_register(&args, file, dry_run, strict)
+        }
+        Commands::Import { zone, dry_run } => {
+            let _span = info_span!("import").entered();
+            import_domains(&args, zone, dry_run)
+        }
+        Commands::Orphan { zone, adopt } => {
+            let _span = info_span!("orphan").entered();
+            orphan_records(&args, zone, adopt)
+        }
+        Commands::Zones => {
+            let _span = info_span!("zones").entered();
+            list_zones(&args)
+        }
+        Commands::Check => {
+            let _span = info_span!("check").entered();
+            check(&args)
+        }
+        Commands::Verify {
+            domain,
+            resolver,
+            timeout_secs,
+        } => {
+            let _span = info_span!("verify").entered();
+            verify_domain(&args, domain, resolver, timeout_secs)
+        }
+        Commands::Diff { zone, tags } => {
+            let _span = info_span!("diff").entered();
+            diff_domains(&args, zone, tags)
+        }
+        Commands::History {
+            domain,
+            limit,
+            since,
+            until,
+        } => {
+            let _span = info_span!("history").entered();
+            show_history(
+                &args,
+                HistoryFilter {
+                    domain: domain.as_deref().map(to_ascii_domain),
+                    since: *since,
+                    until: *until,
+                    limit: *limit,
+                },
+            )
         }
-        _ => {}
+        Commands::Stats { domain } => {
+            let _span = info_span!("stats").entered();
+            show_stats(&args, domain)
+        }
+        Commands::Profiles => {
+            let _span = info_span!("profiles").entered();
+            list_profiles(&args)
+        }
+        Commands::Config(config_command) => {
+            let _span = info_span!("config").entered();
+            config_command_run(&args, config_command)
+        }
+        Commands::Cache { clear, evict_zone } => {
+            let _span = info_span!("cache").entered();
+            cache(&args, clear, evict_zone)
+        }
+        Commands::Cloud(cloud_command) => {
+            let _span = info_span!("cloud").entered();
+            cloud_command_run(&args, cloud_command)
+        }
+        Commands::Migrate { output_file } => {
+            let _span = info_span!("migrate").entered();
+            migrate(&args, output_file)
+        }
+        Commands::Completions {
+            shell,
+            install_completions,
+        } => {
+            let _span = info_span!("completions").entered();
+            completions(shell, install_completions)
+        }
+    };
+
+    if let Err(e) = result {
+        error!("{}", display_chain(&e));
+        process::exit(1);
     }
 }
 
-fn login(args: &Args, cloudflare_token: &String) {
-    let config = Config::new(args);
+fn init_logging(
+    log_level: &Option<Level>,
+    verbose: &bool,
+    quiet: &bool,
+    log_file: &Option<PathBuf>,
+    color: &bool,
+) -> Result<(), ConfigError> {
+    // Only log messages of this crate, an explicit --log-level wins over --verbose, --quiet and RUST_LOG
+    let log_level = log_level
+        .or(verbose.then_some(Level::DEBUG))
+        .or(quiet.then_some(Level::ERROR));
+    let filter = match log_level {
+        Some(level) => EnvFilter::new(format!("{}={}", env!("CARGO_CRATE_NAME"), level)),
+        None => EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| EnvFilter::new(format!("{}=info", env!("CARGO_CRATE_NAME")))),
+    };
+
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match log_file {
+        Some(path) => {
+            // File is unbuffered, so every message is written out as soon as it is logged
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| ConfigError::Io {
+                    path: path.clone(),
+                    source: e,
+                })?;
+            subscriber
+                .with_writer(Mutex::new(file))
+                .with_ansi(false)
+                .with_timer(LogFileTime)
+                .init();
+        }
+        None => subscriber.with_writer(io::stderr).with_ansi(*color).init(),
+    }
+    Ok(())
+}
 
-    let mut cloudflare_client = CloudflareApi::new(cloudflare_token.clone());
+fn color_enabled(args: &Args) -> bool {
+    // See https://no-color.org, an empty NO_COLOR does not disable colors
+    !args.no_color && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
 
-    if cloudflare_client.fetch_cloudflare_zones().is_ok() {
-        match config.set_config_entry("cloudflare_token", cloudflare_token) {
-            Ok(_) => println!("Successfully logged in"),
-            Err(e) => println!("Error while writing config file: {}", e),
+fn print_table(args: &Args, table: &Table) {
+    // Without a terminal printstd skips styles anyway, print() never writes any
+    match color_enabled(args) {
+        true => {
+            table.printstd();
+        }
+        false => {
+            let _ = table.print(&mut io::stdout());
         }
-    } else {
-        println!("Failed to login");
     }
 }
 
-fn delete_domain(args: &Args, domain: &String) {
-    let config = Config::new(args);
-    let mut domains = config.read_domains();
+/// Formats timestamps of the log file as [YYYY-MM-DDTHH:MM:SSZ]
+struct LogFileTime;
 
-    let orig_length = domains.len();
-    domains.retain(|x| x.domain != *domain);
+impl FormatTime for LogFileTime {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        write!(w, "[{}]", format_timestamp(secs))
+    }
+}
 
-    if domains.len() != orig_length {
-        match config.write_domains(&domains) {
-            Ok(_) => println!("Deleted domain '{}' successfully", domain),
-            Err(e) => println!("Error while writing domains.json: {}", e),
-        }
-    } else {
-        println!("Domain '{}' is not registered", domain);
+fn version_string() -> &'static str {
+    // e.g. 1.0.0 (abc1234, 2024-01-01T00:00:00Z), formatted once as clap keeps a reference
+    static VERSION_STRING: OnceLock<String> = OnceLock::new();
+    VERSION_STRING.get_or_init(|| {
+        format!(
+            "{} ({}, {})",
+            VERSION,
+            GIT_HASH,
+            format_timestamp(BUILD_TIMESTAMP)
+        )
+    })
+}
+
+fn parse_ip_source(source: &str) -> Result<String, IpDetectionError> {
+    IpSource::parse(&Some(source.to_string())).map(|_| source.to_string())
+}
+
+fn parse_prefix_source(source: &str) -> Result<String, IpDetectionError> {
+    prefix_source_interface(source).map(|_| source.to_string())
+}
+
+fn login(args: &Args, cloudflare_token: &str) -> Result<(), Error> {
+    let config = Config::new(args)?;
+
+    // Skip the cache, the token is only verified by an actual request to the API
+    let mut cloudflare_client = CloudflareApi::new(ClientConfig {
+        cache_file: None,
+        ..config.read_client_config(resolve_token(cloudflare_token)?)?
+    })?;
+
+    cloudflare_client
+        .fetch_cloudflare_zones()
+        .map_err(Error::LoginFailed)?;
+    config.set_config_entry("cloudflare_token", cloudflare_token)?;
+    info!("Successfully logged in");
+    Ok(())
+}
+
+fn logout(args: &Args) -> Result<(), Error> {
+    let config = Config::new(args)?;
+
+    match config.delete_config_entry("cloudflare_token")? {
+        true => info!("Successfully logged out"),
+        false => warn!("No Cloudflare token stored in {:#?}", config.config_file()),
     }
+    Ok(())
 }
 
-fn register_domain(
-    args: &Args,
-    domain: &String,
-    disable_v4: &bool,
-    v4_suffix: &Option<String>,
-    disable_v6: &bool,
-    v6_suffix: &Option<String>,
-) {
-    let config = Config::new(args);
-    let mut domains = config.read_domains();
+fn import_domains(args: &Args, zone: &Option<String>, dry_run: &bool) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let mut domains = config.read_domains()?;
+    let mut cloudflare_client =
+        CloudflareApi::new(config.read_client_config(config.read_cloudflare_token()?)?)?;
 
-    //Check if domain is already registered
-    for registered_domain in domains.iter() {
-        if registered_domain.domain == *domain {
-            println!("Domain '{}' is already registered", domain);
-            return;
+    let zone_dns_records =
+        cloudflare_client.fetch_cloudflare_zone_dns_records(zone, &SUPPORTED_RECORD_TYPES)?;
+
+    // One registration per name, with the record types that exist for it
+    let mut imported: Vec<DomainRegistration> = Vec::new();
+    for zone_dns_record in zone_dns_records.iter() {
+        let name = zone_dns_record.name.as_str();
+        if domains.iter().any(|d| d.domain == name) {
+            continue;
         }
+        register_record(&mut imported, zone_dns_record);
     }
 
-    let new_domain = DomainRegistration::new(domain, disable_v4, v4_suffix, disable_v6, v6_suffix);
-    domains.push(new_domain);
+    if imported.is_empty() {
+        info!("No new domains found");
+        return Ok(());
+    }
 
-    // Write the new domains.json file
-    match config.write_domains(&domains) {
-        Ok(_) => println!("Registered domain '{}' successfully", domain),
-        Err(e) => println!("Error while writing domains.json: {}", e),
+    for domain_registration in imported.iter() {
+        let record_types = domain_registration.managed_record_types().join(", ");
+        match *dry_run {
+            true => info!(
+                "[DRY RUN] Would import {} ({})",
+                domain_registration.domain, record_types
+            ),
+            false => info!(
+                "Importing {} ({})",
+                domain_registration.domain, record_types
+            ),
+        }
+    }
+    if *dry_run {
+        return Ok(());
     }
-}
 
-fn list_domains(args: &Args, debug: &bool) {
-    let config = Config::new(args);
-    let domains = config.read_domains();
+    let count = imported.len();
+    domains.append(&mut imported);
+    config.write_domains(&domains)?;
+    info!("Imported {} domains successfully", count);
+    Ok(())
+}
 
-    let mut cloudflare_client: CloudflareApi = match *debug {
-        true => CloudflareApi::new(config.read_cloudflare_token()),
-        false => CloudflareApi::new(String::new()), // Token is not needed for listing domains
+fn register_record(domains: &mut Vec<DomainRegistration>, zone_dns_record: &ZoneDnsRecord) {
+    // Enables the record type of the name's registration, a new one manages no other record type
+    let name = zone_dns_record.name.as_str();
+    let domain_registration = match domains.iter().position(|d| d.domain == name) {
+        Some(index) => &mut domains[index],
+        None => {
+            if name.starts_with('*') {
+                warn!("{}: Wildcard record, every subdomain will get its IP", name);
+            }
+            domains.push(DomainRegistration {
+                v4_disabled: true,
+                v6_disabled: true,
+                ttl: Some(zone_dns_record.record.ttl),
+                proxied: zone_dns_record.record.proxied,
+                record_types: vec![zone_dns_record.record_type.clone()],
+                ..DomainRegistration::new(name)
+            });
+            domains.last_mut().unwrap()
+        }
     };
+    match zone_dns_record.record_type.as_str() {
+        "A" => domain_registration.v4_disabled = false,
+        "AAAA" => domain_registration.v6_disabled = false,
+        _ => domain_registration.cname_target = Some(zone_dns_record.record.content.clone()),
+    }
+    domain_registration.enable_record_type(&zone_dns_record.record_type);
+}
+
+fn is_orphan(domains: &[DomainRegistration], zone_dns_record: &ZoneDnsRecord) -> bool {
+    // Records of registered names count as orphaned too if their record type is disabled
+    !domains.iter().any(|domain_registration| {
+        domain_registration.domain == zone_dns_record.name
+            && domain_registration
+                .managed_record_types()
+                .contains(&zone_dns_record.record_type.as_str())
+    })
+}
+
+fn orphan_records(args: &Args, zone: &Option<String>, adopt: &bool) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let mut domains = config.read_domains()?;
+    let mut cloudflare_client =
+        CloudflareApi::new(config.read_client_config(config.read_cloudflare_token()?)?)?;
+
+    let mut orphans: Vec<ZoneDnsRecord> = cloudflare_client
+        .fetch_cloudflare_zone_dns_records(zone, &SUPPORTED_RECORD_TYPES)?
+        .into_iter()
+        .filter(|zone_dns_record| is_orphan(&domains, zone_dns_record))
+        .collect();
+    if orphans.is_empty() {
+        info!("No orphaned records found");
+        return Ok(());
+    }
+    orphans.sort_by(|a, b| {
+        (&a.zone, &a.name, &a.record_type).cmp(&(&b.zone, &b.name, &b.record_type))
+    });
 
     let mut table = Table::new();
     table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row!["Zone", "Name", "Type", "Content"]);
+    for orphan in orphans.iter() {
+        table.add_row(row![
+            orphan.zone,
+            orphan.name,
+            orphan.record_type,
+            orphan.record.content
+        ]);
+    }
+    print_table(args, &table);
+    if !*adopt {
+        return Ok(());
+    }
 
-    let header = match *debug {
-        true => row!["Domain", "IPv4", "ID4", "IPv6", "ID6"],
-        false => row!["Domain", "IPv4", "IPv6"],
-    };
+    for orphan in orphans.iter() {
+        register_record(&mut domains, orphan);
+    }
+    config.write_domains(&domains)?;
+    info!("Adopted {} records successfully", orphans.len());
+    Ok(())
+}
 
-    table.set_titles(header);
+fn batch_register(args: &Args, file: &Path, dry_run: &bool, strict: &bool) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let mut domains = config.read_domains()?;
+    let entries = read_batch_file(file)?;
 
-    for domain in domains.iter() {
-        let v4_string: &str = match domain.v4_disabled {
-            true => "Disabled",
-            false => match domain.v4_suffix {
-                Some(ref suffix) => suffix,
-                None => "Default",
-            },
-        };
-        let v6_string: &str = match domain.v6_disabled {
-            true => "Disabled",
-            false => match domain.v6_suffix {
-                Some(ref suffix) => suffix,
-                None => "Default",
-            },
+    // Invalid entries are skipped, or abort the whole batch in strict mode
+    let mut added: Vec<DomainRegistration> = Vec::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let result = entry
+            .map_err(Error::from)
+            .and_then(|mut domain_registration| {
+                (domain_registration.domain, domain_registration.display_name) =
+                    normalize_domain(&domain_registration.domain)?;
+                check_record_types(&domain_registration)?;
+                domain_registration.comment =
+                    domain_registration.comment.as_deref().map(truncate_comment);
+                Ok(domain_registration)
+            });
+        let domain_registration = match result {
+            Ok(domain_registration) => domain_registration,
+            Err(e) if *strict => return Err(e),
+            Err(e) => {
+                error!("Skipping entry {}: {}", index + 1, display_chain(&e));
+                continue;
+            }
         };
 
-        let mut row = row![domain.domain, v4_string, v6_string];
-        if *debug {
-            let domain_id_4 = cloudflare_client
-                .fetch_cloudflare_dns_record(domain.domain.as_str(), "A")
-                .map(|record| record.id.clone())
-                .unwrap_or("Not Found".to_string());
-            let domain_id_6 = cloudflare_client
-                .fetch_cloudflare_dns_record(domain.domain.as_str(), "AAAA")
-                .map(|record| record.id.clone())
-                .unwrap_or("Not Found".to_string());
-            row.insert_cell(2, Cell::new(domain_id_4.as_str()));
-            row.insert_cell(4, Cell::new(domain_id_6.as_str()));
+        let domain = domain_registration.domain.as_str();
+        if domains
+            .iter()
+            .chain(added.iter())
+            .any(|d| d.domain == domain)
+        {
+            info!("{} is already registered, skipping", domain);
+            continue;
         }
-        table.add_row(row);
+        added.push(domain_registration);
     }
-    table.printstd();
-}
-
-fn update_domains(args: &Args, force: &bool) {
-    let config = Config::new(args);
 
-    let domains = config.read_domains();
+    if added.is_empty() {
+        info!("No new domains found");
+        return Ok(());
+    }
 
-    let mut cloudflare_client = CloudflareApi::new(config.read_cloudflare_token());
+    for domain_registration in added.iter() {
+        match *dry_run {
+            true => info!("[DRY RUN] Would register {}", domain_registration.domain),
+            false => info!("Registering {}", domain_registration.domain),
+        }
+    }
+    if *dry_run {
+        return Ok(());
+    }
 
-    let v4_ip = get_ip("ipv4");
-    let v6_ip = get_ip("ipv6");
+    let count = added.len();
+    domains.append(&mut added);
+    config.write_domains(&domains)?;
+    info!("Registered {} domains successfully", count);
+    Ok(())
+}
 
-    if config.read_config_entry("last_ipv4") == Some(&v4_ip)
-        && config.read_config_entry("last_ipv6") == Some(&v6_ip)
-        && !*force
-    {
-        let last_update = config
-            .read_config_entry("last_update")
-            .and_then(|v| v.parse::<u64>().ok());
+fn list_zones(args: &Args) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let mut cloudflare_client =
+        CloudflareApi::new(config.read_client_config(config.read_cloudflare_token()?)?)?;
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+    let mut zones = cloudflare_client.fetch_cloudflare_zone_names()?;
+    zones.sort();
 
-        if last_update.unwrap_or(0) + 60 * 60 * 12 > now {
-            println!("IP addresses have not changed, skipping update");
-            return;
-        } else {
-            println!("IP addresses have not changed, but it has been more than 12 hours since the last update, updating anyway");
-        }
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row!["ID", "Name"]);
+    for (name, id) in zones.iter() {
+        table.add_row(row![id, name]);
     }
+    print_table(args, &table);
+    Ok(())
+}
 
-    for domain_registration in domains.iter() {
-        if !domain_registration.v4_disabled {
-            let new_ip = match domain_registration.v4_suffix {
-                Some(ref suffix) => replace_ipv4_suffix(&v4_ip, suffix),
-                None => v4_ip.clone(),
-            };
+fn check(args: &Args) -> Result<(), Error> {
+    let mut failed = 0;
+    let mut report = |description: String, result: Result<(), String>| match result {
+        Ok(_) => println!("[PASS] {}", description),
+        Err(e) => {
+            println!("[FAIL] {} ({})", description, e);
+            failed += 1;
+        }
+    };
 
-            check_and_conditionally_update_domain(
-                &mut cloudflare_client,
-                domain_registration.domain.as_str(),
-                "A",
-                &new_ip,
-                force,
-            );
+    let config = match Config::new(args).and_then(|config| {
+        let domains = config.read_domains()?;
+        Ok((config, domains))
+    }) {
+        Ok(config) => {
+            report("Config is valid".to_string(), Ok(()));
+            Some(config)
         }
+        Err(e) => {
+            report("Config is valid".to_string(), Err(display_chain(&e)));
+            None
+        }
+    };
 
-        if !domain_registration.v6_disabled {
-            let new_ip = match domain_registration.v6_suffix {
-                Some(ref suffix) => replace_ipv6_suffix(&v6_ip, suffix),
-                None => v6_ip.clone(),
-            };
+    if let Some((config, domains)) = config {
+        // Skip the cache, only actual requests prove that the token works
+        let cloudflare_client = config
+            .read_cloudflare_token()
+            .and_then(|token| config.read_client_config(token))
+            .map_err(Error::from)
+            .and_then(|client_config| {
+                CloudflareApi::new(ClientConfig {
+                    cache_file: None,
+                    ..client_config
+                })
+                .map_err(Error::from)
+            });
+        let mut cloudflare_client = match cloudflare_client {
+            Ok(mut cloudflare_client) => {
+                let zones = cloudflare_client.fetch_cloudflare_zones();
+                let token_works = zones.is_ok();
+                report(
+                    "Cloudflare token is valid".to_string(),
+                    zones.map(|_| ()).map_err(|e| display_chain(&e)),
+                );
+                token_works.then_some(cloudflare_client)
+            }
+            Err(e) => {
+                report(
+                    "Cloudflare token is valid".to_string(),
+                    Err(display_chain(&e)),
+                );
+                None
+            }
+        };
 
-            check_and_conditionally_update_domain(
-                &mut cloudflare_client,
-                domain_registration.domain.as_str(),
-                "AAAA",
-                &new_ip,
-                force,
+        let mut ip_sources: Vec<(Option<String>, IpVersion)> = Vec::new();
+        for domain_registration in domains.iter() {
+            let name = domain_registration.domain.as_str();
+            let ip_versions = domain_registration.ip_versions();
+
+            if let Some(cloudflare_client) = cloudflare_client.as_mut() {
+                report(
+                    format!("{}: Zone exists", name),
+                    check_domain_in_zone(cloudflare_client, name)
+                        .map(|_| ())
+                        .map_err(|e| display_chain(&e)),
+                );
+                for record_type in domain_registration.managed_record_types().into_iter() {
+                    report(
+                        format!("{}: {} record exists", name, record_type),
+                        cloudflare_client
+                            .fetch_cloudflare_dns_record(name, record_type)
+                            .map(|_| ())
+                            .map_err(|e| display_chain(&e)),
+                    );
+                }
+            }
+
+            for ip_version in ip_versions.into_iter() {
+                let source = match ip_version {
+                    IpVersion::V4 => &domain_registration.v4_source,
+                    IpVersion::V6 => &domain_registration.v6_source,
+                };
+                let ip_source = (source.clone(), ip_version);
+                if !ip_sources.contains(&ip_source) {
+                    ip_sources.push(ip_source);
+                }
+            }
+        }
+
+        for (source, ip_version) in ip_sources.iter() {
+            report(
+                format!(
+                    "{} address detected from {}",
+                    ip_version.name(),
+                    source.as_deref().unwrap_or("ip detection service")
+                ),
+                lookup_ip(&config, source, *ip_version).map(|_| ()),
             );
         }
     }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::ChecksFailed(failed)),
+    }
 }
 
-fn check_and_conditionally_update_domain(
+fn check_domain_in_zone(
     cloudflare_client: &mut CloudflareApi,
-    name: &str,
-    record_type: &str,
-    new_ip: &str,
-    force: &bool,
-) {
-    let (old_ip, is_error) = cloudflare_client
-        .fetch_cloudflare_dns_record(name, record_type)
-        .map(|record| (record.content.clone(), false))
-        .unwrap_or(("No DNS Record Found".to_string(), true));
+    domain: &str,
+) -> Result<String, CloudflareError> {
+    // Any domain has to be within a zone of the token to be updated
+    cloudflare_client.zone_id_for_domain(domain)
+}
 
-    if is_error {
-        println!(
-            "{}: {} (Update IP: {})",
-            name, "No DNS Record Found", new_ip
-        );
-    } else {
-        if old_ip != new_ip || *force {
-            let result = cloudflare_client.update_cloudflare_dns_record(name, record_type, &new_ip);
-            if result.is_err() {
-                println!(
-                    "{}: {} (Update IP: {})",
-                    name, "Failed to update DNS Record", new_ip
-                );
-            } else {
-                println!("{}: {} -> {}", name, old_ip, new_ip);
-            }
+fn has_any_tag(domain: &DomainRegistration, tags: &[String]) -> bool {
+    // Without a tag filter every domain matches
+    tags.is_empty() || domain.tags.iter().any(|tag| tags.contains(tag))
+}
+
+fn in_zone(domain: &DomainRegistration, zone: &Option<String>) -> bool {
+    // Without a zone filter every domain matches
+    match zone {
+        Some(zone) => {
+            // Compared in lowercase ASCII form, like the zone filter of the config
+            let zone = to_ascii_domain(zone.trim_end_matches('.'));
+            domain.domain == zone || domain.domain.ends_with(format!(".{}", zone).as_str())
         }
+        None => true,
     }
 }
 
-fn get_ip(ip_type: &str) -> String {
-    // Get the public ip address of the machine via icanhazip.com
-    let client = Client::new();
-    let url = format!("https://{}.icanhazip.com", ip_type);
-    let response = client
-        .get(url)
-        .send()
-        .expect("Unable to fetch data from icanhazip.com")
-        .text()
-        .expect("Unable to parse response from icanhazip.com");
-    response.trim().to_string()
+fn list_profiles(args: &Args) -> Result<(), Error> {
+    // The active profile might not have a config file yet
+    let mut profiles = config::list_profiles(args)?;
+    if !profiles.contains(&args.profile) {
+        profiles.push(args.profile.clone());
+    }
+
+    for profile in profiles.iter() {
+        match *profile == args.profile {
+            true => println!("{} (active)", profile),
+            false => println!("{}", profile),
+        }
+    }
+    Ok(())
 }
 
-fn replace_ipv4_suffix(ip: &str, suffix: &str) -> String {
-    // Replace the end of the ipv4 address with the given suffix
-    let mut ip_parts: Vec<&str> = ip.split(".").collect();
-    let suffix_parts: Vec<&str> = suffix.split(".").collect();
-    ip_parts.splice(ip_parts.len() - suffix_parts.len().., suffix_parts);
-    ip_parts.join(".")
-}
-
-fn replace_ipv6_suffix(ip: &str, suffix: &str) -> String {
-    // Expand ipv6 address if it contains a ::
-    let mut ip_str = ip.to_string();
-    if ip_str.contains("::") {
-        let mut ip_parts: Vec<&str> = ip_str.split("::").collect();
-        let mut suffix_parts: Vec<&str> = ip_parts[1].split(":").collect();
-        let mut prefix_parts: Vec<&str> = ip_parts[0].split(":").collect();
-        let mut missing_parts = 8 - prefix_parts.len() - suffix_parts.len();
-        while missing_parts > 0 {
-            prefix_parts.push("0000");
-            missing_parts -= 1;
-        }
-        ip_parts = prefix_parts;
-        ip_parts.append(&mut suffix_parts);
-        ip_str = ip_parts.join(":");
-    }
-
-    // Replace the end of the ipv6 address with the given suffix
-    let mut ip_parts: Vec<&str> = ip_str.split(":").collect();
-    let suffix_parts: Vec<&str> = suffix.split(":").collect();
-    ip_parts.splice(ip_parts.len() - suffix_parts.len().., suffix_parts);
-    ip_parts.join(":")
+fn config_command_run(args: &Args, config_command: &ConfigCommands) -> Result<(), Error> {
+    let config = Config::new(args)?;
+
+    match config_command {
+        ConfigCommands::Show => {
+            println!("Config file: {}", config.config_file().display());
+            let mut table = Table::new();
+            table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+            table.set_titles(row!["Key", "Value", "Source"]);
+            for config_value in config.effective_values().iter() {
+                table.add_row(row![
+                    config_value.key,
+                    config_value.value,
+                    config_value.source.name()
+                ]);
+            }
+            print_table(args, &table);
+
+            for key in config.unknown_keys() {
+                warn!("Unknown config key {}, is it misspelled?", key);
+            }
+        }
+        ConfigCommands::Validate => {
+            for key in config.unknown_keys() {
+                warn!("Unknown config key {}, is it misspelled?", key);
+            }
+            let errors = config.check_entries();
+            for e in errors.iter() {
+                error!("{}", display_chain(e));
+            }
+            match errors.len() {
+                0 => info!("{} is valid", config.config_file().display()),
+                invalid => return Err(Error::InvalidConfig(invalid)),
+            }
+        }
+        ConfigCommands::Set { key, value } => {
+            config.set_config_entry(key, value)?;
+            info!("Set {} in {:#?}", key, config.config_file());
+        }
+        ConfigCommands::Unset { key } => match config.delete_config_entry(key)? {
+            true => info!("Removed {} from {:#?}", key, config.config_file()),
+            false => warn!("{} is not set in {:#?}", key, config.config_file()),
+        },
+    }
+    Ok(())
+}
+
+fn cloud_command_run(args: &Args, cloud_command: &CloudCommands) -> Result<(), Error> {
+    // domains.json is neither read nor written, only the cache follows the changed records
+    let config = Config::new(args)?;
+    let mut cloudflare_client =
+        CloudflareApi::new(config.read_client_config(config.read_cloudflare_token()?)?)?;
+
+    match cloud_command {
+        CloudCommands::List { zone } => {
+            let zone = zone.as_deref().map(cloud_zone_name);
+            let mut records = cloudflare_client.fetch_cloudflare_zone_dns_records(&zone, &[])?;
+            records.sort_by(|a, b| {
+                (&a.zone, &a.name, &a.record_type).cmp(&(&b.zone, &b.name, &b.record_type))
+            });
+
+            let mut table = Table::new();
+            table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+            table.set_titles(row!["Zone", "Name", "Type", "Content", "TTL", "Proxied"]);
+            for record in records.iter() {
+                table.add_row(row![
+                    record.zone,
+                    record.name,
+                    record.record_type,
+                    record.record.content,
+                    match record.record.ttl {
+                        1 => "Auto".to_string(),
+                        ttl => ttl.to_string(),
+                    },
+                    match record.record.proxied {
+                        true => "Yes",
+                        false => "No",
+                    }
+                ]);
+            }
+            print_table(args, &table);
+        }
+        CloudCommands::Add {
+            zone,
+            name,
+            record_type,
+            content,
+            ttl,
+            proxied,
+            allow_duplicate,
+        } => {
+            let content = &check_record_content(record_type, content)?;
+            let name = cloud_record_in_zone(&mut cloudflare_client, zone, name)?;
+            // Only one record per name and type is tracked, so a second one needs to be asked for
+            let zone_id = cloudflare_client.zone_id_for_domain(&name)?;
+            if !*allow_duplicate
+                && cloudflare_client
+                    .list_dns_records_of_types(&zone_id, &[record_type])?
+                    .iter()
+                    .any(|record| record.name == name)
+            {
+                return Err(Error::RecordExists {
+                    name,
+                    record_type: record_type.clone(),
+                });
+            }
+            cloudflare_client.create_cloudflare_dns_record(
+                &name,
+                record_type,
+                content,
+                ttl,
+                proxied,
+                &None,
+            )?;
+            info!(
+                "Created {} record of {} with {}",
+                record_type, name, content
+            );
+        }
+        CloudCommands::Remove {
+            zone,
+            name,
+            record_type,
+            content,
+        } => {
+            let name = cloud_record_in_zone(&mut cloudflare_client, zone, name)?;
+            let record = cloud_record(&mut cloudflare_client, &name, record_type, content)?;
+            cloudflare_client.delete_dns_record(&record)?;
+            info!(
+                "Deleted {} record of {} with {}",
+                record_type, name, record.content
+            );
+        }
+        CloudCommands::Update {
+            zone,
+            name,
+            record_type,
+            content,
+            old_content,
+        } => {
+            let content = &check_record_content(record_type, content)?;
+            let name = cloud_record_in_zone(&mut cloudflare_client, zone, name)?;
+            let record = cloud_record(&mut cloudflare_client, &name, record_type, old_content)?;
+            let updated_record = cloudflare_client.update_dns_record(
+                &record,
+                content,
+                &Some(record.ttl),
+                &record.proxied,
+                &record.comment,
+            )?;
+            cloudflare_client.cache_dns_record(updated_record);
+            info!(
+                "Updated {} record of {} from {} to {}",
+                record_type, name, record.content, content
+            );
+        }
+    }
+    Ok(())
+}
+
+fn cloud_record(
+    cloudflare_client: &mut CloudflareApi,
+    name: &str,
+    record_type: &str,
+    content: &Option<String>,
+) -> Result<DnsRecord, Error> {
+    // Names can have several records of a type, e.g. after cloud add --allow-duplicate,
+    // so the record is never picked by chance
+    let content = content
+        .as_deref()
+        .map(|content| check_record_content(record_type, content))
+        .transpose()?;
+    let zone_id = cloudflare_client.zone_id_for_domain(name)?;
+    let mut records: Vec<DnsRecord> = cloudflare_client
+        .list_dns_records_of_types(&zone_id, &[record_type])?
+        .into_iter()
+        .filter(|record| record.name == name)
+        .filter(|record| {
+            content
+                .as_ref()
+                .is_none_or(|content| record.content == *content)
+        })
+        .collect();
+    match records.len() {
+        0 => Err(CloudflareError::RecordNotFound {
+            domain: name.to_string(),
+            record_type: record_type.to_string(),
+        }
+        .into()),
+        1 => Ok(records.remove(0)),
+        count => Err(Error::AmbiguousRecord {
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            count,
+        }),
+    }
+}
+
+fn cloud_zone_name(zone: &str) -> String {
+    to_ascii_domain(zone.trim_end_matches('.'))
+}
+
+fn cloud_record_name(zone: &str, name: &str) -> Result<String, Error> {
+    // Relative names are completed with the zone, like in a zone file
+    let name = name.trim_end_matches('.');
+    let name = match name == "@" || name.is_empty() {
+        true => zone.to_string(),
+        false => to_ascii_domain(name),
+    };
+    let name = match name == zone || name.ends_with(&format!(".{}", zone)) {
+        true => name,
+        false => format!("{}.{}", name, zone),
+    };
+    normalize_domain(&name).map(|(name, _)| name)
+}
+
+fn cloud_record_in_zone(
+    cloudflare_client: &mut CloudflareApi,
+    zone: &str,
+    name: &str,
+) -> Result<String, Error> {
+    // Records are always looked up in the most specific zone, which has to be the given one
+    let zone = cloud_zone_name(zone);
+    let name = cloud_record_name(&zone, name)?;
+    match cloudflare_client.zone_name_for_domain(&name)? == zone {
+        true => Ok(name),
+        false => Err(Error::RecordOutsideZone { name, zone }),
+    }
+}
+
+fn parse_cloud_record_type(record_type: &str) -> Result<String, String> {
+    // Any type Cloudflare knows is passed on, e.g. MX or TXT
+    match !record_type.is_empty() && record_type.chars().all(|c| c.is_ascii_alphanumeric()) {
+        true => Ok(record_type.to_uppercase()),
+        false => Err(format!("'{}' is not a record type", record_type)),
+    }
+}
+
+fn check_record_content(record_type: &str, content: &str) -> Result<String, Error> {
+    // Addresses are sent in the form Cloudflare returns them, e.g. 2001:db8::1 for 2001:DB8:0::1.
+    // Cloudflare validates the content of the other record types
+    let checked = match IpVersion::from_record_type(record_type) {
+        Some(IpVersion::V4) => content.parse::<Ipv4Addr>().ok().map(|ip| ip.to_string()),
+        Some(IpVersion::V6) => content.parse::<Ipv6Addr>().ok().map(|ip| ip.to_string()),
+        None => Some(content.to_string()).filter(|content| !content.is_empty()),
+    };
+    match checked {
+        Some(content) => Ok(content),
+        None => Err(Error::InvalidRecordContent {
+            content: content.to_string(),
+            record_type: record_type.to_string(),
+        }),
+    }
+}
+
+fn migrate(args: &Args, output_file: &Path) -> Result<(), Error> {
+    // The new file must be picked up as TOML and must not replace the files it is made from
+    if ConfigFormat::from_path(output_file) != ConfigFormat::Toml {
+        return Err(Error::MigrationTargetNotToml(output_file.to_path_buf()));
+    }
+    if output_file.exists() {
+        return Err(Error::MigrationTargetExists(output_file.to_path_buf()));
+    }
+
+    let config = Config::new(args)?;
+    let migration = config.migrate_to_toml(output_file)?;
+
+    match migration.migrated.is_empty() {
+        true => info!("No config entries migrated"),
+        false => info!("Migrated config entries: {}", migration.migrated.join(", ")),
+    }
+    if !migration.skipped.is_empty() {
+        warn!(
+            "Skipped unknown config entries: {}",
+            migration.skipped.join(", ")
+        );
+    }
+    info!("Migrated {} domains", migration.domains);
+    info!(
+        "Wrote {}, set CONFIG_PATH or --config-file to it to use the new config",
+        output_file.display()
+    );
+    Ok(())
+}
+
+fn completions(shell: &Shell, install: &bool) -> Result<(), Error> {
+    let mut command = Args::command();
+    if !*install {
+        generate(*shell, &mut command, BINARY_NAME, &mut io::stdout());
+        return Ok(());
+    }
+
+    let path = completions_path(shell)?;
+    let io_error = |source| Error::Completions {
+        path: path.clone(),
+        source,
+    };
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).map_err(io_error)?;
+    }
+    let mut script = Vec::new();
+    generate(*shell, &mut command, BINARY_NAME, &mut script);
+    fs::write(&path, script).map_err(io_error)?;
+    info!("Installed {} completions to {:?}", shell, path);
+    Ok(())
+}
+
+fn completions_path(shell: &Shell) -> Result<PathBuf, Error> {
+    // The directories the shells search by default, zsh only searches the ones in $fpath
+    let home = env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or(Error::NoHomeDirectory)?;
+    let xdg_dir = |variable: &str, default: &str| {
+        env::var_os(variable)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join(default))
+    };
+    match shell {
+        Shell::Bash => Ok(xdg_dir("XDG_DATA_HOME", ".local/share")
+            .join("bash-completion/completions")
+            .join(BINARY_NAME)),
+        Shell::Zsh => Ok(home.join(".zfunc").join(format!("_{}", BINARY_NAME))),
+        Shell::Fish => Ok(xdg_dir("XDG_CONFIG_HOME", ".config")
+            .join("fish/completions")
+            .join(format!("{}.fish", BINARY_NAME))),
+        shell => Err(Error::CompletionsNotInstallable(shell.to_string())),
+    }
+}
+
+fn cache(args: &Args, clear: &bool, evict_zone: &Option<String>) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let cache_file = config.read_cache_file_path();
+
+    if !cache_file.exists() {
+        warn!("No cache file found at {:#?}", cache_file);
+        return Ok(());
+    }
+
+    if *clear {
+        fs::remove_file(&cache_file).map_err(|source| CloudflareError::CacheIo {
+            path: cache_file.clone(),
+            source,
+        })?;
+        info!("Cleared cache {:#?}", cache_file);
+        return Ok(());
+    }
+
+    let mut cache = Cache::load(&cache_file)?;
+    if let Some(zone) = evict_zone {
+        let zone_id =
+            cache
+                .get_zone_id(zone)
+                .cloned()
+                .ok_or_else(|| CloudflareError::ZoneNotFound {
+                    domain: zone.clone(),
+                })?;
+        cache.evict_zone(&zone_id);
+        cache.save(&cache_file)?;
+        info!("Evicted zone '{}' from cache {:#?}", zone, cache_file);
+    }
+    info!(
+        "Cache {:#?} contains {} zones and {} DNS records",
+        cache_file,
+        cache.zone_count(),
+        cache.dns_record_count()
+    );
+    Ok(())
+}
+
+fn delete_domain(args: &Args, domain: &str) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let domain = &to_ascii_domain(domain);
+    let mut domains = config.read_domains()?;
+
+    let orig_length = domains.len();
+    domains.retain(|x| x.domain != *domain);
+
+    if domains.len() == orig_length {
+        return Err(Error::DomainNotRegistered(domain.clone()));
+    }
+
+    config.write_domains(&domains)?;
+    evict_domain_or_warn(&config, domain);
+    info!("Deleted domain '{}' successfully", domain);
+    Ok(())
+}
+
+fn purge_domain(args: &Args, domain: &str, force: &bool) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let mut domains = config.read_domains()?;
+
+    let domain = &to_ascii_domain(domain);
+    let domain_registration = domains
+        .iter()
+        .find(|x| x.domain == *domain)
+        .ok_or_else(|| Error::DomainNotRegistered(domain.clone()))?;
+
+    if !*force
+        && !confirm(format!(
+            "Delete the DNS records of '{}' from Cloudflare?",
+            domain
+        ))?
+    {
+        info!("Aborted, nothing was deleted");
+        return Ok(());
+    }
+
+    let mut cloudflare_client =
+        CloudflareApi::new(config.read_client_config(config.read_cloudflare_token()?)?)?;
+    for record_type in domain_registration.managed_record_types().into_iter() {
+        match cloudflare_client.delete_cloudflare_dns_record(domain, record_type) {
+            Ok(_) => info!("{}: Deleted {} Record", domain, record_type),
+            Err(CloudflareError::RecordNotFound { .. } | CloudflareError::ZoneNotFound { .. }) => {
+                info!("{}: No {} Record Found, skipping", domain, record_type)
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    domains.retain(|x| x.domain != *domain);
+    config.write_domains(&domains)?;
+    evict_domain_or_warn(&config, domain);
+    info!("Deleted domain '{}' successfully", domain);
+    Ok(())
+}
+
+fn confirm(question: String) -> Result<bool, Error> {
+    // Ask on the terminal, only an explicit yes confirms
+    print!("{} [y/N] ", question);
+    io::stdout().flush().map_err(Error::Prompt)?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).map_err(Error::Prompt)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn edit_domain(args: &Args, edit_args: &EditArgs) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let mut domains = config.read_domains()?;
+    let domain = to_ascii_domain(&edit_args.domain);
+    let domain = domain.as_str();
+
+    let domain_registration = domains
+        .iter_mut()
+        .find(|x| x.domain == domain)
+        .ok_or_else(|| Error::DomainNotRegistered(domain.to_string()))?;
+
+    // Only touch the fields given on the command line and log every change
+    let mut changed = false;
+    if let Some(ref v4_suffix) = edit_args.v4_suffix {
+        let v4_suffix = Some(v4_suffix.clone()).filter(|s| !s.is_empty());
+        changed |= edit_field(
+            domain,
+            "v4_suffix",
+            &mut domain_registration.v4_suffix,
+            v4_suffix,
+        );
+    }
+    if let Some(ref v6_suffix) = edit_args.v6_suffix {
+        let v6_suffix = Some(v6_suffix.clone()).filter(|s| !s.is_empty());
+        changed |= edit_field(
+            domain,
+            "v6_suffix",
+            &mut domain_registration.v6_suffix,
+            v6_suffix,
+        );
+    }
+    if let Some(ref v6_prefix_source) = edit_args.v6_prefix_source {
+        let v6_prefix_source = match v6_prefix_source.is_empty() {
+            true => None,
+            false => Some(parse_prefix_source(v6_prefix_source)?),
+        };
+        changed |= edit_field(
+            domain,
+            "v6_prefix_source",
+            &mut domain_registration.v6_prefix_source,
+            v6_prefix_source,
+        );
+    }
+    if let Some(disable_v4) = edit_args.disable_v4 {
+        changed |= edit_field(
+            domain,
+            "v4_disabled",
+            &mut domain_registration.v4_disabled,
+            disable_v4,
+        );
+        if !disable_v4 {
+            changed |= domain_registration.enable_record_type("A");
+        }
+    }
+    if let Some(disable_v6) = edit_args.disable_v6 {
+        changed |= edit_field(
+            domain,
+            "v6_disabled",
+            &mut domain_registration.v6_disabled,
+            disable_v6,
+        );
+        if !disable_v6 {
+            changed |= domain_registration.enable_record_type("AAAA");
+        }
+    }
+    if let Some(ttl) = edit_args.ttl {
+        changed |= edit_field(domain, "ttl", &mut domain_registration.ttl, Some(ttl));
+    }
+    if let Some(proxied) = edit_args.proxied {
+        changed |= edit_field(domain, "proxied", &mut domain_registration.proxied, proxied);
+    }
+    if let Some(ref comment) = edit_args.comment {
+        let comment = Some(comment.as_str())
+            .filter(|c| !c.is_empty())
+            .map(truncate_comment);
+        changed |= edit_field(domain, "comment", &mut domain_registration.comment, comment);
+    }
+
+    if !changed {
+        info!("Domain '{}' is unchanged", domain);
+        return Ok(());
+    }
+
+    // Enabling an ip version must not add an address record next to a CNAME record
+    check_record_types(domain_registration)?;
+    config.write_domains(&domains)?;
+    info!("Edited domain '{}' successfully", domain);
+    Ok(())
+}
+
+fn truncate_comment(comment: &str) -> String {
+    // Longer comments are rejected by Cloudflare for free zones
+    match comment.chars().count() > MAX_COMMENT_LENGTH {
+        true => {
+            warn!(
+                "Comment is longer than {} characters, truncating it",
+                MAX_COMMENT_LENGTH
+            );
+            comment.chars().take(MAX_COMMENT_LENGTH).collect()
+        }
+        false => comment.to_string(),
+    }
+}
+
+fn edit_field<T: PartialEq + Debug>(domain: &str, name: &str, field: &mut T, value: T) -> bool {
+    if *field == value {
+        return false;
+    }
+    info!("{}: {} {:?} -> {:?}", domain, name, field, value);
+    *field = value;
+    true
+}
+
+fn register_domain(args: &Args, mut new_domain: DomainRegistration) -> Result<(), Error> {
+    (new_domain.domain, new_domain.display_name) = normalize_domain(&new_domain.domain)?;
+    check_record_types(&new_domain)?;
+
+    let config = Config::new(args)?;
+    let mut domains = config.read_domains()?;
+    let domain = new_domain.domain.clone();
+
+    //Check if domain is already registered
+    for registered_domain in domains.iter() {
+        if registered_domain.domain == domain {
+            return Err(Error::DomainAlreadyRegistered(domain));
+        }
+    }
+
+    if new_domain.is_disabled() {
+        warn!(
+            "All record types of '{}' are disabled, updates will skip it",
+            domain
+        );
+    }
+    domains.push(new_domain);
+
+    // Write the new domains.json file
+    config.write_domains(&domains)?;
+    info!("Registered domain '{}' successfully", domain);
+    Ok(())
+}
+
+fn check_record_types(domain_registration: &DomainRegistration) -> Result<(), Error> {
+    // Cloudflare rejects a CNAME next to other records of the same name
+    let invalid = |reason: String| {
+        Err(Error::InvalidRecordTypes {
+            domain: domain_registration.domain.clone(),
+            reason,
+        })
+    };
+    let record_types = &domain_registration.record_types;
+    if let Some(record_type) = record_types
+        .iter()
+        .find(|record_type| !SUPPORTED_RECORD_TYPES.contains(&record_type.as_str()))
+    {
+        return invalid(format!(
+            "{} is not supported, expected one of {}",
+            record_type,
+            SUPPORTED_RECORD_TYPES.join(", ")
+        ));
+    }
+
+    let cname = record_types
+        .iter()
+        .any(|record_type| record_type == "CNAME");
+    match (cname, &domain_registration.cname_target) {
+        (true, None) => invalid("a CNAME record needs --cname-target".to_string()),
+        (false, Some(_)) => invalid("--cname-target needs the CNAME record type".to_string()),
+        (true, Some(_)) if record_types.len() > 1 => {
+            invalid("a CNAME record can not be combined with other record types".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn rename_domain(args: &Args, old_domain: &str, new_domain: &str) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let old_domain = to_ascii_domain(old_domain);
+    let (new_domain, display_name) = normalize_domain(new_domain)?;
+    let mut domains = config.read_domains()?;
+
+    if domains.iter().any(|x| x.domain == new_domain) {
+        return Err(Error::DomainAlreadyRegistered(new_domain));
+    }
+    let domain_registration = domains
+        .iter_mut()
+        .find(|x| x.domain == old_domain)
+        .ok_or_else(|| Error::DomainNotRegistered(old_domain.clone()))?;
+
+    // The record of the new name was never updated by us, so forget the last update
+    domain_registration.domain = new_domain.clone();
+    domain_registration.display_name = display_name;
+    domain_registration.last_v4 = None;
+    domain_registration.last_v6 = None;
+    domain_registration.last_update = None;
+    config.write_domains(&domains)?;
+
+    evict_domain_or_warn(&config, &old_domain);
+    info!("Renamed domain '{}' to '{}'", old_domain, new_domain);
+    Ok(())
+}
+
+fn evict_domain_or_warn(config: &Config, domain: &str) {
+    // The domain is already changed in domains.json, a stale cache only costs a lookup
+    if let Err(e) = evict_cached_records(config, domain) {
+        warn!(
+            "Unable to remove '{}' from cache: {}",
+            domain,
+            display_chain(&e)
+        );
+    }
+}
+
+fn evict_cached_records(config: &Config, domain: &str) -> Result<(), CloudflareError> {
+    let cache_file = config.read_cache_file_path();
+    if !cache_file.exists() {
+        return Ok(());
+    }
+
+    let mut cache = Cache::load(&cache_file)?;
+    cache.evict_domain(domain);
+    cache.save(&cache_file)
+}
+
+fn normalize_domain(domain: &str) -> Result<(String, Option<String>), Error> {
+    // Internationalized domains are stored in their ASCII form, the Unicode form is only displayed
+    let invalid_domain = |reason: String| Error::InvalidDomain {
+        domain: domain.to_string(),
+        reason,
+    };
+    let ascii_domain = idna::domain_to_ascii(domain)
+        .map_err(|_| invalid_domain("not a valid internationalized domain name".to_string()))?;
+    validate_domain_name(&ascii_domain).map_err(invalid_domain)?;
+
+    let (unicode_domain, _) = idna::domain_to_unicode(&ascii_domain);
+    let display_name = match unicode_domain != ascii_domain {
+        true => Some(unicode_domain),
+        false => None,
+    };
+    Ok((ascii_domain, display_name))
+}
+
+fn validate_domain_name(domain: &str) -> Result<(), String> {
+    // Punycode labels (xn--) pass as they only use letters, digits and hyphens
+    if domain.is_empty() {
+        return Err("domain is empty".to_string());
+    }
+    if domain.len() > 253 {
+        return Err("domain is longer than 253 characters".to_string());
+    }
+    for (index, label) in domain.split('.').enumerate() {
+        // Cloudflare supports wildcard records like *.example.com
+        if index == 0 && label == "*" {
+            continue;
+        }
+        if label.is_empty() {
+            return Err("domain contains an empty label".to_string());
+        }
+        if let Some(c) = label
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && *c != '-')
+        {
+            return Err(format!(
+                "label '{}' contains invalid character '{}'",
+                label, c
+            ));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("label '{}' starts or ends with a hyphen", label));
+        }
+    }
+    Ok(())
+}
+
+/// Entry of the `list --output json` output
+#[derive(Serialize)]
+struct ListEntry<'a> {
+    #[serde(flatten)]
+    domain: &'a DomainRegistration,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    records: Option<ListRecords>,
+}
+
+#[derive(Serialize)]
+struct ListRecords {
+    a: Option<DnsRecord>,
+    aaaa: Option<DnsRecord>,
+}
+
+fn list_domains(
+    args: &Args,
+    debug: &bool,
+    output: &OutputFormat,
+    zone: &Option<String>,
+    tags: &[String],
+) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let mut domains = config.read_domains()?;
+    domains.retain(|domain| in_zone(domain, zone) && has_any_tag(domain, tags));
+
+    let mut cloudflare_client: CloudflareApi = match *debug {
+        true => CloudflareApi::new(config.read_client_config(config.read_cloudflare_token()?)?)?,
+        false => CloudflareApi::new(config.read_client_config(String::new())?)?, // Token is not needed for listing domains
+    };
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    let header = match *debug {
+        true => row![
+            "Domain", "IPv4", "ID4", "TTL4", "IPv6", "ID6", "TTL6", "Proxied", "Comment", "Tags"
+        ],
+        false => row!["Domain", "IPv4", "IPv6", "Proxied", "Comment", "Tags"],
+    };
+
+    table.set_titles(header);
+
+    let mut entries: Vec<ListEntry> = Vec::new();
+    for domain in domains.iter() {
+        if *output == OutputFormat::Json {
+            let records = match *debug {
+                true => Some(ListRecords {
+                    a: cloudflare_client
+                        .fetch_cloudflare_dns_record(domain.domain.as_str(), "A")
+                        .ok()
+                        .cloned(),
+                    aaaa: cloudflare_client
+                        .fetch_cloudflare_dns_record(domain.domain.as_str(), "AAAA")
+                        .ok()
+                        .cloned(),
+                }),
+                false => None,
+            };
+            entries.push(ListEntry { domain, records });
+            continue;
+        }
+
+        let v4_string: &str = match domain.v4_disabled {
+            true => "Disabled",
+            false => match domain.v4_suffix {
+                Some(ref suffix) => suffix,
+                None => "Default",
+            },
+        };
+        let v6_string: &str = match domain.v6_disabled {
+            true => "Disabled",
+            false => match domain.v6_suffix {
+                Some(ref suffix) => suffix,
+                None => "Default",
+            },
+        };
+
+        let proxied_string = match domain.proxied {
+            true => "Yes",
+            false => "No",
+        };
+
+        let name = domain.display_name.as_ref().unwrap_or(&domain.domain);
+        let name = match domain.is_disabled() {
+            true => format!("{} [DISABLED]", name),
+            false => name.to_string(),
+        };
+        let comment = domain.comment.as_deref().unwrap_or("-");
+        let tags = match domain.tags.is_empty() {
+            true => "-".to_string(),
+            false => domain.tags.join(", "),
+        };
+        let mut row = row![name, v4_string, v6_string, proxied_string, comment, tags];
+        if *debug {
+            let (domain_id_4, ttl_4) = cloudflare_client
+                .fetch_cloudflare_dns_record(domain.domain.as_str(), "A")
+                .map(|record| (record.id.clone(), record.ttl.to_string()))
+                .unwrap_or(("Not Found".to_string(), "-".to_string()));
+            let (domain_id_6, ttl_6) = cloudflare_client
+                .fetch_cloudflare_dns_record(domain.domain.as_str(), "AAAA")
+                .map(|record| (record.id.clone(), record.ttl.to_string()))
+                .unwrap_or(("Not Found".to_string(), "-".to_string()));
+            row.insert_cell(2, Cell::new(domain_id_4.as_str()));
+            row.insert_cell(3, Cell::new(ttl_4.as_str()));
+            row.insert_cell(5, Cell::new(domain_id_6.as_str()));
+            row.insert_cell(6, Cell::new(ttl_6.as_str()));
+        }
+        table.add_row(row);
+    }
+
+    match output {
+        OutputFormat::Table => {
+            print_table(args, &table);
+        }
+        OutputFormat::Json => {
+            println!(
+                "{}",
+                to_string_pretty(&entries).expect("Unable to serialize domains")
+            );
+        }
+        OutputFormat::Csv => {
+            let writer = WriterBuilder::new()
+                .terminator(Terminator::CRLF)
+                .from_writer(io::stdout());
+            if let Err(e) = table.to_csv_writer(writer) {
+                error!("Unable to write CSV (Error: {})", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Entry of the `status --format json|yaml` output
+#[derive(Serialize)]
+struct RecordStatus {
+    domain: String,
+    record_type: &'static str,
+    cloudflare_ip: Option<String>,
+    local_ip: Option<String>,
+    in_sync: bool,
+    ttl: Option<u32>,
+    proxied: Option<bool>,
+    status: String,
+}
+
+fn domain_status(args: &Args, domain: &str, format: &DetailFormat) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let domains = config.read_domains()?;
+
+    let domain = &to_ascii_domain(domain);
+    let domain_registration = domains
+        .iter()
+        .find(|x| x.domain == *domain)
+        .ok_or_else(|| Error::DomainNotRegistered(domain.clone()))?;
+
+    let mut cloudflare_client =
+        CloudflareApi::new(config.read_client_config(config.read_cloudflare_token()?)?)?;
+    let mut detected_ips: DetectedIps = HashMap::new();
+
+    let mut statuses = Vec::new();
+    for ip_version in [IpVersion::V4, IpVersion::V6] {
+        let disabled = match ip_version {
+            IpVersion::V4 => domain_registration.v4_disabled,
+            IpVersion::V6 => domain_registration.v6_disabled,
+        };
+        let current_ip = match disabled {
+            true => Err("Disabled".to_string()),
+            false => domain_ip(&config, &mut detected_ips, domain_registration, ip_version),
+        };
+        statuses.push(record_status(
+            &mut cloudflare_client,
+            domain,
+            ip_version.record_type(),
+            current_ip,
+        ));
+    }
+
+    if *format != DetailFormat::Table {
+        print_serialized(&statuses, format);
+        return Ok(());
+    }
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row!["Type", "Cloudflare IP", "Current IP", "Status"]);
+    let or_none = |value: &Option<String>| value.clone().unwrap_or("-".to_string());
+    for status in statuses.iter() {
+        table.add_row(row![
+            status.record_type,
+            or_none(&status.cloudflare_ip),
+            or_none(&status.local_ip),
+            status.status
+        ]);
+    }
+    print_table(args, &table);
+    Ok(())
+}
+
+fn record_status(
+    cloudflare_client: &mut CloudflareApi,
+    name: &str,
+    record_type: &'static str,
+    current_ip: Result<String, String>,
+) -> RecordStatus {
+    // Without a current ip the record is not looked up, the error is the status
+    let mut status = RecordStatus {
+        domain: name.to_string(),
+        record_type,
+        cloudflare_ip: None,
+        local_ip: None,
+        in_sync: false,
+        ttl: None,
+        proxied: None,
+        status: String::new(),
+    };
+    let current_ip = match current_ip {
+        Ok(current_ip) => current_ip,
+        Err(e) => {
+            status.status = e;
+            return status;
+        }
+    };
+
+    match cloudflare_client.fetch_cloudflare_dns_record(name, record_type) {
+        Ok(record) => {
+            status.in_sync = record.content == current_ip;
+            status.status = match status.in_sync {
+                true => "Up-to-date".to_string(),
+                false => "Stale".to_string(),
+            };
+            status.cloudflare_ip = Some(record.content.clone());
+            status.ttl = Some(record.ttl);
+            status.proxied = Some(record.proxied);
+        }
+        Err(_) => status.status = "No record found, would create on next update".to_string(),
+    }
+    status.local_ip = Some(current_ip);
+    status
+}
+
+fn print_serialized<T: Serialize>(value: &T, format: &DetailFormat) {
+    match format {
+        DetailFormat::Yaml => print!(
+            "{}",
+            serde_yaml::to_string(value).expect("Unable to serialize output")
+        ),
+        _ => println!(
+            "{}",
+            to_string_pretty(value).expect("Unable to serialize output")
+        ),
+    }
+}
+
+fn parse_resolver(resolver: &str) -> Result<SocketAddr, String> {
+    // The port is optional, e.g. 8.8.8.8 or [2606:4700:4700::1111]:53
+    resolver
+        .parse::<SocketAddr>()
+        .or_else(|_| resolver.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+        .map_err(|_| format!("'{}' is not an ip address", resolver))
+}
+
+fn verify_domain(
+    args: &Args,
+    domain: &str,
+    resolver: &SocketAddr,
+    timeout_secs: &u64,
+) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let domains = config.read_domains()?;
+    let domain = &to_ascii_domain(domain);
+
+    let domain_registration = domains
+        .iter()
+        .find(|x| x.domain == *domain)
+        .ok_or_else(|| Error::DomainNotRegistered(domain.clone()))?;
+
+    let mut cloudflare_client =
+        CloudflareApi::new(config.read_client_config(config.read_cloudflare_token()?)?)?;
+
+    // Proxied records resolve to Cloudflare's addresses, so they can not be compared
+    let mut failed = 0;
+    for ip_version in domain_registration.ip_versions().into_iter() {
+        let record_type = ip_version.record_type();
+        let record = cloudflare_client.fetch_cloudflare_dns_record(domain, record_type)?;
+        if record.proxied {
+            println!("[SKIP] {} {}: Record is proxied", domain, record_type);
+            continue;
+        }
+
+        let expected = record.content.clone();
+        let addresses = resolve(
+            resolver,
+            domain,
+            ip_version,
+            Duration::from_secs(*timeout_secs),
+        )?;
+        let matches = addresses
+            .iter()
+            .any(|address| IpAddr::from_str(&expected).is_ok_and(|expected| expected == *address));
+        match matches {
+            true => println!("[PASS] {} {}: {}", domain, record_type, expected),
+            false => {
+                let found: Vec<String> = addresses.iter().map(|a| a.to_string()).collect();
+                println!(
+                    "[FAIL] {} {}: Expected {}, {} returned {}",
+                    domain,
+                    record_type,
+                    expected,
+                    resolver,
+                    match found.is_empty() {
+                        true => "nothing".to_string(),
+                        false => found.join(", "),
+                    }
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::NotPropagated(failed)),
+    }
+}
+
+fn diff_domains(args: &Args, zone: &Option<String>, tags: &[String]) -> Result<(), Error> {
+    // Compares every record like a forced update would, the last update is not looked at
+    let config = Config::new(args)?;
+    let domains = config.read_domains()?;
+    let ttl_limits = read_ttl_limits(&config, None, None)?;
+    let default_ttl = read_ttl(&config, "default_ttl");
+
+    let mut cloudflare_client =
+        CloudflareApi::new(config.read_client_config(config.read_cloudflare_token()?)?)?;
+    let mut detected_ips: DetectedIps = HashMap::new();
+
+    let mut changes = 0;
+    for domain_registration in domains.iter() {
+        if !in_zone(domain_registration, zone) || !has_any_tag(domain_registration, tags) {
+            continue;
+        }
+        let domain_registration = &DomainRegistration {
+            ttl: domain_registration.ttl.or(default_ttl),
+            ..domain_registration.clone()
+        };
+        let name = domain_registration.domain.as_str();
+        for record_type in domain_registration.managed_record_types().into_iter() {
+            let new_ip = match record_content(
+                &config,
+                &mut detected_ips,
+                domain_registration,
+                record_type,
+            ) {
+                Ok(new_ip) => new_ip,
+                Err(e) => {
+                    warn!("{}: Skipping {} record: {}", name, record_type, e);
+                    continue;
+                }
+            };
+            match cloudflare_client.fetch_cloudflare_dns_record(name, record_type) {
+                Ok(record) => {
+                    match record_needs_update(
+                        record,
+                        domain_registration,
+                        &new_ip,
+                        &ttl_limits,
+                        &false,
+                    ) {
+                        true => {
+                            println!(
+                                "~ {} {} {} -> {}",
+                                name, record_type, record.content, new_ip
+                            );
+                            changes += 1;
+                        }
+                        false => println!("= {} {} {}", name, record_type, record.content),
+                    }
+                }
+                Err(CloudflareError::RecordNotFound { .. }) => {
+                    println!("+ {} {} {}", name, record_type, new_ip);
+                    changes += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    match changes {
+        0 => Ok(()),
+        changes => Err(Error::ChangesPending(changes)),
+    }
+}
+
+/// Output of `show --format json|yaml`
+#[derive(Serialize)]
+struct DomainDetails<'a> {
+    #[serde(flatten)]
+    domain: &'a DomainRegistration,
+    records: Vec<RecordDetails>,
+}
+
+#[derive(Serialize)]
+struct RecordDetails {
+    record_type: &'static str,
+    record: Option<DnsRecord>,
+    current_ip: Option<String>,
+    status: String,
+}
+
+fn show_domain(args: &Args, domain: &str, format: &DetailFormat) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let domains = config.read_domains()?;
+
+    let domain = &to_ascii_domain(domain);
+    let domain_registration = domains
+        .iter()
+        .find(|x| x.domain == *domain)
+        .ok_or_else(|| Error::DomainNotRegistered(domain.clone()))?;
+
+    let mut cloudflare_client =
+        CloudflareApi::new(config.read_client_config(config.read_cloudflare_token()?)?)?;
+    let mut detected_ips: DetectedIps = HashMap::new();
+
+    let or_none = |value: &Option<String>| value.clone().unwrap_or("-".to_string());
+    let mut entries: Vec<(String, String)> = vec![
+        ("Domain".to_string(), domain_registration.domain.clone()),
+        (
+            "Display name".to_string(),
+            or_none(&domain_registration.display_name),
+        ),
+        (
+            "IPv4 disabled".to_string(),
+            domain_registration.v4_disabled.to_string(),
+        ),
+        (
+            "IPv4 suffix".to_string(),
+            or_none(&domain_registration.v4_suffix),
+        ),
+        (
+            "IPv4 source".to_string(),
+            or_none(&domain_registration.v4_source),
+        ),
+        (
+            "IPv6 disabled".to_string(),
+            domain_registration.v6_disabled.to_string(),
+        ),
+        (
+            "IPv6 suffix".to_string(),
+            or_none(&domain_registration.v6_suffix),
+        ),
+        (
+            "IPv6 source".to_string(),
+            or_none(&domain_registration.v6_source),
+        ),
+        (
+            "IPv6 prefix source".to_string(),
+            or_none(&domain_registration.v6_prefix_source),
+        ),
+        (
+            "TTL".to_string(),
+            domain_registration
+                .ttl
+                .map(|ttl| ttl.to_string())
+                .unwrap_or("Automatic".to_string()),
+        ),
+        (
+            "Proxied".to_string(),
+            domain_registration.proxied.to_string(),
+        ),
+        (
+            "Record types".to_string(),
+            match domain_registration.record_types.is_empty() {
+                true => "-".to_string(),
+                false => domain_registration.record_types.join(", "),
+            },
+        ),
+        (
+            "Tags".to_string(),
+            match domain_registration.tags.is_empty() {
+                true => "-".to_string(),
+                false => domain_registration.tags.join(", "),
+            },
+        ),
+        (
+            "Single zone".to_string(),
+            domain_registration.single_zone.to_string(),
+        ),
+        (
+            "Webhook URL".to_string(),
+            or_none(&domain_registration.webhook_url),
+        ),
+        ("Comment".to_string(), or_none(&domain_registration.comment)),
+        (
+            "Last IPv4".to_string(),
+            or_none(&domain_registration.last_v4),
+        ),
+        (
+            "Last IPv6".to_string(),
+            or_none(&domain_registration.last_v6),
+        ),
+        (
+            "Last update".to_string(),
+            domain_registration
+                .last_update
+                .map(|last_update| last_update.to_string())
+                .unwrap_or("-".to_string()),
+        ),
+    ];
+
+    let ip_versions = domain_registration.ip_versions();
+    let mut records = Vec::new();
+    for ip_version in [IpVersion::V4, IpVersion::V6] {
+        let record_type = ip_version.record_type();
+        let current_ip = match ip_versions.contains(&ip_version) {
+            true => domain_ip(&config, &mut detected_ips, domain_registration, ip_version),
+            false => Err("Disabled".to_string()),
+        };
+
+        let record = cloudflare_client
+            .fetch_cloudflare_dns_record(domain, record_type)
+            .map_err(|e| display_chain(&e));
+        let status = match (&record, &current_ip) {
+            (_, Err(_)) => "-".to_string(),
+            (Ok(record), Ok(current_ip)) => match record.content == *current_ip {
+                true => "In sync".to_string(),
+                false => "Out of sync".to_string(),
+            },
+            (Err(_), Ok(_)) => "No record found, would create on next update".to_string(),
+        };
+
+        records.push(RecordDetails {
+            record_type,
+            record: record.clone().ok().cloned(),
+            current_ip: current_ip.clone().ok(),
+            status: status.clone(),
+        });
+        match record {
+            Ok(record) => {
+                entries.push((format!("{} record ID", record_type), record.id.clone()));
+                entries.push((
+                    format!("{} record content", record_type),
+                    record.content.clone(),
+                ));
+                entries.push((
+                    format!("{} record TTL", record_type),
+                    record.ttl.to_string(),
+                ));
+                entries.push((
+                    format!("{} record proxied", record_type),
+                    record.proxied.to_string(),
+                ));
+            }
+            Err(e) => entries.push((format!("{} record", record_type), e)),
+        }
+        entries.push((
+            match ip_version {
+                IpVersion::V4 => "Current IPv4".to_string(),
+                IpVersion::V6 => "Current IPv6".to_string(),
+            },
+            current_ip.unwrap_or_else(|e| e),
+        ));
+        entries.push((format!("{} status", record_type), status));
+    }
+
+    if *format != DetailFormat::Table {
+        print_serialized(
+            &DomainDetails {
+                domain: domain_registration,
+                records,
+            },
+            format,
+        );
+        return Ok(());
+    }
+
+    // Align the values in a second column
+    let width = entries.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    for (key, value) in entries.iter() {
+        println!(
+            "{:width$}  {}",
+            format!("{}:", key),
+            value,
+            width = width + 1
+        );
+    }
+    Ok(())
+}
+
+fn run_periodically(args: &Args, update_args: &UpdateArgs, interval: u64) -> Result<(), Error> {
+    // Like the daemon, but without pid file and without touching the config file
+    let config = Config::new(args)?;
+    let daemon = Daemon::new(interval, None)?;
+
+    info!("Updating every {} seconds", interval);
+    let mut force = force_on_startup(&config, update_args);
+    daemon.run(|| {
+        if let Err(e) = update_domains(args, update_args, &force, None) {
+            error!("{}", display_chain(&e));
+        }
+        force = update_args.force;
+    });
+    Ok(())
+}
+
+fn run_daemon(args: &Args, update_args: &UpdateArgs) -> Result<(), Error> {
+    let config = Config::new(args)?;
+
+    let interval = match update_args.interval {
+        Some(interval) => {
+            if let Err(e) = config.set_config_entry("interval", interval.to_string().as_str()) {
+                error!("Error while writing config file: {}", display_chain(&e));
+            }
+            interval
+        }
+        None => config
+            .read_config_entry("interval")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_INTERVAL),
+    };
+
+    let pid_file = update_args
+        .pid_file
+        .clone()
+        .or_else(|| config.read_config_entry("pid_file").map(|v| v.into()));
+
+    let daemon = Daemon::new(interval, pid_file)?;
+
+    // Without metrics the daemon is still useful, so a busy port only stops the metrics server
+    let metrics_port = update_args
+        .metrics_port
+        .or_else(|| {
+            config
+                .read_config_entry("metrics_port")
+                .and_then(|v| v.parse::<u16>().ok())
+        })
+        .unwrap_or(DEFAULT_METRICS_PORT);
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    if let Err(e) = metrics::serve(metrics_port, metrics.clone()) {
+        error!("{}", display_chain(&e));
+    }
+
+    // A failed update must not stop the daemon, the next one might succeed
+    info!("Starting daemon, updating every {} seconds", interval);
+    let mut force = force_on_startup(&config, update_args);
+    daemon.run(|| {
+        if let Err(e) = update_domains(args, update_args, &force, Some(&metrics)) {
+            error!("{}", display_chain(&e));
+        }
+        force = update_args.force;
+    });
+    Ok(())
+}
+
+fn force_on_startup(config: &Config, update_args: &UpdateArgs) -> bool {
+    // After a restart the last ips might match while the DNS records were changed meanwhile
+    update_args.force
+        || update_args.update_on_startup
+        || config
+            .read_config_entry("update_on_startup")
+            .is_some_and(|v| v == "true")
+}
+
+fn update_domains(
+    args: &Args,
+    update_args: &UpdateArgs,
+    force: &bool,
+    metrics: Option<&Mutex<Metrics>>,
+) -> Result<(), Error> {
+    let mut config = Config::new(args)?;
+    if update_args.stun {
+        config.enable_stun();
+    }
+    if let Some(ip) = update_args.ip_v4 {
+        config.override_ip(IpVersion::V4, ip.to_string());
+    }
+    if let Some(ip) = update_args.ip_v6 {
+        config.override_ip(IpVersion::V6, ip.to_string());
+    }
+
+    // Concurrent runs would read the same last update and write the same records twice
+    let _lock = match LockFile::acquire(&config.read_lock_file_path()) {
+        Ok(lock) => lock,
+        Err(e @ LockError::Held { .. }) => {
+            warn!("{}, skipping update", e);
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut domains = config.read_domains()?;
+    check_registrations(&domains);
+
+    let ttl_limits = read_ttl_limits(&config, update_args.min_ttl, update_args.max_ttl)?;
+    let default_ttl = read_ttl(&config, "default_ttl");
+
+    let mut detected_ips: DetectedIps = HashMap::new();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    // Resolving the zones of the domains needs the zones of the token, so only with a zone filter
+    let zone_filter = config.read_zone_filter();
+    let mut zone_client = match zone_filter {
+        Some(_) => Some(CloudflareApi::new(
+            config.read_client_config(config.read_cloudflare_token()?)?,
+        )?),
+        None => None,
+    };
+
+    let mut updates: Vec<(DomainRegistration, &'static str, String)> = Vec::new();
+    let mut errors: Vec<String> = Vec::new();
+    let mut matched = 0;
+    for domain_registration in domains.iter() {
+        if !in_zone(domain_registration, &update_args.zone)
+            || !has_any_tag(domain_registration, &update_args.tags)
+        {
+            continue;
+        }
+        if let (Some(zone_filter), Some(zone_client)) = (&zone_filter, zone_client.as_mut()) {
+            match zone_client.zone_name_for_domain(&domain_registration.domain) {
+                Ok(zone) if zone == *zone_filter => matched += 1,
+                Ok(zone) => {
+                    info!(
+                        "{}: Zone {} does not match the zone filter, skipping",
+                        domain_registration.domain, zone
+                    );
+                    continue;
+                }
+                Err(e @ CloudflareError::ZoneNotFound { .. }) => {
+                    info!("{}: {}, skipping", domain_registration.domain, e);
+                    continue;
+                }
+                Err(e) => {
+                    errors.push(format!(
+                        "{}: Unable to resolve zone: {}",
+                        domain_registration.domain,
+                        display_chain(&e)
+                    ));
+                    continue;
+                }
+            }
+        }
+        for record_type in domain_registration.managed_record_types().into_iter() {
+            match record_content(&config, &mut detected_ips, domain_registration, record_type) {
+                Err(e) => errors.push(format!(
+                    "{}: Skipped {} record: {}",
+                    domain_registration.domain, record_type, e
+                )),
+                Ok(new_ip) => {
+                    let ip_version = IpVersion::from_record_type(record_type);
+                    if !*force
+                        && ip_version.is_some_and(|ip_version| {
+                            ip_unchanged(&config, domain_registration, ip_version, &new_ip, now)
+                        })
+                    {
+                        info!(
+                            "{}: IP address has not changed, skipping {} update",
+                            domain_registration.domain, record_type
+                        );
+                        continue;
+                    }
+                    // Domains without their own TTL get the default_ttl of the config file,
+                    // records in other zones are never touched with a zone filter
+                    let domain_registration = DomainRegistration {
+                        ttl: domain_registration.ttl.or(default_ttl),
+                        single_zone: domain_registration.single_zone || zone_filter.is_some(),
+                        ..domain_registration.clone()
+                    };
+                    updates.push((domain_registration, record_type, new_ip));
+                }
+            }
+        }
+    }
+
+    if let Some(metrics) = metrics {
+        let mut metrics = metrics.lock().unwrap();
+        for ((source, _), ip) in detected_ips.iter() {
+            if ip.is_err() {
+                metrics.record_ip_fetch_error(&ip_provider_name(&config, source));
+            }
+        }
+    }
+
+    if let Some(zone_filter) = &zone_filter {
+        if matched == 0 && errors.is_empty() {
+            info!(
+                "No registered domain is in zone {}, nothing to update",
+                zone_filter
+            );
+        }
+    }
+
+    let notifiers = active_notifiers(&config, &update_args.notify_args, &domains);
+    if updates.is_empty() {
+        notify_all(&notifiers, &[], &errors);
+        return Ok(());
+    }
+
+    let mut client_config = config.read_client_config(config.read_cloudflare_token()?)?;
+    if let Some(connect_timeout) = update_args.connect_timeout {
+        client_config.connect_timeout = Duration::from_secs(connect_timeout);
+    }
+    if let Some(read_timeout) = update_args.read_timeout {
+        client_config.read_timeout = Duration::from_secs(read_timeout);
+    }
+    client_config.cache = !update_args.no_cache;
+
+    let (synced, zone_errors) =
+        sync_dns_records(client_config, updates, update_args, force, &ttl_limits)?;
+    errors.extend(zone_errors);
+
+    let entries = history_entries(&synced, now);
+    record_history(&config, &entries);
+    notify_all(&notifiers, &update_events(&entries), &errors);
+
+    // Remember the ips the DNS records have now, so unchanged ips can be skipped next time
+    if let Some(metrics) = metrics {
+        let mut metrics = metrics.lock().unwrap();
+        for (domain, record_type, new_ip, record_sync) in synced.iter() {
+            metrics.record_update(domain, record_type, record_sync.status());
+            if !matches!(record_sync, RecordSync::Failed) {
+                metrics.record_current_ip(domain, record_type, new_ip, now);
+            }
+        }
+    }
+    let synced: Vec<SyncedRecord> = synced
+        .into_iter()
+        .filter(|(_, _, _, record_sync)| !matches!(record_sync, RecordSync::Failed))
+        .collect();
+    if synced.is_empty() {
+        return Ok(());
+    }
+    for (domain, record_type, new_ip, _) in synced.into_iter() {
+        if let Some(domain_registration) = domains.iter_mut().find(|d| d.domain == domain) {
+            match record_type {
+                "A" => domain_registration.last_v4 = Some(new_ip),
+                "AAAA" => domain_registration.last_v6 = Some(new_ip),
+                _ => {}
+            }
+            domain_registration.last_update = Some(now);
+        }
+    }
+    config.write_domains(&domains)?;
+    Ok(())
+}
+
+fn check_registrations(domains: &[DomainRegistration]) {
+    for domain_registration in domains.iter().filter(|d| d.is_disabled()) {
+        warn!(
+            "{}: All record types are disabled, skipping it",
+            domain_registration.domain
+        );
+    }
+}
+
+fn active_notifiers(
+    config: &Config,
+    notify_args: &NotifyArgs,
+    domains: &[DomainRegistration],
+) -> Vec<Box<dyn Notifier>> {
+    // Every backend that is configured, one that can not be set up is skipped with a warning
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+    let proxy_config = config.read_proxy_config();
+    let skip = |name: &str, e: NotificationError| {
+        warn!("Unable to notify {} (Cause: {})", name, display_chain(&e));
+    };
+
+    let webhook_url = notify_args
+        .webhook_url
+        .as_ref()
+        .or(config.read_config_entry("webhook_url"));
+    let domain_urls: HashMap<String, String> = domains
+        .iter()
+        .filter_map(|d| Some((d.domain.clone(), d.webhook_url.clone()?)))
+        .collect();
+    if webhook_url.is_some() || !domain_urls.is_empty() {
+        match WebhookNotifier::new(webhook_url.cloned(), domain_urls, &proxy_config) {
+            Ok(notifier) => notifiers.push(Box::new(notifier)),
+            Err(e) => skip("webhook", e),
+        }
+    }
+
+    let telegram_token = notify_args
+        .telegram_token
+        .as_ref()
+        .or(config.read_config_entry("telegram_bot_token"));
+    let telegram_chat_id = notify_args
+        .telegram_chat_id
+        .as_ref()
+        .or(config.read_config_entry("telegram_chat_id"));
+    if let (Some(token), Some(chat_id)) = (telegram_token, telegram_chat_id) {
+        match TelegramNotifier::new(token, chat_id, &proxy_config) {
+            Ok(notifier) => notifiers.push(Box::new(notifier)),
+            Err(e) => skip("Telegram", e),
+        }
+    }
+
+    if let Some(email_config) = config.read_email_config(&notify_args.notify_email) {
+        notifiers.push(Box::new(EmailNotifier::new(email_config)));
+    }
+    if let Some(path) = config.read_notification_log_file_path() {
+        notifiers.push(Box::new(LogFileNotifier::new(path)));
+    }
+    notifiers
+}
+
+fn update_events(entries: &[HistoryEntry]) -> Vec<UpdateEvent> {
+    entries
+        .iter()
+        .map(|entry| UpdateEvent {
+            domain: entry.domain.clone(),
+            record_type: entry.record_type.clone(),
+            old_ip: entry.old_ip.clone().unwrap_or_default(),
+            new_ip: entry.new_ip.clone(),
+            success: entry.success,
+            timestamp: entry.timestamp,
+        })
+        .collect()
+}
+
+fn test_notify(args: &Args, notify_args: &NotifyArgs) -> Result<(), Error> {
+    // Domains with their own webhook URL get an event too, so every URL is tried
+    let config = Config::new(args)?;
+    let domains = config.read_domains()?;
+    let notifiers = active_notifiers(&config, notify_args, &domains);
+    if notifiers.is_empty() {
+        warn!("No notification channel is configured");
+        return Ok(());
+    }
+
+    let test_event = |domain: &str| UpdateEvent {
+        domain: domain.to_string(),
+        record_type: "A".to_string(),
+        old_ip: "0.0.0.0".to_string(),
+        new_ip: "127.0.0.1".to_string(),
+        success: true,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    };
+    let events: Vec<UpdateEvent> = iter::once(TEST_NOTIFY_DOMAIN)
+        .chain(
+            domains
+                .iter()
+                .filter(|d| d.webhook_url.is_some())
+                .map(|d| d.domain.as_str()),
+        )
+        .map(test_event)
+        .collect();
+
+    let mut failed = 0;
+    for notifier in notifiers.iter() {
+        match notifier.notify_all(&events, &[]) {
+            Ok(_) => info!("{}: Sent test notification", notifier.name()),
+            Err(e) => {
+                error!(
+                    "{}: Unable to send test notification (Cause: {})",
+                    notifier.name(),
+                    display_chain(&e)
+                );
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::NotificationsFailed(failed)),
+    }
+}
+
+fn notify_all(notifiers: &[Box<dyn Notifier>], events: &[UpdateEvent], errors: &[String]) {
+    // A notifier that can not be reached must not fail the update
+    if events.is_empty() && errors.is_empty() {
+        return;
+    }
+    for notifier in notifiers.iter() {
+        if let Err(e) = notifier.notify_all(events, errors) {
+            warn!(
+                "Unable to notify {} (Cause: {})",
+                notifier.name(),
+                display_chain(&e)
+            );
+        }
+    }
+}
+
+fn ip_provider_name(config: &Config, source: &Option<String>) -> String {
+    // The configured source, or how the public ip is detected without one
+    match source {
+        Some(source) => source.clone(),
+        None => match config.read_stun_server() {
+            Some(server) => format!("stun:{}", server),
+            None => "http".to_string(),
+        },
+    }
+}
+
+fn sync_dns_records(
+    client_config: ClientConfig,
+    updates: Vec<(DomainRegistration, &'static str, String)>,
+    update_args: &UpdateArgs,
+    force: &bool,
+    ttl_limits: &TtlLimits,
+) -> Result<(Vec<SyncedRecord>, Vec<String>), CloudflareError> {
+    // Update the DNS records and return the ones that have the new ip now or failed to get it,
+    // failed records of other zones are returned as errors, they keep the result of the domain's own record
+    let options = SyncOptions {
+        ttl_limits: *ttl_limits,
+        force: *force,
+        no_create: update_args.no_create,
+        dry_run: update_args.dry_run,
+    };
+
+    #[cfg(feature = "tokio")]
+    if !update_args.no_parallel && update_args.jobs > 1 {
+        return Ok(update_domains_parallel(
+            AsyncCloudflareApi::new(client_config)?,
+            updates,
+            &update_args.jobs,
+            &options,
+        ));
+    }
+
+    let mut cloudflare_client = CloudflareApi::new(client_config)?;
+    let mut synced = Vec::new();
+    let mut errors = Vec::new();
+    for (domain_registration, record_type, new_ip) in updates.into_iter() {
+        let record_sync = check_and_conditionally_update_domain(
+            &mut cloudflare_client,
+            &domain_registration,
+            record_type,
+            &new_ip,
+            &options,
+        );
+        if !domain_registration.single_zone {
+            errors.extend(update_records_in_other_zones(
+                &mut cloudflare_client,
+                &domain_registration,
+                record_type,
+                &new_ip,
+                &options,
+            ));
+        }
+        match record_sync {
+            RecordSync::Skipped => {}
+            record_sync => {
+                synced.push((domain_registration.domain, record_type, new_ip, record_sync))
+            }
+        }
+    }
+    Ok((synced, errors))
+}
+
+fn ip_unchanged(
+    config: &Config,
+    domain_registration: &DomainRegistration,
+    ip_version: IpVersion,
+    new_ip: &str,
+    now: u64,
+) -> bool {
+    // Domains updated by older versions only have the global last_ipv4, last_ipv6 and last_update entries
+    let last_ip = match ip_version {
+        IpVersion::V4 => domain_registration
+            .last_v4
+            .clone()
+            .or_else(|| config.read_config_entry("last_ipv4").cloned()),
+        IpVersion::V6 => domain_registration
+            .last_v6
+            .clone()
+            .or_else(|| config.read_config_entry("last_ipv6").cloned()),
+    };
+    let last_update = domain_registration.last_update.or_else(|| {
+        config
+            .read_config_entry("last_update")
+            .and_then(|v| v.parse::<u64>().ok())
+    });
+
+    // Update at least every 12 hours, in case the DNS record was changed elsewhere
+    last_ip.as_deref() == Some(new_ip) && last_update.unwrap_or(0) + 60 * 60 * 12 > now
+}
+
+#[cfg(feature = "tokio")]
+fn update_domains_parallel(
+    cloudflare_client: AsyncCloudflareApi,
+    updates: Vec<(DomainRegistration, &'static str, String)>,
+    jobs: &usize,
+    options: &SyncOptions,
+) -> (Vec<SyncedRecord>, Vec<String>) {
+    // Run one task per dns record, but never more than `jobs` at the same time
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Unable to start tokio runtime");
+
+    let options = *options;
+    let semaphore = Arc::new(Semaphore::new(*jobs));
+    runtime.block_on(async move {
+        let mut tasks = JoinSet::new();
+        let mut synced = Vec::new();
+        let mut errors = Vec::new();
+        for (domain_registration, record_type, new_ip) in updates.into_iter() {
+            let cloudflare_client = cloudflare_client.clone();
+            let semaphore = semaphore.clone();
+            tasks.spawn(
+                async move {
+                    let _permit = semaphore.acquire_owned().await.unwrap();
+                    let record_sync = check_and_conditionally_update_domain_async(
+                        &cloudflare_client,
+                        &domain_registration,
+                        record_type,
+                        &new_ip,
+                        &options,
+                    )
+                    .await;
+                    let errors = match domain_registration.single_zone {
+                        true => Vec::new(),
+                        false => {
+                            update_records_in_other_zones_async(
+                                &cloudflare_client,
+                                &domain_registration,
+                                record_type,
+                                &new_ip,
+                                &options,
+                            )
+                            .await
+                        }
+                    };
+                    let synced = match record_sync {
+                        RecordSync::Skipped => None,
+                        record_sync => {
+                            Some((domain_registration.domain, record_type, new_ip, record_sync))
+                        }
+                    };
+                    (synced, errors)
+                }
+                .in_current_span(),
+            );
+        }
+
+        while let Some(result) = tasks.join_next().await {
+            match result {
+                Ok((update, task_errors)) => {
+                    synced.extend(update);
+                    errors.extend(task_errors);
+                }
+                Err(e) => error!("Update task failed: {}", e),
+            }
+        }
+        (synced, errors)
+    })
+}
+
+/// What happened to a DNS record during an update
+enum RecordSync {
+    /// The record already has the new ip
+    Unchanged,
+    Created,
+    Updated {
+        old_ip: String,
+    },
+    /// Creating or updating the record failed
+    Failed,
+    /// The record was not changed, e.g. in a dry run
+    Skipped,
+}
+
+impl RecordSync {
+    fn status(&self) -> &'static str {
+        match self {
+            RecordSync::Unchanged => "unchanged",
+            RecordSync::Created => "created",
+            RecordSync::Updated { .. } => "updated",
+            RecordSync::Failed => "failed",
+            RecordSync::Skipped => "skipped",
+        }
+    }
+}
+
+/// How the DNS records are updated, shared by all of them
+#[derive(Clone, Copy)]
+struct SyncOptions {
+    ttl_limits: TtlLimits,
+    force: bool,
+    no_create: bool,
+    dry_run: bool,
+}
+
+// A DNS record after the update, with the new ip unless it failed
+type SyncedRecord = (String, &'static str, String, RecordSync);
+
+/// What an update does with a DNS record, decided from the record Cloudflare has
+enum RecordAction {
+    Create {
+        ttl: Option<u32>,
+    },
+    Update {
+        old_ip: String,
+        ttl: Option<u32>,
+    },
+    /// Nothing is sent to Cloudflare, the record ends up like this
+    Done(RecordSync),
+}
+
+fn record_action(
+    domain_registration: &DomainRegistration,
+    record_type: &str,
+    new_ip: &str,
+    lookup: Result<DnsRecord, CloudflareError>,
+    options: &SyncOptions,
+) -> RecordAction {
+    // Only a missing record is created, any other error could hide an existing one
+    let name = domain_registration.domain.as_str();
+    let ttl_limits = &options.ttl_limits;
+    let record = match lookup {
+        Ok(record) => record,
+        Err(CloudflareError::RecordNotFound { .. }) => {
+            if options.no_create {
+                warn!("{}: No DNS Record Found (Update IP: {})", name, new_ip);
+                return RecordAction::Done(RecordSync::Skipped);
+            }
+            if options.dry_run {
+                info!(
+                    "[DRY RUN] Would create {} {}: {}",
+                    name, record_type, new_ip
+                );
+                return RecordAction::Done(RecordSync::Skipped);
+            }
+            return RecordAction::Create {
+                ttl: ttl_limits.target_ttl(domain_registration.ttl, 1),
+            };
+        }
+        Err(e) => {
+            error!(
+                "{}: Unable to fetch {} record (Update IP: {}, Cause: {})",
+                name,
+                record_type,
+                new_ip,
+                display_chain(&e)
+            );
+            return RecordAction::Done(RecordSync::Failed);
+        }
+    };
+
+    if !record_needs_update(
+        &record,
+        domain_registration,
+        new_ip,
+        ttl_limits,
+        &options.force,
+    ) {
+        return match options.dry_run {
+            true => RecordAction::Done(RecordSync::Skipped),
+            false => RecordAction::Done(RecordSync::Unchanged),
+        };
+    }
+    if options.dry_run {
+        info!(
+            "[DRY RUN] Would update {} {}: {} -> {}",
+            name, record_type, record.content, new_ip
+        );
+        return RecordAction::Done(RecordSync::Skipped);
+    }
+    RecordAction::Update {
+        ttl: ttl_limits.target_ttl(domain_registration.ttl, record.ttl),
+        old_ip: record.content,
+    }
+}
+
+fn record_action_result(
+    name: &str,
+    record_type: &str,
+    new_ip: &str,
+    action: RecordAction,
+    result: Result<(), CloudflareError>,
+) -> RecordSync {
+    match (action, result) {
+        (RecordAction::Done(record_sync), _) => record_sync,
+        (RecordAction::Create { .. }, Ok(_)) => {
+            info!("{}: Created {} Record -> {}", name, record_type, new_ip);
+            RecordSync::Created
+        }
+        (RecordAction::Create { .. }, Err(e)) => {
+            error!(
+                "{}: No DNS Record Found and unable to create it (Update IP: {}, Cause: {})",
+                name,
+                new_ip,
+                display_chain(&e)
+            );
+            RecordSync::Failed
+        }
+        (RecordAction::Update { old_ip, .. }, Ok(_)) => {
+            info!("{}: {} -> {}", name, old_ip, new_ip);
+            RecordSync::Updated { old_ip }
+        }
+        (RecordAction::Update { .. }, Err(e)) => {
+            error!(
+                "{}: Failed to update DNS Record (Update IP: {}, Cause: {})",
+                name,
+                new_ip,
+                display_chain(&e)
+            );
+            RecordSync::Failed
+        }
+    }
+}
+
+fn check_and_conditionally_update_domain(
+    cloudflare_client: &mut CloudflareApi,
+    domain_registration: &DomainRegistration,
+    record_type: &str,
+    new_ip: &str,
+    options: &SyncOptions,
+) -> RecordSync {
+    let name = domain_registration.domain.as_str();
+    let proxied = &domain_registration.proxied;
+    let comment = &domain_registration.comment;
+
+    let lookup = cloudflare_client
+        .fetch_cloudflare_dns_record(name, record_type)
+        .cloned();
+    let action = record_action(domain_registration, record_type, new_ip, lookup, options);
+    let result = match &action {
+        RecordAction::Create { ttl } => cloudflare_client
+            .create_cloudflare_dns_record(name, record_type, new_ip, ttl, proxied, comment)
+            .map(|_| ()),
+        RecordAction::Update { ttl, .. } => cloudflare_client
+            .update_cloudflare_dns_record(name, record_type, new_ip, ttl, proxied, comment)
+            .map(|_| ()),
+        RecordAction::Done(_) => Ok(()),
+    };
+    record_action_result(name, record_type, new_ip, action, result)
+}
+
+#[cfg(feature = "tokio")]
+async fn check_and_conditionally_update_domain_async(
+    cloudflare_client: &AsyncCloudflareApi,
+    domain_registration: &DomainRegistration,
+    record_type: &str,
+    new_ip: &str,
+    options: &SyncOptions,
+) -> RecordSync {
+    let name = domain_registration.domain.as_str();
+    let proxied = &domain_registration.proxied;
+    let comment = &domain_registration.comment;
+
+    let lookup = cloudflare_client
+        .fetch_cloudflare_dns_record(name, record_type)
+        .await;
+    let action = record_action(domain_registration, record_type, new_ip, lookup, options);
+    let result = match &action {
+        RecordAction::Create { ttl } => cloudflare_client
+            .create_cloudflare_dns_record(name, record_type, new_ip, ttl, proxied, comment)
+            .await
+            .map(|_| ()),
+        RecordAction::Update { ttl, .. } => cloudflare_client
+            .update_cloudflare_dns_record(name, record_type, new_ip, ttl, proxied, comment)
+            .await
+            .map(|_| ()),
+        RecordAction::Done(_) => Ok(()),
+    };
+    record_action_result(name, record_type, new_ip, action, result)
+}
+
+fn records_in_several_zones(
+    name: &str,
+    record_type: &str,
+    lookup: Result<Vec<DnsRecord>, CloudflareError>,
+) -> Vec<DnsRecord> {
+    // Empty unless the domain has records in parent or child zones too
+    match lookup {
+        Ok(records) if records.len() > 1 => {
+            warn!(
+                "{}: Found {} {} records in multiple zones, updating all of them",
+                name,
+                records.len(),
+                record_type
+            );
+            records
+        }
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            warn!(
+                "{}: Unable to look for records in other zones: {}",
+                name,
+                display_chain(&e)
+            );
+            Vec::new()
+        }
+    }
+}
+
+fn other_zone_updates(
+    domain_registration: &DomainRegistration,
+    record_type: &str,
+    new_ip: &str,
+    records: Vec<DnsRecord>,
+    zone_id: Option<String>,
+    options: &SyncOptions,
+) -> Vec<(DnsRecord, Option<u32>)> {
+    // The records of other zones that need the new ip, with their TTL. The record of the
+    // domain's own zone is handled by check_and_conditionally_update_domain
+    let name = domain_registration.domain.as_str();
+    let mut updates = Vec::new();
+    for record in records.into_iter() {
+        if Some(&record.zone_id) == zone_id.as_ref()
+            || !record_needs_update(
+                &record,
+                domain_registration,
+                new_ip,
+                &options.ttl_limits,
+                &options.force,
+            )
+        {
+            continue;
+        }
+        if options.dry_run {
+            info!(
+                "[DRY RUN] Would update {} {} of zone {}: {} -> {}",
+                name, record_type, record.zone_id, record.content, new_ip
+            );
+            continue;
+        }
+        let ttl = options
+            .ttl_limits
+            .target_ttl(domain_registration.ttl, record.ttl);
+        updates.push((record, ttl));
+    }
+    updates
+}
+
+fn other_zone_update_result(
+    name: &str,
+    record: &DnsRecord,
+    new_ip: &str,
+    result: Result<DnsRecord, CloudflareError>,
+) -> Option<String> {
+    // The error of a failed update, reported apart from the record of the domain's own zone
+    match result {
+        Ok(_) => {
+            info!(
+                "{}: {} -> {} (zone {})",
+                name, record.content, new_ip, record.zone_id
+            );
+            None
+        }
+        Err(e) => {
+            let message = format!(
+                "{}: Failed to update {} record of zone {} (Update IP: {}, Cause: {})",
+                name,
+                record.record_type,
+                record.zone_id,
+                new_ip,
+                display_chain(&e)
+            );
+            error!("{}", message);
+            Some(message)
+        }
+    }
+}
+
+fn update_records_in_other_zones(
+    cloudflare_client: &mut CloudflareApi,
+    domain_registration: &DomainRegistration,
+    record_type: &str,
+    new_ip: &str,
+    options: &SyncOptions,
+) -> Vec<String> {
+    // Records of the domain in parent or child zones get the new ip as well, returns the failed updates
+    let name = domain_registration.domain.as_str();
+    let lookup = cloudflare_client.fetch_all_dns_records_for_domain(name, record_type);
+    let records = records_in_several_zones(name, record_type, lookup);
+    if records.is_empty() {
+        return Vec::new();
+    }
+
+    let zone_id = cloudflare_client.zone_id_for_domain(name).ok();
+    let mut errors = Vec::new();
+    for (record, ttl) in other_zone_updates(
+        domain_registration,
+        record_type,
+        new_ip,
+        records,
+        zone_id,
+        options,
+    ) {
+        let result = cloudflare_client.update_dns_record(
+            &record,
+            new_ip,
+            &ttl,
+            &domain_registration.proxied,
+            &domain_registration.comment,
+        );
+        errors.extend(other_zone_update_result(name, &record, new_ip, result));
+    }
+    errors
+}
+
+#[cfg(feature = "tokio")]
+async fn update_records_in_other_zones_async(
+    cloudflare_client: &AsyncCloudflareApi,
+    domain_registration: &DomainRegistration,
+    record_type: &str,
+    new_ip: &str,
+    options: &SyncOptions,
+) -> Vec<String> {
+    let name = domain_registration.domain.as_str();
+    let lookup = cloudflare_client
+        .fetch_all_dns_records_for_domain(name, record_type)
+        .await;
+    let records = records_in_several_zones(name, record_type, lookup);
+    if records.is_empty() {
+        return Vec::new();
+    }
+
+    let zone_id = cloudflare_client.zone_id_for_domain(name).await.ok();
+    let mut errors = Vec::new();
+    for (record, ttl) in other_zone_updates(
+        domain_registration,
+        record_type,
+        new_ip,
+        records,
+        zone_id,
+        options,
+    ) {
+        let result = cloudflare_client
+            .update_dns_record(
+                &record,
+                new_ip,
+                &ttl,
+                &domain_registration.proxied,
+                &domain_registration.comment,
+            )
+            .await;
+        errors.extend(other_zone_update_result(name, &record, new_ip, result));
+    }
+    errors
+}
+
+fn history_entries(synced: &[SyncedRecord], timestamp: u64) -> Vec<HistoryEntry> {
+    // Unchanged records are not part of the history
+    synced
+        .iter()
+        .filter_map(|(domain, record_type, new_ip, record_sync)| {
+            let (old_ip, success) = match record_sync {
+                RecordSync::Updated { old_ip } => (Some(old_ip.clone()), true),
+                RecordSync::Created => (None, true),
+                RecordSync::Failed => (None, false),
+                RecordSync::Unchanged | RecordSync::Skipped => return None,
+            };
+            Some(HistoryEntry {
+                timestamp,
+                domain: domain.clone(),
+                record_type: record_type.to_string(),
+                old_ip,
+                new_ip: new_ip.clone(),
+                success,
+            })
+        })
+        .collect()
+}
+
+fn record_history(config: &Config, entries: &[HistoryEntry]) {
+    // A failed write only loses the history
+    if entries.is_empty() {
+        return;
+    }
+    if let Err(e) = append_history(&config.read_history_file_path(), entries) {
+        warn!("{}", display_chain(&e));
+    }
+}
+
+fn show_history(args: &Args, filter: HistoryFilter) -> Result<(), Error> {
+    let config = Config::new(args)?;
+    let entries = read_history(&config.read_history_file_path(), &filter)?;
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row!["Time", "Domain", "Type", "Old IP", "New IP", "Result"]);
+    for entry in entries.iter() {
+        table.add_row(row![
+            format_timestamp(entry.timestamp),
+            entry.domain,
+            entry.record_type,
+            entry.old_ip.as_deref().unwrap_or("-"),
+            entry.new_ip,
+            match entry.success {
+                true => "ok",
+                false => "failed",
+            }
+        ]);
+    }
+    print_table(args, &table);
+    Ok(())
+}
+
+fn show_stats(args: &Args, domain: &Option<String>) -> Result<(), Error> {
+    // Registered domains without any change in the history are listed with zero updates
+    let config = Config::new(args)?;
+    let domain = domain.as_deref().map(to_ascii_domain);
+    let filter = HistoryFilter {
+        domain: domain.clone(),
+        ..HistoryFilter::default()
+    };
+    let entries = read_history(&config.read_history_file_path(), &filter)?;
+    let domains: Vec<String> = match domain {
+        Some(domain) => vec![domain],
+        None => config
+            .read_domains()?
+            .into_iter()
+            .map(|domain_registration| domain_registration.domain)
+            .collect(),
+    };
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.set_titles(row![
+        "Domain",
+        "Updates",
+        "Successful",
+        "Failure rate",
+        "Average interval",
+        "Current IP",
+        "Last update"
+    ]);
+    for domain in domains.iter() {
+        let stats = domain_stats(&entries, domain);
+        table.add_row(row![
+            stats.domain,
+            stats.updates,
+            stats.successful,
+            stats
+                .failure_rate()
+                .map_or("-".to_string(), |rate| format!("{:.1}%", rate * 100.0)),
+            stats
+                .average_interval
+                .map_or("-".to_string(), format_duration),
+            match stats.current_ips.is_empty() {
+                true => "-".to_string(),
+                false => stats.current_ips.join(", "),
+            },
+            stats.last_update.map_or("-".to_string(), format_timestamp)
+        ]);
+    }
+    print_table(args, &table);
+    Ok(())
+}
+
+fn format_duration(secs: u64) -> String {
+    // The two largest units are precise enough, e.g. 2d 3h
+    let units = [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+    let parts: Vec<String> = units
+        .iter()
+        .scan(secs, |rest, (unit, name)| {
+            let count = *rest / unit;
+            *rest %= unit;
+            Some((count, name))
+        })
+        .skip_while(|(count, _)| *count == 0)
+        .take(2)
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, name)| format!("{}{}", count, name))
+        .collect();
+    match parts.is_empty() {
+        true => "0s".to_string(),
+        false => parts.join(" "),
+    }
+}
+
+fn read_ttl(config: &Config, key: &str) -> Option<u32> {
+    config
+        .read_config_entry(key)
+        .and_then(|v| v.parse::<u32>().ok())
+}
+
+fn read_ttl_limits(
+    config: &Config,
+    min_ttl: Option<u32>,
+    max_ttl: Option<u32>,
+) -> Result<TtlLimits, Error> {
+    // Limits given on the command line override the ones of the config file
+    let ttl_limits = TtlLimits {
+        min: min_ttl.or_else(|| read_ttl(config, "min_ttl")),
+        max: max_ttl.or_else(|| read_ttl(config, "max_ttl")),
+    };
+    match (ttl_limits.min, ttl_limits.max) {
+        (Some(min), Some(max)) if min > max => Err(Error::InvalidTtlRange { min, max }),
+        _ => Ok(ttl_limits),
+    }
+}
+
+/// Range the TTLs sent to Cloudflare are clamped to
+#[derive(Clone, Copy, Default)]
+struct TtlLimits {
+    min: Option<u32>,
+    max: Option<u32>,
+}
+
+impl TtlLimits {
+    fn clamp(&self, ttl: u32) -> u32 {
+        // Automatic TTL (1) stands for the 300 seconds Cloudflare uses, it is kept if that is in range
+        let seconds = match ttl {
+            1 => AUTOMATIC_TTL_SECS as u32,
+            ttl => ttl,
+        };
+        let mut clamped = seconds;
+        if let Some(min) = self.min {
+            clamped = clamped.max(min);
+        }
+        if let Some(max) = self.max {
+            clamped = clamped.min(max);
+        }
+        match clamped == seconds {
+            true => ttl,
+            false => clamped,
+        }
+    }
+
+    fn target_ttl(&self, ttl: Option<u32>, current_ttl: u32) -> Option<u32> {
+        // The TTL to send, a configured one always and the current one only if it is out of range
+        match ttl {
+            Some(ttl) => Some(self.clamp(ttl)),
+            None => Some(self.clamp(current_ttl)).filter(|ttl| *ttl != current_ttl),
+        }
+    }
+}
+
+fn record_needs_update(
+    record: &DnsRecord,
+    domain_registration: &DomainRegistration,
+    new_ip: &str,
+    ttl_limits: &TtlLimits,
+    force: &bool,
+) -> bool {
+    let ttl_changed = ttl_limits
+        .target_ttl(domain_registration.ttl, record.ttl)
+        .is_some_and(|ttl| ttl != record.ttl);
+    let comment_changed = domain_registration
+        .comment
+        .as_ref()
+        .is_some_and(|comment| record.comment.as_ref() != Some(comment));
+    record.content != new_ip
+        || ttl_changed
+        || comment_changed
+        || record.proxied != domain_registration.proxied
+        || *force
+}
+
+// Detected addresses per ip source, so every source is only asked once per run.
+// Errors are kept as their message, they are only shown to the user
+type DetectedIps = HashMap<(Option<String>, IpVersion), Result<String, String>>;
+
+fn detect_ip(
+    config: &Config,
+    detected_ips: &mut DetectedIps,
+    source: &Option<String>,
+    ip_version: IpVersion,
+) -> Result<String, String> {
+    // An address given on the command line replaces every ip source
+    if let Some(ip) = config.read_ip_override(ip_version) {
+        return Ok(ip.clone());
+    }
+    detected_ips
+        .entry((source.clone(), ip_version))
+        .or_insert_with(|| {
+            let ip = lookup_ip(config, source, ip_version);
+            if let Err(ref e) = ip {
+                warn!("{}, skipping {} update", e, ip_version.name());
+            }
+            ip
+        })
+        .clone()
+}
+
+fn lookup_ip(
+    config: &Config,
+    source: &Option<String>,
+    ip_version: IpVersion,
+) -> Result<String, String> {
+    IpSource::parse(source)
+        .and_then(|source| match source {
+            IpSource::Service => match config.read_stun_server() {
+                Some(server) => config
+                    .read_bind_address(ip_version)
+                    .map_err(IpDetectionError::from)
+                    .and_then(|local_address| get_ip_via_stun(&server, ip_version, local_address)),
+                None => config.ip_detection_client(ip_version).and_then(|client| {
+                    get_ip(
+                        client,
+                        ip_version,
+                        &config.read_ip_providers(ip_version),
+                        &config.read_insecure(),
+                    )
+                }),
+            },
+            IpSource::Interface(iface) => get_ip_from_interface(&iface, ip_version),
+        })
+        .map_err(|e| display_chain(&e))
+}
+
+fn record_content(
+    config: &Config,
+    detected_ips: &mut DetectedIps,
+    domain_registration: &DomainRegistration,
+    record_type: &str,
+) -> Result<String, String> {
+    // Address records get the detected ip, the other record types their target of the registration
+    match IpVersion::from_record_type(record_type) {
+        Some(ip_version) => domain_ip(config, detected_ips, domain_registration, ip_version),
+        None => domain_registration
+            .cname_target()
+            .cloned()
+            .ok_or_else(|| format!("No target for the {} record", record_type)),
+    }
+}
+
+fn domain_ip(
+    config: &Config,
+    detected_ips: &mut DetectedIps,
+    domain_registration: &DomainRegistration,
+    ip_version: IpVersion,
+) -> Result<String, String> {
+    // Detect the address of the domain's ip source and apply its suffix
+    let ip = match ip_version {
+        IpVersion::V4 => {
+            let ip = detect_ip(
+                config,
+                detected_ips,
+                &domain_registration.v4_source,
+                ip_version,
+            )?;
+            Ok(match domain_registration.v4_suffix {
+                Some(ref suffix) => replace_ipv4_suffix(&ip, suffix).map_err(|e| {
+                    let e = display_chain(&e);
+                    warn!(
+                        "{}: {}, skipping ipv4 update",
+                        domain_registration.domain, e
+                    );
+                    e
+                })?,
+                None => ip,
+            })
+        }
+        IpVersion::V6 => {
+            // A delegated prefix replaces the detected address, the suffix is its host part
+            let ip = match domain_registration.v6_prefix_source {
+                Some(ref prefix_source) => delegated_prefix(prefix_source).map_err(|e| {
+                    warn!(
+                        "{}: {}, skipping ipv6 update",
+                        domain_registration.domain, e
+                    );
+                    e
+                })?,
+                None => detect_ip(
+                    config,
+                    detected_ips,
+                    &domain_registration.v6_source,
+                    ip_version,
+                )?,
+            };
+            // Both return the canonical form Cloudflare uses, so unchanged ips compare equal
+            let ip = match (
+                &domain_registration.v6_suffix,
+                &domain_registration.v6_prefix_source,
+            ) {
+                (Some(suffix), _) => replace_ipv6_suffix(&ip, suffix),
+                (None, Some(_)) => Err(IpError::NoHostSuffix),
+                (None, None) => ipv6_normalize(&ip),
+            };
+            ip.map_err(|e| {
+                let e = display_chain(&e);
+                warn!(
+                    "{}: {}, skipping ipv6 update",
+                    domain_registration.domain, e
+                );
+                e
+            })
+        }
+    }?;
+
+    // A suffix or an unusual ip source may produce an address nobody outside can reach
+    if !config.read_allow_private_ips() {
+        validate_global_ip(&ip, ip_version).map_err(|e| {
+            let e = display_chain(&e);
+            warn!(
+                "{}: {}, skipping {} update",
+                domain_registration.domain,
+                e,
+                ip_version.name()
+            );
+            e
+        })?;
+    }
+    Ok(ip)
+}
+
+fn delegated_prefix(prefix_source: &str) -> Result<String, String> {
+    let iface = prefix_source_interface(prefix_source).map_err(|e| display_chain(&e))?;
+    get_delegated_prefix(iface)
+        .map(|prefix| prefix.to_string())
+        .map_err(|e| display_chain(&e))
+}
+
+fn replace_ipv4_suffix(ip: &str, suffix: &str) -> Result<String, IpError> {
+    // Replace the end of the ipv4 address with the given suffix
+    let ip_parts: Vec<&str> = ip.split(".").collect();
+    let suffix_parts: Vec<&str> = suffix.split(".").collect();
+
+    if ip_parts.len() != 4 {
+        return Err(IpError::InvalidIpv4(ip.to_string()));
+    }
+    if suffix_parts.len() > ip_parts.len() {
+        return Err(IpError::SuffixTooLong(suffix.to_string()));
+    }
+    let parse_octets = |parts: &[&str]| {
+        parts
+            .iter()
+            .map(|octet| {
+                octet
+                    .parse::<u8>()
+                    .map_err(|_| IpError::InvalidOctet(octet.to_string()))
+            })
+            .collect::<Result<Vec<u8>, IpError>>()
+    };
+    let mut octets: [u8; 4] = parse_octets(&ip_parts)?.try_into().unwrap();
+    let suffix_octets = parse_octets(&suffix_parts)?;
+
+    // Formatting the parsed octets drops leading zeros, e.g. a suffix of 010 becomes 10
+    let start = octets.len() - suffix_octets.len();
+    octets[start..].copy_from_slice(&suffix_octets);
+    Ok(Ipv4Addr::from(octets).to_string())
+}
+
+fn ipv6_normalize(addr: &str) -> Result<String, IpError> {
+    // Compress the address to its canonical form, e.g. 2001:0db8:0:0::1 to 2001:db8::1
+    Ipv6Addr::from_str(addr)
+        .map(|addr| addr.to_string())
+        .map_err(|_| IpError::InvalidIpv6(addr.to_string()))
+}
+
+fn replace_ipv6_suffix(ip: &str, suffix: &str) -> Result<String, IpError> {
+    // Replace the last groups of the ipv6 address with the groups of the suffix
+    let mut groups = ip
+        .parse::<Ipv6Addr>()
+        .map_err(|_| IpError::InvalidIpv6(ip.to_string()))?
+        .segments();
+    let suffix = match suffix.strip_prefix("eui64:") {
+        Some(mac) => &mac_to_eui64(mac)?,
+        None => suffix,
+    };
+
+    let suffix_groups = suffix
+        .split(':')
+        .map(|group| {
+            u16::from_str_radix(group, 16).map_err(|_| IpError::InvalidGroup(group.to_string()))
+        })
+        .collect::<Result<Vec<u16>, IpError>>()?;
+    if suffix_groups.len() > groups.len() {
+        return Err(IpError::SuffixTooLong(suffix.to_string()));
+    }
+
+    let start = groups.len() - suffix_groups.len();
+    groups[start..].copy_from_slice(&suffix_groups);
+    Ok(Ipv6Addr::from(groups).to_string())
+}
+
+fn mac_to_eui64(mac: &str) -> Result<String, IpError> {
+    // Insert ff:fe between OUI and NIC of the MAC and invert its universal/local bit
+    let octets = mac
+        .split([':', '-'])
+        .map(|octet| match octet.len() {
+            2 => u8::from_str_radix(octet, 16).ok(),
+            _ => None,
+        })
+        .collect::<Option<Vec<u8>>>()
+        .filter(|octets| octets.len() == 6)
+        .ok_or_else(|| IpError::InvalidMac(mac.to_string()))?;
+
+    let eui64 = [
+        octets[0] ^ 0x02,
+        octets[1],
+        octets[2],
+        0xff,
+        0xfe,
+        octets[3],
+        octets[4],
+        octets[5],
+    ];
+    Ok(eui64
+        .chunks(2)
+        .map(|group| format!("{:x}", u16::from_be_bytes([group[0], group[1]])))
+        .collect::<Vec<String>>()
+        .join(":"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn assert_ipv6_suffix(ip: &str, suffix: &str, expected: &str) {
+        assert_eq!(replace_ipv6_suffix(ip, suffix).unwrap(), expected);
+    }
+
+    fn assert_ipv4_suffix(ip: &str, suffix: &str, expected: &str) {
+        assert_eq!(replace_ipv4_suffix(ip, suffix).unwrap(), expected);
+    }
+
+    proptest! {
+        #[test]
+        fn ipv4_suffix_replaces_last_octets(
+            ip in any::<Ipv4Addr>(),
+            suffix in prop::collection::vec(any::<u8>(), 1..=4),
+        ) {
+            let suffix_string: Vec<String> = suffix.iter().map(|octet| octet.to_string()).collect();
+            let replaced = replace_ipv4_suffix(&ip.to_string(), &suffix_string.join(".")).unwrap();
+
+            let octets = Ipv4Addr::from_str(&replaced).unwrap().octets();
+            let start = octets.len() - suffix.len();
+            prop_assert_eq!(&octets[..start], &ip.octets()[..start]);
+            prop_assert_eq!(&octets[start..], suffix.as_slice());
+        }
+
+        #[test]
+        fn ipv6_suffix_replaces_last_groups(
+            ip in any::<Ipv6Addr>(),
+            suffix in prop::collection::vec(any::<u16>(), 1..=8),
+        ) {
+            let suffix_string: Vec<String> = suffix.iter().map(|group| format!("{:x}", group)).collect();
+            let replaced = replace_ipv6_suffix(&ip.to_string(), &suffix_string.join(":")).unwrap();
+
+            let replaced_ip = Ipv6Addr::from_str(&replaced).unwrap();
+            prop_assert_eq!(&replaced, &replaced_ip.to_string());
+            let groups = replaced_ip.segments();
+            let start = groups.len() - suffix.len();
+            prop_assert_eq!(&groups[..start], &ip.segments()[..start]);
+            prop_assert_eq!(&groups[start..], suffix.as_slice());
+        }
+    }
+
+    #[test]
+    fn replaces_last_octets() {
+        assert_ipv4_suffix("198.51.100.7", "1", "198.51.100.1");
+        assert_ipv4_suffix("198.51.100.7", "0.1", "198.51.0.1");
+        assert_ipv4_suffix("198.51.100.7", "10.0.0.1", "10.0.0.1");
+    }
+
+    #[test]
+    fn drops_leading_zeros_of_octets() {
+        assert_ipv4_suffix("198.51.100.7", "010", "198.51.100.10");
+        assert_ipv4_suffix("198.051.100.7", "1", "198.51.100.1");
+    }
+
+    #[test]
+    fn accepts_boundary_octets() {
+        assert_ipv4_suffix("0.0.0.0", "255", "0.0.0.255");
+        assert_ipv4_suffix("255.255.255.255", "0.0", "255.255.0.0");
+    }
+
+    #[test]
+    fn rejects_too_long_ipv4_suffix() {
+        assert!(matches!(
+            replace_ipv4_suffix("198.51.100.7", "1.2.3.4.5"),
+            Err(IpError::SuffixTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_octets() {
+        for suffix in ["256", "-1", "a", "", "1..2"] {
+            assert!(
+                matches!(
+                    replace_ipv4_suffix("198.51.100.7", suffix),
+                    Err(IpError::InvalidOctet(_))
+                ),
+                "{}",
+                suffix
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_ipv4_address() {
+        assert!(matches!(
+            replace_ipv4_suffix("198.51.100", "1"),
+            Err(IpError::InvalidIpv4(_))
+        ));
+        assert!(matches!(
+            replace_ipv4_suffix("2001:db8::1", "1"),
+            Err(IpError::InvalidIpv4(_))
+        ));
+        assert!(matches!(
+            replace_ipv4_suffix("198.51.100.300", "1"),
+            Err(IpError::InvalidOctet(_))
+        ));
+    }
+
+    #[test]
+    fn replaces_last_group() {
+        assert_ipv6_suffix("2001:db8::1", "2", "2001:db8::2");
+    }
+
+    #[test]
+    fn replaces_groups_of_full_address() {
+        assert_ipv6_suffix("2001:db8:1:2:3:4:5:6", "ffff", "2001:db8:1:2:3:4:5:ffff");
+        assert_ipv6_suffix("2001:db8:1:2:3:4:5:6", "a:b:c:d", "2001:db8:1:2:a:b:c:d");
+    }
+
+    #[test]
+    fn formats_durations_with_two_units() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(42), "42s");
+        assert_eq!(format_duration(3600), "1h");
+        assert_eq!(format_duration(86400 * 2 + 3600 * 3 + 59), "2d 3h");
+        assert_eq!(format_duration(86400 + 60), "1d");
+    }
+
+    #[test]
+    fn derives_suffix_from_mac() {
+        assert_eq!(
+            mac_to_eui64("00:11:22:33:44:55").unwrap(),
+            "211:22ff:fe33:4455"
+        );
+        assert_eq!(
+            mac_to_eui64("02-AB-CD-EF-01-23").unwrap(),
+            "ab:cdff:feef:123"
+        );
+        assert_ipv6_suffix(
+            "2001:db8:1:2::1",
+            "eui64:00:11:22:33:44:55",
+            "2001:db8:1:2:211:22ff:fe33:4455",
+        );
+        assert!(matches!(
+            mac_to_eui64("00:11:22:33:44"),
+            Err(IpError::InvalidMac(_))
+        ));
+        assert!(matches!(
+            mac_to_eui64("00:11:22:33:44:5g"),
+            Err(IpError::InvalidMac(_))
+        ));
+    }
+
+    #[test]
+    fn replaces_all_groups() {
+        assert_ipv6_suffix("2001:db8::1", "1:2:3:4:5:6:7:8", "1:2:3:4:5:6:7:8");
+    }
+
+    #[test]
+    fn expands_compressed_address() {
+        assert_ipv6_suffix("2001:db8:0:0:0:0:0:1", "abcd:1", "2001:db8::abcd:1");
+        assert_ipv6_suffix("2001:db8:a:b::", "1:2:3:4", "2001:db8:a:b:1:2:3:4");
+    }
+
+    #[test]
+    fn handles_unspecified_address() {
+        assert_ipv6_suffix("::", "1", "::1");
+    }
+
+    #[test]
+    fn handles_leading_double_colon() {
+        assert_ipv6_suffix("::1", "2", "::2");
+        assert_ipv6_suffix("::1", "1:0:0:0", "::1:0:0:0");
+    }
+
+    #[test]
+    fn handles_trailing_double_colon() {
+        assert_ipv6_suffix("fe80::", "1", "fe80::1");
+        assert_ipv6_suffix("fe80::", "1:2:3:4", "fe80::1:2:3:4");
+    }
+
+    #[test]
+    fn accepts_zero_padded_suffix() {
+        assert_ipv6_suffix("2001:db8::", "0000:0000:0000:0001", "2001:db8::1");
+    }
+
+    #[test]
+    fn compresses_zero_groups_of_suffix() {
+        assert_ipv6_suffix("2001:db8::1:0:0:1", "0:0", "2001:db8:0:0:1::");
+    }
+
+    #[test]
+    fn normalizes_uppercase_hex() {
+        assert_ipv6_suffix("2001:DB8::1", "A", "2001:db8::a");
+    }
+
+    #[test]
+    fn normalizes_ipv6_address() {
+        assert_eq!(
+            ipv6_normalize("2001:0db8:0000:0000:0000:0000:0001:0002").unwrap(),
+            "2001:db8::1:2"
+        );
+        assert_eq!(ipv6_normalize("FE80:0:0:0:0:0:0:1").unwrap(), "fe80::1");
+        assert!(matches!(
+            ipv6_normalize("2001:db8::1::2"),
+            Err(IpError::InvalidIpv6(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_address() {
+        assert!(matches!(
+            replace_ipv6_suffix("not-an-ip", "1"),
+            Err(IpError::InvalidIpv6(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_ipv4_address() {
+        assert!(matches!(
+            replace_ipv6_suffix("192.0.2.1", "1"),
+            Err(IpError::InvalidIpv6(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_too_long_suffix() {
+        assert!(matches!(
+            replace_ipv6_suffix("2001:db8::1", "1:2:3:4:5:6:7:8:9"),
+            Err(IpError::SuffixTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_out_of_range_group() {
+        assert!(matches!(
+            replace_ipv6_suffix("2001:db8::1", "12345"),
+            Err(IpError::InvalidGroup(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_non_hex_group() {
+        assert!(matches!(
+            replace_ipv6_suffix("2001:db8::1", "xyz"),
+            Err(IpError::InvalidGroup(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_compressed_suffix() {
+        assert!(matches!(
+            replace_ipv6_suffix("2001:db8::1", "1::2"),
+            Err(IpError::InvalidGroup(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_suffix() {
+        assert!(matches!(
+            replace_ipv6_suffix("2001:db8::1", ""),
+            Err(IpError::InvalidGroup(_))
+        ));
+    }
+
+    #[test]
+    fn accepts_valid_domain_names() {
+        assert!(validate_domain_name("example.com").is_ok());
+        assert!(validate_domain_name("sub-1.example.com").is_ok());
+        assert!(validate_domain_name("xn--bcher-kva.example").is_ok());
+        assert!(validate_domain_name("*.example.com").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_domain_names() {
+        assert!(validate_domain_name("").is_err());
+        assert!(validate_domain_name("my domain.com").is_err());
+        assert!(validate_domain_name(".example.com").is_err());
+        assert!(validate_domain_name("example..com").is_err());
+        assert!(validate_domain_name("-example.com").is_err());
+        assert!(validate_domain_name("example-.com").is_err());
+        assert!(validate_domain_name("sub.*.example.com").is_err());
+        assert!(validate_domain_name(&format!("{}.com", "a".repeat(250))).is_err());
+    }
+
+    #[test]
+    fn adopts_orphaned_record_types() {
+        let zone_dns_record = |name: &str, record_type: &str| ZoneDnsRecord {
+            zone: "example.com".to_string(),
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            record: DnsRecord {
+                id: "id".to_string(),
+                zone_id: "zone".to_string(),
+                name: name.to_string(),
+                record_type: record_type.to_string(),
+                content: "192.0.2.1".to_string(),
+                ttl: 300,
+                proxied: true,
+                comment: None,
+            },
+        };
+        let mut domains = vec![DomainRegistration {
+            v6_disabled: true,
+            ..DomainRegistration::new("home.example.com")
+        }];
+
+        assert!(!is_orphan(
+            &domains,
+            &zone_dns_record("home.example.com", "A")
+        ));
+        let orphans = [
+            zone_dns_record("home.example.com", "AAAA"),
+            zone_dns_record("nas.example.com", "AAAA"),
+        ];
+        assert!(orphans.iter().all(|orphan| is_orphan(&domains, orphan)));
+
+        orphans
+            .iter()
+            .for_each(|orphan| register_record(&mut domains, orphan));
+        assert_eq!(domains.len(), 2);
+        assert!(!domains[0].v4_disabled && !domains[0].v6_disabled);
+        assert!(domains[1].v4_disabled && !domains[1].v6_disabled);
+        assert_eq!(domains[1].ttl, Some(300));
+        assert!(domains[1].proxied);
+        assert!(orphans.iter().all(|orphan| !is_orphan(&domains, orphan)));
+    }
+
+    #[test]
+    fn clamps_ttl_to_limits() {
+        let ttl_limits = TtlLimits {
+            min: Some(60),
+            max: Some(600),
+        };
+        assert_eq!(ttl_limits.target_ttl(Some(30), 300), Some(60));
+        assert_eq!(ttl_limits.target_ttl(None, 3600), Some(600));
+        assert_eq!(ttl_limits.target_ttl(None, 120), None);
+        // Automatic TTL means 300 seconds, which is in range
+        assert_eq!(ttl_limits.target_ttl(None, 1), None);
+        assert_eq!(
+            TtlLimits {
+                min: Some(900),
+                max: None
+            }
+            .target_ttl(None, 1),
+            Some(900)
+        );
+    }
+
+    #[test]
+    fn completes_cloud_record_names_with_zone() {
+        let name = |name: &str| cloud_record_name("example.com", name).unwrap();
+        assert_eq!(name("home"), "home.example.com");
+        assert_eq!(name("home.example.com."), "home.example.com");
+        assert_eq!(name("@"), "example.com");
+        assert_eq!(name("example.com"), "example.com");
+        assert_eq!(name("a.b"), "a.b.example.com");
+        // A zone name inside a label is no suffix of the name
+        assert_eq!(name("myexample.com"), "myexample.com.example.com");
+        assert!(cloud_record_name("example.com", "bad_name").is_err());
+    }
+
+    #[test]
+    fn checks_content_matches_record_type() {
+        assert!(check_record_content("A", "192.0.2.1").is_ok());
+        assert!(check_record_content("AAAA", "2001:db8::1").is_ok());
+        assert!(matches!(
+            check_record_content("A", "2001:db8::1"),
+            Err(Error::InvalidRecordContent { .. })
+        ));
+        assert!(check_record_content("AAAA", "192.0.2.1").is_err());
+        assert!(check_record_content("A", "example.com").is_err());
+        assert_eq!(
+            check_record_content("AAAA", "2001:DB8:0::1").unwrap(),
+            "2001:db8::1"
+        );
+        assert!(check_record_content("CNAME", "origin.example.net").is_ok());
+        assert!(check_record_content("TXT", "v=spf1 -all").is_ok());
+        assert_eq!(parse_cloud_record_type("txt").unwrap(), "TXT");
+        assert!(parse_cloud_record_type("A;").is_err());
+    }
+
+    fn sync_options(dry_run: bool) -> SyncOptions {
+        SyncOptions {
+            ttl_limits: TtlLimits {
+                min: None,
+                max: None,
+            },
+            force: false,
+            no_create: false,
+            dry_run,
+        }
+    }
+
+    fn dns_record(content: &str) -> DnsRecord {
+        DnsRecord {
+            id: "372e67954025e0ba6aaa6d586b9e0b59".to_string(),
+            zone_id: "023e105f4ecef8ad9ca31a8372d0c353".to_string(),
+            name: "home.example.com".to_string(),
+            record_type: "A".to_string(),
+            content: content.to_string(),
+            ttl: 1,
+            proxied: false,
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn reports_failed_other_zone_update_as_error() {
+        let record = dns_record("192.0.2.1");
+        assert!(other_zone_update_result(
+            "home.example.com",
+            &record,
+            "192.0.2.7",
+            Ok(dns_record("192.0.2.7"))
+        )
+        .is_none());
+
+        let error = other_zone_update_result(
+            "home.example.com",
+            &record,
+            "192.0.2.7",
+            Err(CloudflareError::RecordNotUpdated {
+                domain: "home.example.com".to_string(),
+                record_type: "A".to_string(),
+            }),
+        )
+        .unwrap();
+        assert!(error.starts_with("home.example.com: Failed to update A record of zone"));
+    }
+
+    #[test]
+    fn records_where_the_token_comes_from() {
+        let args = |argv: &[&str]| {
+            Args::from_matches(&Args::command().try_get_matches_from(argv).unwrap()).unwrap()
+        };
+
+        let given = args(&["cf-dynamic", "--cloudflare-token", "secret", "list"]);
+        assert_eq!(given.cloudflare_token_source, ConfigSource::Cli);
+        let piped = args(&["cf-dynamic", "--cloudflare-token-stdin", "list"]);
+        assert_eq!(piped.cloudflare_token_source, ConfigSource::Stdin);
+        let domains = args(&["cf-dynamic", "-d", "domains.json", "list"]);
+        assert_eq!(domains.domains_file_source, ConfigSource::Cli);
+    }
+
+    #[test]
+    fn validates_record_types() {
+        let domain = |record_types: &[&str], cname_target: Option<&str>| DomainRegistration {
+            record_types: record_types.iter().map(|t| t.to_string()).collect(),
+            cname_target: cname_target.map(|target| target.to_string()),
+            ..DomainRegistration::new("cdn.example.com")
+        };
+
+        assert!(check_record_types(&domain(&["A", "AAAA"], None)).is_ok());
+        assert!(check_record_types(&domain(&["CNAME"], Some("origin.example.net"))).is_ok());
+        assert!(check_record_types(&domain(&["MX"], None)).is_err());
+        assert!(check_record_types(&domain(&["CNAME"], None)).is_err());
+        assert!(check_record_types(&domain(&["A"], Some("origin.example.net"))).is_err());
+        assert!(check_record_types(&domain(&["A", "CNAME"], Some("origin.example.net"))).is_err());
+    }
+
+    #[test]
+    fn matches_zone_in_ascii_form() {
+        let domain = DomainRegistration::new("home.example.com");
+        let zone = |zone: &str| Some(zone.to_string());
+
+        assert!(in_zone(&domain, &None));
+        assert!(in_zone(&domain, &zone("example.com")));
+        assert!(in_zone(&domain, &zone("Example.COM.")));
+        assert!(!in_zone(&domain, &zone("ample.com")));
+        assert!(in_zone(
+            &DomainRegistration::new("home.xn--mnchen-3ya.de"),
+            &zone("MÜNCHEN.de")
+        ));
+    }
+
+    #[test]
+    fn decides_record_action_from_lookup() {
+        let domain = DomainRegistration::new("home.example.com");
+        let action = |lookup, dry_run| {
+            record_action(&domain, "A", "192.0.2.7", lookup, &sync_options(dry_run))
+        };
+        let not_found = || CloudflareError::RecordNotFound {
+            domain: "home.example.com".to_string(),
+            record_type: "A".to_string(),
+        };
+
+        assert!(matches!(
+            action(Ok(dns_record("192.0.2.7")), false),
+            RecordAction::Done(RecordSync::Unchanged)
+        ));
+        assert!(matches!(
+            action(Ok(dns_record("192.0.2.1")), false),
+            RecordAction::Update { old_ip, .. } if old_ip == "192.0.2.1"
+        ));
+        assert!(matches!(
+            action(Ok(dns_record("192.0.2.1")), true),
+            RecordAction::Done(RecordSync::Skipped)
+        ));
+        assert!(matches!(
+            action(Err(not_found()), false),
+            RecordAction::Create { ttl: None }
+        ));
+        assert!(matches!(
+            action(Err(not_found()), true),
+            RecordAction::Done(RecordSync::Skipped)
+        ));
+    }
+
+    #[test]
+    fn never_creates_record_after_failed_lookup() {
+        let domain = DomainRegistration::new("home.example.com");
+        let lookup = Err(CloudflareError::ZoneNotFound {
+            domain: "home.example.com".to_string(),
+        });
+        assert!(matches!(
+            record_action(&domain, "A", "192.0.2.7", lookup, &sync_options(false)),
+            RecordAction::Done(RecordSync::Failed)
+        ));
+    }
 }
@@ -0,0 +1,157 @@
+use crate::error::MetricsError;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Response, Server};
+use tracing::{info, warn};
+
+/// Port the daemon serves `/metrics` on if none is given
+pub const DEFAULT_METRICS_PORT: u16 = 9909;
+
+/// Counters and gauges of the daemon, rendered in the Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    updates: BTreeMap<(String, String, &'static str), u64>,
+    ip_fetch_errors: BTreeMap<String, u64>,
+    last_updates: BTreeMap<String, u64>,
+    current_ips: BTreeMap<(String, String), String>,
+}
+
+impl Metrics {
+    pub fn record_update(&mut self, domain: &str, record_type: &str, status: &'static str) {
+        *self
+            .updates
+            .entry((domain.to_string(), record_type.to_string(), status))
+            .or_default() += 1;
+    }
+
+    pub fn record_ip_fetch_error(&mut self, provider: &str) {
+        *self
+            .ip_fetch_errors
+            .entry(provider.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn record_current_ip(&mut self, domain: &str, record_type: &str, ip: &str, timestamp: u64) {
+        self.last_updates.insert(domain.to_string(), timestamp);
+        self.current_ips.insert(
+            (domain.to_string(), record_type.to_string()),
+            ip.to_string(),
+        );
+    }
+
+    pub fn render(&self) -> String {
+        // Writing to a String can not fail
+        let mut out = String::new();
+        out.push_str("# HELP ddns_updates_total DNS record updates by result\n");
+        out.push_str("# TYPE ddns_updates_total counter\n");
+        for ((domain, record_type, status), count) in self.updates.iter() {
+            let _ = writeln!(
+                out,
+                "ddns_updates_total{{domain=\"{}\",record_type=\"{}\",status=\"{}\"}} {}",
+                escape_label(domain),
+                record_type,
+                status,
+                count
+            );
+        }
+
+        out.push_str("# HELP ddns_ip_fetch_errors_total Failed detections of the public ip\n");
+        out.push_str("# TYPE ddns_ip_fetch_errors_total counter\n");
+        for (provider, count) in self.ip_fetch_errors.iter() {
+            let _ = writeln!(
+                out,
+                "ddns_ip_fetch_errors_total{{provider=\"{}\"}} {}",
+                escape_label(provider),
+                count
+            );
+        }
+
+        out.push_str(
+            "# HELP ddns_last_update_timestamp Unix time the domain last had the current ip\n",
+        );
+        out.push_str("# TYPE ddns_last_update_timestamp gauge\n");
+        for (domain, timestamp) in self.last_updates.iter() {
+            let _ = writeln!(
+                out,
+                "ddns_last_update_timestamp{{domain=\"{}\"}} {}",
+                escape_label(domain),
+                timestamp
+            );
+        }
+
+        out.push_str("# HELP ddns_current_ip Current ip of the DNS record, given as label\n");
+        out.push_str("# TYPE ddns_current_ip gauge\n");
+        for ((domain, record_type), ip) in self.current_ips.iter() {
+            let _ = writeln!(
+                out,
+                "ddns_current_ip{{domain=\"{}\",record_type=\"{}\",ip=\"{}\"}} 1",
+                escape_label(domain),
+                record_type,
+                ip
+            );
+        }
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub fn serve(port: u16, metrics: Arc<Mutex<Metrics>>) -> Result<(), MetricsError> {
+    // Only bound locally, the requests are answered on their own thread so the update loop never waits
+    let server =
+        Server::http(("127.0.0.1", port)).map_err(|source| MetricsError::Bind { port, source })?;
+    info!("Serving metrics on http://127.0.0.1:{}/metrics", port);
+
+    thread::spawn(move || {
+        let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+        for request in server.incoming_requests() {
+            let response = match request.url() {
+                "/metrics" => Response::from_string(metrics.lock().unwrap().render())
+                    .with_header(content_type.clone()),
+                _ => Response::from_string("Not Found").with_status_code(404),
+            };
+            if let Err(e) = request.respond(response) {
+                warn!("Unable to answer metrics request: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_prometheus_text_format() {
+        let mut metrics = Metrics::default();
+        metrics.record_update("a.example.com", "A", "updated");
+        metrics.record_update("a.example.com", "A", "updated");
+        metrics.record_ip_fetch_error("interface:eth0");
+        metrics.record_current_ip("a.example.com", "A", "192.0.2.1", 1700000000);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "ddns_updates_total{domain=\"a.example.com\",record_type=\"A\",status=\"updated\"} 2\n"
+        ));
+        assert!(rendered.contains("ddns_ip_fetch_errors_total{provider=\"interface:eth0\"} 1\n"));
+        assert!(
+            rendered.contains("ddns_last_update_timestamp{domain=\"a.example.com\"} 1700000000\n")
+        );
+        assert!(rendered.contains(
+            "ddns_current_ip{domain=\"a.example.com\",record_type=\"A\",ip=\"192.0.2.1\"} 1\n"
+        ));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
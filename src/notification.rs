@@ -0,0 +1,490 @@
+use crate::error::NotificationError;
+use crate::proxy::ProxyConfig;
+use crate::time::format_timestamp;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{Message, SmtpTransport, Transport};
+use reqwest::blocking::Client;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds to wait for a webhook to respond
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+pub const TELEGRAM_API_URL: &str = "https://api.telegram.org";
+/// Seconds to wait for the SMTP server
+const SMTP_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_SMTP_PORT: u16 = 587;
+
+/// A DNS record that was created, updated or failed to update
+#[derive(Serialize, Clone, Debug)]
+pub struct UpdateEvent {
+    pub domain: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    /// Empty for created records and failed updates
+    pub old_ip: String,
+    pub new_ip: String,
+    pub success: bool,
+    pub timestamp: u64,
+}
+
+/// A backend told about every DNS record an update changed or failed to change
+pub trait Notifier {
+    /// Named in warnings if the notification fails
+    fn name(&self) -> &'static str;
+
+    fn notify(&self, event: &UpdateEvent) -> Result<(), NotificationError>;
+
+    /// All events of one update and the errors that skipped records before they were synced,
+    /// notifiers that can send a summary do so instead of one notification per event.
+    /// Every event is sent even if an earlier one fails
+    fn notify_all(
+        &self,
+        events: &[UpdateEvent],
+        _errors: &[String],
+    ) -> Result<(), NotificationError> {
+        let mut failures: Vec<NotificationError> = events
+            .iter()
+            .filter_map(|event| self.notify(event).err())
+            .collect();
+        match failures.len() {
+            0 => Ok(()),
+            1 => Err(failures.remove(0)),
+            _ => Err(NotificationError::Several(failures)),
+        }
+    }
+}
+
+/// Posts every event as json to a URL, domains may have their own URL
+pub struct WebhookNotifier {
+    url: Option<String>,
+    domain_urls: HashMap<String, String>,
+    client: Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(
+        url: Option<String>,
+        domain_urls: HashMap<String, String>,
+        proxy: &ProxyConfig,
+    ) -> Result<WebhookNotifier, NotificationError> {
+        Ok(WebhookNotifier {
+            url,
+            domain_urls,
+            client: http_client(proxy)?,
+        })
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn notify(&self, event: &UpdateEvent) -> Result<(), NotificationError> {
+        let Some(url) = self.domain_urls.get(&event.domain).or(self.url.as_ref()) else {
+            return Ok(());
+        };
+        let response = self
+            .client
+            .post(url.as_str())
+            .json(event)
+            .send()
+            .map_err(NotificationError::Http)?;
+
+        match response.status().is_success() {
+            true => Ok(()),
+            false => Err(NotificationError::Status {
+                url: url.clone(),
+                status: response.status(),
+            }),
+        }
+    }
+}
+
+/// Sends messages to a Telegram chat via a bot
+pub struct TelegramNotifier {
+    api_url: String,
+    chat_id: String,
+    client: Client,
+}
+
+impl TelegramNotifier {
+    pub fn new(
+        token: &str,
+        chat_id: &str,
+        proxy: &ProxyConfig,
+    ) -> Result<TelegramNotifier, NotificationError> {
+        // The environment allows testing against a mock server
+        let api_url = env::var("TELEGRAM_API_URL").unwrap_or_else(|_| TELEGRAM_API_URL.to_string());
+        Ok(TelegramNotifier {
+            api_url: format!("{}/bot{}", api_url.trim_end_matches('/'), token),
+            chat_id: chat_id.to_string(),
+            client: http_client(proxy)?,
+        })
+    }
+
+    fn send_message(&self, text: &str) -> Result<(), NotificationError> {
+        // Errors must not contain the URL, it includes the bot token
+        let response = self
+            .client
+            .post(format!("{}/sendMessage", self.api_url))
+            .json(&json!({ "chat_id": self.chat_id, "text": text }))
+            .send()
+            .map_err(|e| NotificationError::Telegram(e.without_url()))?;
+
+        match response.status().is_success() {
+            true => Ok(()),
+            false => Err(NotificationError::TelegramStatus(response.status())),
+        }
+    }
+}
+
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &'static str {
+        "Telegram"
+    }
+
+    fn notify(&self, event: &UpdateEvent) -> Result<(), NotificationError> {
+        self.notify_all(std::slice::from_ref(event), &[])
+    }
+
+    fn notify_all(
+        &self,
+        events: &[UpdateEvent],
+        errors: &[String],
+    ) -> Result<(), NotificationError> {
+        self.send_message(&format!(
+            "{}\n\n{}",
+            summary_subject(events, errors),
+            summary_body(events, errors)
+        ))
+    }
+}
+
+/// Appends a line per event to a file, e.g. for log monitoring
+pub struct LogFileNotifier {
+    path: PathBuf,
+}
+
+impl LogFileNotifier {
+    pub fn new(path: PathBuf) -> LogFileNotifier {
+        LogFileNotifier { path }
+    }
+}
+
+impl Notifier for LogFileNotifier {
+    fn name(&self) -> &'static str {
+        "log file"
+    }
+
+    fn notify(&self, event: &UpdateEvent) -> Result<(), NotificationError> {
+        self.notify_all(std::slice::from_ref(event), &[])
+    }
+
+    fn notify_all(
+        &self,
+        events: &[UpdateEvent],
+        errors: &[String],
+    ) -> Result<(), NotificationError> {
+        // The errors have no time of their own, they happened at the time of the update
+        let timestamp = events.first().map_or_else(now, |event| event.timestamp);
+        let mut lines = String::new();
+        for event in events.iter() {
+            let _ = writeln!(
+                lines,
+                "{} {}",
+                format_timestamp(event.timestamp),
+                event_line(event)
+            );
+        }
+        for error in errors.iter() {
+            let _ = writeln!(lines, "{} {}", format_timestamp(timestamp), error);
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(lines.as_bytes()))
+            .map_err(|source| NotificationError::LogFile {
+                path: self.path.clone(),
+                source,
+            })
+    }
+}
+
+fn http_client(proxy: &ProxyConfig) -> Result<Client, NotificationError> {
+    let client = Client::builder().timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS));
+    match proxy.proxy() {
+        Some(proxy) => client.proxy(proxy),
+        None => client.no_proxy(),
+    }
+    .build()
+    .map_err(NotificationError::Client)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// SMTP server and addresses of the summary emails
+#[derive(Clone, Debug)]
+pub struct EmailConfig {
+    pub host: String,
+    pub port: u16,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: String,
+    /// Accept invalid TLS certificates of the SMTP server
+    pub insecure: bool,
+}
+
+/// Sends one email per update with all changed records and errors
+pub struct EmailNotifier {
+    config: EmailConfig,
+}
+
+impl EmailNotifier {
+    pub fn new(config: EmailConfig) -> EmailNotifier {
+        EmailNotifier { config }
+    }
+
+    fn transport(&self) -> Result<SmtpTransport, NotificationError> {
+        // Port 465 expects TLS right away, 587 requires STARTTLS, any other port uses it if offered
+        let smtp_error = |source| NotificationError::Smtp {
+            host: self.config.host.clone(),
+            source,
+        };
+        let tls_parameters = TlsParameters::builder(self.config.host.clone())
+            .dangerous_accept_invalid_certs(self.config.insecure)
+            .build()
+            .map_err(smtp_error)?;
+        let tls = match self.config.port {
+            465 => Tls::Wrapper(tls_parameters),
+            587 => Tls::Required(tls_parameters),
+            _ => Tls::Opportunistic(tls_parameters),
+        };
+
+        let mut transport = SmtpTransport::builder_dangerous(self.config.host.as_str())
+            .port(self.config.port)
+            .tls(tls)
+            .timeout(Some(Duration::from_secs(SMTP_TIMEOUT_SECS)));
+        if let (Some(user), Some(password)) = (&self.config.user, &self.config.password) {
+            transport = transport.credentials(Credentials::new(user.clone(), password.clone()));
+        }
+        Ok(transport.build())
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn notify(&self, event: &UpdateEvent) -> Result<(), NotificationError> {
+        self.notify_all(std::slice::from_ref(event), &[])
+    }
+
+    fn notify_all(
+        &self,
+        events: &[UpdateEvent],
+        errors: &[String],
+    ) -> Result<(), NotificationError> {
+        let mailbox = |address: &str| {
+            address
+                .parse::<Mailbox>()
+                .map_err(|source| NotificationError::Address {
+                    address: address.to_string(),
+                    source,
+                })
+        };
+        let message = Message::builder()
+            .from(mailbox(&self.config.from)?)
+            .to(mailbox(&self.config.to)?)
+            .subject(summary_subject(events, errors))
+            .body(summary_body(events, errors))
+            .map_err(NotificationError::Email)?;
+
+        self.transport()?
+            .send(&message)
+            .map(|_| ())
+            .map_err(|source| NotificationError::Smtp {
+                host: self.config.host.clone(),
+                source,
+            })
+    }
+}
+
+fn event_line(event: &UpdateEvent) -> String {
+    let old_ip = match event.old_ip.is_empty() {
+        true => "-",
+        false => event.old_ip.as_str(),
+    };
+    let result = match event.success {
+        true => "updated",
+        false => "failed",
+    };
+    format!(
+        "{} {}: {} -> {} ({})",
+        event.domain, event.record_type, old_ip, event.new_ip, result
+    )
+}
+
+fn summary_subject(events: &[UpdateEvent], errors: &[String]) -> String {
+    let changed = events.iter().filter(|event| event.success).count();
+    let failed = events.len() - changed + errors.len();
+    match failed {
+        0 => format!("cf-dynamic: {} DNS records updated", changed),
+        _ => format!(
+            "cf-dynamic: {} DNS records updated, {} errors",
+            changed, failed
+        ),
+    }
+}
+
+fn summary_body(events: &[UpdateEvent], errors: &[String]) -> String {
+    // One line per record, followed by the errors that skipped records before they were synced
+    let mut body = String::new();
+    for event in events.iter() {
+        let _ = writeln!(body, "{}", event_line(event));
+    }
+    if !errors.is_empty() {
+        if !body.is_empty() {
+            body.push('\n');
+        }
+        body.push_str("Errors:\n");
+        for error in errors.iter() {
+            let _ = writeln!(body, "{}", error);
+        }
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn event(
+        domain: &str,
+        record_type: &str,
+        old_ip: &str,
+        new_ip: &str,
+        success: bool,
+    ) -> UpdateEvent {
+        UpdateEvent {
+            domain: domain.to_string(),
+            record_type: record_type.to_string(),
+            old_ip: old_ip.to_string(),
+            new_ip: new_ip.to_string(),
+            success,
+            timestamp: 1,
+        }
+    }
+
+    /// Fails every event of the given domains
+    struct FailingNotifier {
+        failing: Vec<&'static str>,
+        sent: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl Notifier for FailingNotifier {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn notify(&self, event: &UpdateEvent) -> Result<(), NotificationError> {
+            self.sent.borrow_mut().push(event.domain.clone());
+            match self.failing.contains(&event.domain.as_str()) {
+                true => Err(NotificationError::TelegramStatus(
+                    reqwest::StatusCode::BAD_GATEWAY,
+                )),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn sends_every_event_despite_failures() {
+        let notifier = FailingNotifier {
+            failing: vec!["a.example.com", "c.example.com"],
+            sent: Default::default(),
+        };
+        let events: Vec<UpdateEvent> = ["a.example.com", "b.example.com", "c.example.com"]
+            .iter()
+            .map(|domain| event(domain, "A", "192.0.2.1", "192.0.2.2", true))
+            .collect();
+
+        let result = notifier.notify_all(&events, &[]);
+
+        assert_eq!(
+            *notifier.sent.borrow(),
+            vec!["a.example.com", "b.example.com", "c.example.com"]
+        );
+        assert!(matches!(
+            result,
+            Err(NotificationError::Several(ref failures)) if failures.len() == 2
+        ));
+        assert!(matches!(
+            notifier.notify_all(&events[..2], &[]),
+            Err(NotificationError::TelegramStatus(_))
+        ));
+    }
+
+    #[test]
+    fn appends_events_to_log_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("notifications.log");
+        let notifier = LogFileNotifier::new(path.clone());
+
+        notifier
+            .notify(&event("a.example.com", "A", "192.0.2.1", "192.0.2.2", true))
+            .unwrap();
+        notifier
+            .notify_all(
+                &[event("b.example.com", "A", "", "192.0.2.2", false)],
+                &["c.example.com: Unable to detect ipv4 address".to_string()],
+            )
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "1970-01-01T00:00:01Z a.example.com A: 192.0.2.1 -> 192.0.2.2 (updated)\n\
+             1970-01-01T00:00:01Z b.example.com A: - -> 192.0.2.2 (failed)\n\
+             1970-01-01T00:00:01Z c.example.com: Unable to detect ipv4 address\n"
+        );
+    }
+
+    #[test]
+    fn summarizes_changes_and_errors() {
+        let events = vec![
+            event("a.example.com", "A", "192.0.2.1", "192.0.2.2", true),
+            event("b.example.com", "AAAA", "", "2001:db8::2", false),
+        ];
+        let errors = vec!["c.example.com: Unable to detect ipv4 address".to_string()];
+
+        assert_eq!(
+            summary_subject(&events, &errors),
+            "cf-dynamic: 1 DNS records updated, 2 errors"
+        );
+        assert_eq!(
+            summary_body(&events, &errors),
+            "a.example.com A: 192.0.2.1 -> 192.0.2.2 (updated)\n\
+             b.example.com AAAA: - -> 2001:db8::2 (failed)\n\
+             \n\
+             Errors:\n\
+             c.example.com: Unable to detect ipv4 address\n"
+        );
+    }
+}
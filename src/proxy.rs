@@ -0,0 +1,180 @@
+use reqwest::{Proxy, Url};
+use std::env;
+use std::net::IpAddr;
+use tracing::warn;
+
+/// Proxies of all HTTP clients, from the http_proxy config entry or HTTP_PROXY, HTTPS_PROXY and NO_PROXY
+#[derive(Clone, Default, Debug)]
+pub struct ProxyConfig {
+    http: Option<Url>,
+    https: Option<Url>,
+    no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+    pub fn new(config_proxy: Option<&String>) -> ProxyConfig {
+        // The config entry is used for both schemes and wins over the environment
+        let env_proxy = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+        };
+        let http = config_proxy
+            .cloned()
+            .or_else(|| env_proxy(&["HTTP_PROXY", "http_proxy"]));
+        let https = config_proxy
+            .cloned()
+            .or_else(|| env_proxy(&["HTTPS_PROXY", "https_proxy"]));
+        let no_proxy = env_proxy(&["NO_PROXY", "no_proxy"]).unwrap_or_default();
+
+        ProxyConfig {
+            http: http.as_deref().and_then(parse_proxy_url),
+            https: https.as_deref().and_then(parse_proxy_url),
+            no_proxy: parse_no_proxy(&no_proxy),
+        }
+    }
+
+    pub fn proxy(&self) -> Option<Proxy> {
+        // Without any proxy the clients connect directly, they never look at the environment themselves
+        if self.http.is_none() && self.https.is_none() {
+            return None;
+        }
+        let proxy_config = self.clone();
+        Some(Proxy::custom(move |url| proxy_config.proxy_for(url)))
+    }
+
+    pub fn proxy_for(&self, url: &Url) -> Option<Url> {
+        let proxy = match url.scheme() {
+            "https" => self.https.clone(),
+            "http" => self.http.clone(),
+            _ => None,
+        }?;
+        match url.host_str() {
+            Some(host) if self.bypasses(host) => None,
+            _ => Some(proxy),
+        }
+    }
+
+    fn bypasses(&self, host: &str) -> bool {
+        // NO_PROXY entries match the host itself and its subdomains, a leading dot is optional
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        self.no_proxy.iter().any(|entry| {
+            entry == "*"
+                || host == entry
+                || (host
+                    .strip_suffix(entry.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+                    && host.parse::<IpAddr>().is_err())
+        })
+    }
+}
+
+pub fn proxy_url(proxy: &str) -> Result<Url, String> {
+    // Proxies are often given without a scheme, e.g. proxy.local:3128
+    let with_scheme = match proxy.contains("://") {
+        true => proxy.to_string(),
+        false => format!("http://{}", proxy),
+    };
+    Url::parse(&with_scheme).map_err(|e| e.to_string())
+}
+
+fn parse_proxy_url(proxy: &str) -> Option<Url> {
+    match proxy_url(proxy) {
+        Ok(url) => Some(url),
+        Err(e) => {
+            warn!("Ignoring invalid proxy '{}': {}", proxy, e);
+            None
+        }
+    }
+}
+
+fn parse_no_proxy(no_proxy: &str) -> Vec<String> {
+    // Ports are ignored, hosts are compared case insensitively
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches('.').to_lowercase())
+        .map(|entry| match entry.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') && port.parse::<u16>().is_ok() => {
+                host.to_string()
+            }
+            _ => entry
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+        })
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::blocking::Client;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn proxy_config(proxy: &str, no_proxy: &str) -> ProxyConfig {
+        ProxyConfig {
+            http: parse_proxy_url(proxy),
+            https: parse_proxy_url(proxy),
+            no_proxy: parse_no_proxy(no_proxy),
+        }
+    }
+
+    #[test]
+    fn skips_hosts_of_no_proxy() {
+        let proxy_config = proxy_config("proxy.local:3128", ".example.com, localhost:8080, ::1");
+        let proxy_for = |url: &str| proxy_config.proxy_for(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            proxy_for("https://api.cloudflare.com/client/v4"),
+            Url::parse("http://proxy.local:3128").ok()
+        );
+        assert_eq!(proxy_for("https://example.com/"), None);
+        assert_eq!(proxy_for("http://ip.example.com/"), None);
+        assert_eq!(proxy_for("http://localhost/"), None);
+        assert_eq!(proxy_for("http://[::1]/"), None);
+        assert!(proxy_for("https://notexample.com/").is_some());
+    }
+
+    #[test]
+    fn sends_requests_through_proxy() {
+        // The proxy answers every request itself, so the requested host does not need to exist
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request_line = String::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            reader.read_line(&mut request_line).unwrap();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n192.0.2.7\n")
+                .unwrap();
+            request_line
+        });
+
+        let client = Client::builder()
+            .proxy(proxy_config(&proxy, "").proxy().unwrap())
+            .build()
+            .unwrap();
+        let body = client
+            .get("http://ip.example.invalid/")
+            .send()
+            .and_then(|response| response.text())
+            .unwrap();
+
+        assert_eq!(body, "192.0.2.7\n");
+        assert_eq!(
+            handle.join().unwrap().trim(),
+            "GET http://ip.example.invalid/ HTTP/1.1"
+        );
+    }
+}
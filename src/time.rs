@@ -0,0 +1,149 @@
+use crate::error::TimestampError;
+
+pub fn format_timestamp(secs: u64) -> String {
+    // Unix time as YYYY-MM-DDTHH:MM:SSZ
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let time = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+pub fn parse_timestamp(time: &str) -> Result<u64, TimestampError> {
+    // Unix timestamps or RFC 3339 times like 2024-01-01T00:00:00Z or 2024-01-01T01:00:00+01:00
+    match time.parse::<u64>() {
+        Ok(secs) => Ok(secs),
+        Err(_) => parse_rfc3339(time).ok_or_else(|| TimestampError::Invalid(time.to_string())),
+    }
+}
+
+fn parse_rfc3339(time: &str) -> Option<u64> {
+    let (date, time) = time.split_once(['T', 't', ' '])?;
+    let mut date_parts = date.splitn(3, '-');
+    let year = date_parts.next()?.parse::<i64>().ok()?;
+    let month = date_parts.next()?.parse::<u64>().ok()?;
+    let day = date_parts.next()?.parse::<u64>().ok()?;
+    // Out of range fields would overflow the calendar arithmetic below
+    if !(0..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // The offset is subtracted to get UTC, e.g. 01:00:00+01:00 is 00:00:00Z
+    let (time, offset) = match time.strip_suffix(['Z', 'z']) {
+        Some(time) => (time, 0),
+        None => {
+            let split = time.rfind(['+', '-'])?;
+            let (time, offset) = time.split_at(split);
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let (hours, minutes) = (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?);
+            if !(0..=23).contains(&hours) || !(0..=59).contains(&minutes) {
+                return None;
+            }
+            let secs = hours * 3600 + minutes * 60;
+            match offset.starts_with('-') {
+                true => (time, -secs),
+                false => (time, secs),
+            }
+        }
+    };
+    let mut time_parts = time.splitn(3, ':');
+    let hour = time_parts.next()?.parse::<i64>().ok()?;
+    let minute = time_parts.next()?.parse::<i64>().ok()?;
+    // Fractions of a second are dropped
+    let second = time_parts.next()?.split('.').next()?.parse::<i64>().ok()?;
+    if !(0..=23).contains(&hour) || !(0..=59).contains(&minute) || !(0..=60).contains(&second) {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second - offset;
+    u64::try_from(secs).ok()
+}
+
+fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+    // Inverse of civil_from_days, days since 1970-01-01 of a date
+    let year = year - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400) as u64;
+    let shifted_month = match month > 2 {
+        true => month - 3,
+        false => month + 9,
+    };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era as i64 - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    // Convert days since 1970-01-01 to a date of the proleptic gregorian calendar
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097) as u64;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = match shifted_month < 10 {
+        true => shifted_month + 3,
+        false => shifted_month - 9,
+    };
+    let year = year_of_era as i64 + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_unix_and_rfc3339_timestamps() {
+        assert_eq!(parse_timestamp("1704067200").unwrap(), 1704067200);
+        assert_eq!(parse_timestamp("2024-01-01T00:00:00Z").unwrap(), 1704067200);
+        assert_eq!(
+            parse_timestamp("2024-01-01T01:30:00.250+01:30").unwrap(),
+            1704067200
+        );
+        assert_eq!(
+            parse_timestamp("2023-12-31 19:00:00-05:00").unwrap(),
+            1704067200
+        );
+        assert_eq!(parse_timestamp("2024-02-29T12:00:00Z").unwrap(), 1709208000);
+        assert!(parse_timestamp("2023-02-29T00:00:00Z").is_err());
+        assert!(parse_timestamp("2024-01-01T25:00:00Z").is_err());
+        assert!(parse_timestamp("2024-01-01T00:00:00").is_err());
+        assert!(parse_timestamp("yesterday").is_err());
+        assert!(parse_timestamp("2024-03-00T00:00:00Z").is_err());
+        assert!(parse_timestamp("2024-00-01T00:00:00Z").is_err());
+        assert!(parse_timestamp("2024-13-01T00:00:00Z").is_err());
+        assert!(parse_timestamp("2024-99999999999999999-01T00:00:00Z").is_err());
+        assert!(parse_timestamp("2024-01-32T00:00:00Z").is_err());
+        assert!(parse_timestamp("99999999999999999-01-01T00:00:00Z").is_err());
+        assert!(parse_timestamp("2024-01-01T-1:00:00Z").is_err());
+        assert!(parse_timestamp("2024-01-01T00:00:00+99999999999999:00").is_err());
+        assert!(parse_timestamp("2024-01-01T00:00:00+24:00").is_err());
+        assert!(parse_timestamp("2024-01-01T00:00:00+01:60").is_err());
+    }
+
+    #[test]
+    fn converts_days_to_date() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(20740), (2026, 10, 14));
+    }
+
+    #[test]
+    fn formats_timestamp_as_rfc3339() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(1709208000), "2024-02-29T12:00:00Z");
+    }
+}
@@ -0,0 +1,524 @@
+//! Runs `cf-dynamic update` against mocks of the Cloudflare API and the ip detection service
+
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use tempfile::TempDir;
+use wiremock::matchers::{body_json, header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+static ZONES_PAGE_1: &str = include_str!("fixtures/zones_page_1.json");
+static ZONES_PAGE_2: &str = include_str!("fixtures/zones_page_2.json");
+static API_ERROR: &str = include_str!("fixtures/api_error.json");
+
+const ZONE_ID: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+const RECORD_ID: &str = "372e67954025e0ba6aaa6d586b9e0b59";
+const DOMAIN: &str = "home.example.com";
+const DETECTED_IP: &str = "198.51.100.7";
+const OLD_IP: &str = "198.51.100.1";
+
+struct TestSetup {
+    server: MockServer,
+    directory: TempDir,
+}
+
+impl TestSetup {
+    async fn new(domain: &str) -> TestSetup {
+        // Only the A record is managed, the mocked ip detection service answers for ipv4
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/ipv4"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!("{}\n", DETECTED_IP)))
+            .mount(&server)
+            .await;
+
+        let directory = tempfile::tempdir().unwrap();
+        fs::write(
+            directory.path().join("cf-dynamic.conf"),
+            "cloudflare_token=test-token\nmax_retries=2\nretry_base_delay_ms=1\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.path().join("domains.json"),
+            json!([{ "domain": domain, "v6_disabled": true }]).to_string(),
+        )
+        .unwrap();
+        TestSetup { server, directory }
+    }
+
+    async fn mock(&self, mock: Mock) {
+        mock.mount(&self.server).await;
+    }
+
+    fn update(&self) -> Output {
+        self.run(&["update"])
+    }
+
+    fn run(&self, command: &[&str]) -> Output {
+        // The environment of the test run must not leak into the command, e.g. proxies or another token
+        Command::new(env!("CARGO_BIN_EXE_cf-dynamic"))
+            .arg("--config-file")
+            .arg(self.directory.path().join("cf-dynamic.conf"))
+            .arg("--domains-file")
+            .arg(self.domains_path())
+            .args([
+                "--ip-provider",
+                &format!("{}/{{version}}", self.server.uri()),
+            ])
+            .args(["--allow-private-ips", "--no-color"])
+            .args(command)
+            .env(
+                "CLOUDFLARE_API_URL",
+                format!("{}/client/v4", self.server.uri()),
+            )
+            .env_remove("CLOUDFLARE_TOKEN")
+            .env_remove("CF_DYNAMIC_PROFILE")
+            .env_remove("HTTP_PROXY")
+            .env_remove("HTTPS_PROXY")
+            .env_remove("http_proxy")
+            .env_remove("https_proxy")
+            .output()
+            .unwrap()
+    }
+
+    fn domains_path(&self) -> PathBuf {
+        self.directory.path().join("domains.json")
+    }
+
+    async fn cloudflare_requests(&self) -> Vec<String> {
+        // Method, path and query of every request to the Cloudflare API in the order they were made
+        self.server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter_map(|request| {
+                let path = request.url.path().strip_prefix("/client/v4/")?;
+                Some(match request.url.query() {
+                    Some(query) => format!("{} {}?{}", request.method, path, query),
+                    None => format!("{} {}", request.method, path),
+                })
+            })
+            .collect()
+    }
+}
+
+fn page(result: Value) -> Value {
+    json!({
+        "success": true,
+        "errors": [],
+        "result": result,
+        "result_info": { "page": 1, "per_page": 100, "total_count": 1, "total_pages": 1 }
+    })
+}
+
+fn record(zone_id: &str, content: &str) -> Value {
+    json!({
+        "id": RECORD_ID,
+        "zone_id": zone_id,
+        "name": DOMAIN,
+        "type": "A",
+        "content": content,
+        "ttl": 1,
+        "proxied": false
+    })
+}
+
+fn zones_mock() -> Mock {
+    Mock::given(method("GET"))
+        .and(path("/client/v4/zones"))
+        .and(header("Authorization", "Bearer test-token"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(page(json!([{ "id": ZONE_ID, "name": "example.com" }]))),
+        )
+}
+
+fn records_mock(zone_id: &str, records: Value) -> Mock {
+    Mock::given(method("GET"))
+        .and(path(format!("/client/v4/zones/{}/dns_records", zone_id)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(page(records)))
+}
+
+fn zones_request() -> String {
+    "GET zones?page=1&per_page=100".to_string()
+}
+
+fn records_request(zone_id: &str) -> String {
+    format!(
+        "GET zones/{}/dns_records?type=A&type=AAAA&type=CNAME&page=1&per_page=100",
+        zone_id
+    )
+}
+
+fn read_domains(setup: &TestSetup) -> Value {
+    serde_json::from_str(&fs::read_to_string(setup.domains_path()).unwrap()).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn updates_record_when_ip_changed() {
+    let setup = TestSetup::new(DOMAIN).await;
+    setup.mock(zones_mock()).await;
+    setup
+        .mock(records_mock(ZONE_ID, json!([record(ZONE_ID, OLD_IP)])))
+        .await;
+    setup
+        .mock(
+            Mock::given(method("PUT"))
+                .and(path(format!(
+                    "/client/v4/zones/{}/dns_records/{}",
+                    ZONE_ID, RECORD_ID
+                )))
+                .and(body_json(
+                    json!({ "content": DETECTED_IP, "proxied": false }),
+                ))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_json(page(record(ZONE_ID, DETECTED_IP))),
+                ),
+        )
+        .await;
+
+    let output = setup.update();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        setup.cloudflare_requests().await,
+        vec![
+            zones_request(),
+            records_request(ZONE_ID),
+            format!("PUT zones/{}/dns_records/{}", ZONE_ID, RECORD_ID),
+        ]
+    );
+    assert_eq!(read_domains(&setup)[0]["last_v4"], DETECTED_IP);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn changes_nothing_when_ip_unchanged() {
+    let setup = TestSetup::new(DOMAIN).await;
+    setup.mock(zones_mock()).await;
+    setup
+        .mock(records_mock(ZONE_ID, json!([record(ZONE_ID, DETECTED_IP)])))
+        .await;
+
+    let output = setup.update();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        setup.cloudflare_requests().await,
+        vec![zones_request(), records_request(ZONE_ID)]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn creates_missing_record() {
+    let setup = TestSetup::new(DOMAIN).await;
+    setup.mock(zones_mock()).await;
+    setup.mock(records_mock(ZONE_ID, json!([]))).await;
+    setup
+        .mock(
+            Mock::given(method("POST"))
+                .and(path(format!("/client/v4/zones/{}/dns_records", ZONE_ID)))
+                .and(body_json(json!({
+                    "type": "A",
+                    "name": DOMAIN,
+                    "content": DETECTED_IP,
+                    "ttl": 1,
+                    "proxied": false,
+                    "comment": null
+                })))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_json(page(record(ZONE_ID, DETECTED_IP))),
+                ),
+        )
+        .await;
+
+    let output = setup.update();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        setup.cloudflare_requests().await,
+        vec![
+            zones_request(),
+            records_request(ZONE_ID),
+            format!("POST zones/{}/dns_records", ZONE_ID),
+        ]
+    );
+    assert_eq!(read_domains(&setup)[0]["last_v4"], DETECTED_IP);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn finds_zone_on_second_page() {
+    // example.net is only listed on the second page of zones
+    let zone_id = "3c3b3e1a5e2d4f8b9c0d1e2f3a4b5c6d";
+    let setup = TestSetup::new("home.example.net").await;
+    for (page, body) in [("1", ZONES_PAGE_1), ("2", ZONES_PAGE_2)] {
+        setup
+            .mock(
+                Mock::given(method("GET"))
+                    .and(path("/client/v4/zones"))
+                    .and(query_param("page", page))
+                    .respond_with(ResponseTemplate::new(200).set_body_string(body)),
+            )
+            .await;
+    }
+    let mut net_record = record(zone_id, DETECTED_IP);
+    net_record["name"] = json!("home.example.net");
+    setup.mock(records_mock(zone_id, json!([net_record]))).await;
+
+    let output = setup.update();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        setup.cloudflare_requests().await,
+        vec![
+            zones_request(),
+            "GET zones?page=2&per_page=100".to_string(),
+            records_request(zone_id),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_rate_limited_requests() {
+    let setup = TestSetup::new(DOMAIN).await;
+    setup
+        .mock(
+            Mock::given(method("GET"))
+                .and(path("/client/v4/zones"))
+                .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+                .up_to_n_times(1)
+                .with_priority(1),
+        )
+        .await;
+    setup.mock(zones_mock()).await;
+    setup
+        .mock(records_mock(ZONE_ID, json!([record(ZONE_ID, DETECTED_IP)])))
+        .await;
+
+    let output = setup.update();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        setup.cloudflare_requests().await,
+        vec![zones_request(), zones_request(), records_request(ZONE_ID)]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gives_up_on_authentication_failure() {
+    let setup = TestSetup::new(DOMAIN).await;
+    setup
+        .mock(
+            Mock::given(method("GET"))
+                .and(path("/client/v4/zones"))
+                .respond_with(ResponseTemplate::new(401).set_body_string(API_ERROR)),
+        )
+        .await;
+
+    let output = setup.update();
+
+    // The lookup and the search in other zones each give up after their first request, invalid
+    // credentials are never retried and a failed lookup never leads to creating the record
+    assert_eq!(setup.cloudflare_requests().await, vec![zones_request(); 2]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("9109:Invalid access token"));
+    assert_eq!(read_domains(&setup)[0]["last_v4"], Value::Null);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cloud_add_creates_record_without_registering_it() {
+    let setup = TestSetup::new("other.example.com").await;
+    setup.mock(zones_mock()).await;
+    setup.mock(records_mock(ZONE_ID, json!([]))).await;
+    setup
+        .mock(
+            Mock::given(method("POST"))
+                .and(path(format!("/client/v4/zones/{}/dns_records", ZONE_ID)))
+                .and(body_json(json!({
+                    "type": "A",
+                    "name": DOMAIN,
+                    "content": OLD_IP,
+                    "ttl": 300,
+                    "proxied": false,
+                    "comment": null
+                })))
+                .respond_with(
+                    ResponseTemplate::new(200).set_body_json(page(record(ZONE_ID, OLD_IP))),
+                ),
+        )
+        .await;
+    let domains = fs::read_to_string(setup.domains_path()).unwrap();
+
+    let output = setup.run(&[
+        "cloud",
+        "add",
+        "example.com",
+        "home",
+        "a",
+        OLD_IP,
+        "--ttl",
+        "300",
+    ]);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        setup.cloudflare_requests().await,
+        vec![
+            zones_request(),
+            format!(
+                "GET zones/{}/dns_records?type=A&page=1&per_page=100",
+                ZONE_ID
+            ),
+            format!("POST zones/{}/dns_records", ZONE_ID),
+        ]
+    );
+    assert_eq!(fs::read_to_string(setup.domains_path()).unwrap(), domains);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cloud_list_shows_records_of_every_type() {
+    let setup = TestSetup::new(DOMAIN).await;
+    setup.mock(zones_mock()).await;
+    let mut txt_record = record(ZONE_ID, "v=spf1 -all");
+    txt_record["type"] = json!("TXT");
+    setup
+        .mock(records_mock(
+            ZONE_ID,
+            json!([record(ZONE_ID, OLD_IP), txt_record]),
+        ))
+        .await;
+
+    let output = setup.run(&["cloud", "list", "example.com"]);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        setup.cloudflare_requests().await,
+        vec![
+            zones_request(),
+            format!("GET zones/{}/dns_records?page=1&per_page=100", ZONE_ID),
+        ]
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("TXT") && stdout.contains("v=spf1 -all"),
+        "{}",
+        stdout
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn never_creates_record_when_lookup_fails() {
+    let setup = TestSetup::new(DOMAIN).await;
+    setup.mock(zones_mock()).await;
+    setup
+        .mock(
+            Mock::given(method("GET"))
+                .and(path(format!("/client/v4/zones/{}/dns_records", ZONE_ID)))
+                .respond_with(ResponseTemplate::new(500)),
+        )
+        .await;
+
+    let output = setup.update();
+
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!setup
+        .cloudflare_requests()
+        .await
+        .iter()
+        .any(|request| request.starts_with("POST")));
+    assert_eq!(read_domains(&setup)[0]["last_v4"], Value::Null);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn creates_cname_record_of_registered_target() {
+    let setup = TestSetup::new(DOMAIN).await;
+    fs::write(setup.domains_path(), "[]").unwrap();
+    setup.mock(zones_mock()).await;
+    setup.mock(records_mock(ZONE_ID, json!([]))).await;
+    let mut cname_record = record(ZONE_ID, "origin.example.net");
+    cname_record["type"] = json!("CNAME");
+    setup
+        .mock(
+            Mock::given(method("POST"))
+                .and(path(format!("/client/v4/zones/{}/dns_records", ZONE_ID)))
+                .and(body_json(json!({
+                    "type": "CNAME",
+                    "name": DOMAIN,
+                    "content": "origin.example.net",
+                    "ttl": 1,
+                    "proxied": false,
+                    "comment": null
+                })))
+                .respond_with(ResponseTemplate::new(200).set_body_json(page(cname_record))),
+        )
+        .await;
+
+    let register = setup.run(&["register", DOMAIN, "--cname-target", "Origin.example.net."]);
+    let output = setup.update();
+
+    assert!(register.status.success(), "{:?}", register);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        setup.cloudflare_requests().await,
+        vec![
+            zones_request(),
+            records_request(ZONE_ID),
+            format!("POST zones/{}/dns_records", ZONE_ID),
+        ]
+    );
+    let domains = read_domains(&setup);
+    assert_eq!(domains[0]["record_types"], json!(["CNAME"]));
+    assert_eq!(domains[0]["last_v4"], Value::Null);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cloud_remove_picks_duplicate_record_by_content() {
+    let setup = TestSetup::new(DOMAIN).await;
+    setup.mock(zones_mock()).await;
+    let txt_record = |id: &str, content: &str| {
+        let mut txt_record = record(ZONE_ID, content);
+        txt_record["id"] = json!(id);
+        txt_record["type"] = json!("TXT");
+        txt_record
+    };
+    setup
+        .mock(records_mock(
+            ZONE_ID,
+            json!([txt_record("first", "one"), txt_record("second", "two")]),
+        ))
+        .await;
+    setup
+        .mock(
+            Mock::given(method("DELETE"))
+                .and(path(format!(
+                    "/client/v4/zones/{}/dns_records/second",
+                    ZONE_ID
+                )))
+                .respond_with(ResponseTemplate::new(200).set_body_json(page(json!({})))),
+        )
+        .await;
+
+    let ambiguous = setup.run(&["cloud", "remove", "example.com", "home", "txt"]);
+    let output = setup.run(&[
+        "cloud",
+        "remove",
+        "example.com",
+        "home",
+        "txt",
+        "--content",
+        "two",
+    ]);
+
+    assert!(!ambiguous.status.success(), "{:?}", ambiguous);
+    assert!(output.status.success(), "{:?}", output);
+    let deletes: Vec<String> = setup
+        .cloudflare_requests()
+        .await
+        .into_iter()
+        .filter(|request| request.starts_with("DELETE"))
+        .collect();
+    assert_eq!(
+        deletes,
+        vec![format!("DELETE zones/{}/dns_records/second", ZONE_ID)]
+    );
+}